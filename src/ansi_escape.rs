@@ -16,9 +16,6 @@ pub(crate) const RESET: &str = "\x1b[m";
 /// White background: invert foreground and background color
 pub(crate) const WBG: &str = "\x1b[7m";
 
-/// Move the cursor to 1:1
-pub(crate) const MOVE_CURSOR_TO_START: &str = "\x1b[H";
-
 /// DECTCTEM: Make the cursor invisible
 pub(crate) const HIDE_CURSOR: &str = "\x1b[?25l";
 /// DECTCTEM: Make the cursor visible
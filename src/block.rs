@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Block selection
+//!
+//! A block selection is the rectangle of rendered columns between the anchor
+//! and the cursor. In each of its rows, the characters shown in these columns
+//! can be copied, cut, deleted or typed over, and pasted blocks are inserted at
+//! the same rendered column in the following rows.
+
+use std::ops::{Range, RangeInclusive};
+
+use crate::row::Row;
+
+/// Return the rows and the rendered columns of the block between the anchor
+/// and the cursor, both given as `(rx, y)`.
+pub fn area(
+    (arx, ay): (usize, usize), (crx, cy): (usize, usize),
+) -> (RangeInclusive<usize>, Range<usize>) {
+    (ay.min(cy)..=ay.max(cy), arx.min(crx)..arx.max(crx))
+}
+
+/// Return the range of indices in `row.chars` of the characters shown in the
+/// range of rendered columns `cols`, using `row.rx2cx`. Characters partly in
+/// the range, such as tabs and wide characters, are included. If `cols` is
+/// empty, an empty range at the column is returned.
+pub fn cx_range(row: &Row, cols: &Range<usize>) -> Range<usize> {
+    let cx = |rx: usize| row.rx2cx.get(rx).copied().unwrap_or(row.chars.len());
+    let (start, mut end) = (cx(cols.start), cx(cols.end));
+    if cols.is_empty() {
+        return start..start;
+    }
+    if end < row.chars.len() && row.cx2rx[end] < cols.end {
+        end += row.get_char_size(row.cx2rx[end]);
+    }
+    start..end
+}
+
+/// Return where a line of a pasted block is inserted in `row` (`None` past
+/// the end of the buffer) to start at the rendered column `rx`: the index in
+/// `row.chars`, and the number of spaces needed to pad a short row.
+pub fn insert_position(row: Option<&Row>, rx: usize) -> (usize, usize) {
+    row.map_or((0, rx), |row| {
+        let width = row.cx2rx[row.chars.len()];
+        (row.rx2cx.get(rx).copied().unwrap_or(row.chars.len()), rx.saturating_sub(width))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    fn row(chars: &str) -> Row {
+        let mut row = Row::new(chars.as_bytes().to_vec());
+        row.update_render(NonZeroUsize::new(4).unwrap());
+        row
+    }
+
+    #[rstest]
+    #[case("abc", 1..2, 1..2)]
+    #[case("a\tb", 0..2, 0..2)]
+    #[case("a\tb", 2..3, 1..2)]
+    #[case("日本c", 1..3, 0..6)]
+    #[case("日本c", 2..2, 3..3)]
+    #[case("ab", 3..5, 2..2)]
+    fn cx_range_of_columns(
+        #[case] chars: &str, #[case] cols: Range<usize>, #[case] expected: Range<usize>,
+    ) {
+        assert_eq!(cx_range(&row(chars), &cols), expected);
+    }
+
+    #[rstest]
+    #[case(Some("abcd"), 2, (2, 0))]
+    #[case(Some("a\tb"), 4, (2, 0))]
+    #[case(Some("ab"), 4, (2, 2))]
+    #[case(None, 3, (0, 3))]
+    fn insert_position_of_line(
+        #[case] chars: Option<&str>, #[case] rx: usize, #[case] expected: (usize, usize),
+    ) {
+        assert_eq!(insert_position(chars.map(row).as_ref(), rx), expected);
+    }
+}
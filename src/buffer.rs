@@ -0,0 +1,307 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Buffer
+//!
+//! A buffer holds the state of a document open in the editor: its text, file
+//! name, syntax configuration and history.
+//!
+//! The text is stored in a rope. Rows are only rendered and highlighted when
+//! they need to be shown, and the rendered rows are kept until they are
+//! modified, or until a modification changes their highlighting.
+
+use std::{borrow::Cow, collections::BTreeMap, num::NonZeroUsize, ops::Range};
+
+use crate::encoding::Encoding;
+use crate::history::{Edit, History, shift_pos};
+use crate::indent::Indent;
+use crate::line_ending::LineEnding;
+use crate::rope::Rope;
+use crate::row::{HlState, Row};
+use crate::syntax::{Conf as SyntaxConf, HlType};
+
+/// The maximum number of rendered rows kept in a buffer. When it is exceeded,
+/// the rendered rows are discarded before rendering new ones.
+const MAX_RENDERED_ROWS: usize = 1024;
+
+/// The maximum number of positions kept in the jump list.
+const MAX_JUMPS: usize = 100;
+
+/// The state of a document open in the editor.
+#[derive(Default)]
+pub struct Buffer {
+    /// The text of the document.
+    pub text: Rope,
+    /// The rows that have been rendered and highlighted, by index.
+    pub rendered_rows: BTreeMap<usize, Row>,
+    /// The highlight state at the end of each of the first rows. The states of
+    /// the following rows are computed when these rows are rendered.
+    pub hl_states: Vec<HlState>,
+    /// Whether the document has been modified since it was last saved.
+    pub dirty: bool,
+    /// The undo / redo history of the document.
+    pub history: History,
+    /// The file name. If None, the user will be prompted for a file name the
+    /// first time they try to save.
+    // TODO: It may be better to store a PathBuf instead
+    pub file_name: Option<String>,
+    /// The syntax configuration corresponding to the current file's extension.
+    pub syntax: SyntaxConf,
+    /// If not `None`, the row and the range of rendered characters that are
+    /// currently matched during a FIND operation.
+    pub match_segment: Option<(usize, Range<usize>)>,
+    /// The marks set in the document, from `a` to `z`, with their positions
+    /// `(x, y)`.
+    pub marks: BTreeMap<u8, (usize, usize)>,
+    /// The jump list: the positions `(x, y)` the cursor jumped from, oldest
+    /// first.
+    pub jumps: Vec<(usize, usize)>,
+    /// The index in `jumps` of the position reached by jumping back, or the
+    /// length of `jumps` if the cursor has not jumped back.
+    pub jump_idx: usize,
+    /// The line endings used in the file. The text itself always uses `\n`.
+    pub line_ending: LineEnding,
+    /// The line endings of the file when it was last loaded or saved.
+    pub saved_line_ending: LineEnding,
+    /// The encoding of the file. The text itself is edited as UTF-8.
+    pub encoding: Encoding,
+    /// The encoding of the file when it was last loaded or saved.
+    pub saved_encoding: Encoding,
+    /// The indentation guessed from the rows of the file, or chosen when
+    /// converting them. If `None`, the configured indentation is used.
+    pub indent: Option<Indent>,
+    /// The size of a tab set for the file by an `.editorconfig` file. If
+    /// `None`, the configured size is used.
+    pub tab_stop: Option<NonZeroUsize>,
+    /// Whether to trim the trailing whitespace of the rows when saving, as set
+    /// for the file by an `.editorconfig` file. If `None`, the configured
+    /// setting is used.
+    pub trim_trailing_whitespace: Option<bool>,
+    /// Whether to end the file with a new line when saving, as set for the
+    /// file by an `.editorconfig` file. If `None`, the configured setting is
+    /// used.
+    pub insert_final_newline: Option<bool>,
+}
+
+impl Buffer {
+    /// Return the name of the buffer, as shown in the status bar.
+    pub fn name(&self) -> &str { self.file_name.as_deref().unwrap_or("[No Name]") }
+
+    /// Return whether the buffer is empty or not. If there is more than one
+    /// row, even if all the rows are empty, `is_empty` returns `false`, since
+    /// the text contains new lines.
+    pub const fn is_empty(&self) -> bool { self.text.is_empty() }
+
+    /// Return the number of rows.
+    pub const fn n_rows(&self) -> usize { self.text.n_lines() }
+
+    /// Return the number of bytes of the text, excluding new lines.
+    pub const fn n_bytes(&self) -> u64 {
+        (self.text.len() - self.n_rows().saturating_sub(1)) as u64
+    }
+
+    /// Return the size of the file, with its line endings.
+    pub const fn file_size(&self) -> u64 {
+        self.n_bytes()
+            + (self.n_rows().saturating_sub(1) * self.line_ending.as_bytes().len()) as u64
+    }
+
+    /// Return the characters of row `y`, borrowed from the text unless the
+    /// row spans several chunks of the rope.
+    pub fn line(&self, y: usize) -> Cow<'_, [u8]> { self.text.line(y) }
+
+    /// Return row `y` with its rendered string and its mappings, or `None` if
+    /// there is no such row. The row is only highlighted if it has been
+    /// rendered with `render_rows`.
+    pub fn row(&self, y: usize, tab: NonZeroUsize) -> Option<Cow<'_, Row>> {
+        if let Some(row) = self.rendered_rows.get(&y) {
+            return Some(Cow::Borrowed(row));
+        }
+        (y < self.n_rows()).then(|| {
+            let mut row = Row::new(self.line(y).into_owned());
+            row.update_render(tab);
+            Cow::Owned(row)
+        })
+    }
+
+    /// Return row `y` if it has been rendered.
+    pub fn rendered_row(&self, y: usize) -> Option<&Row> { self.rendered_rows.get(&y) }
+
+    /// Render and highlight the rows in `range` that have not been rendered
+    /// yet, computing the highlight states of the previous rows if needed.
+    pub fn render_rows(&mut self, range: Range<usize>, tab: NonZeroUsize) {
+        if self.rendered_rows.len() > MAX_RENDERED_ROWS {
+            self.rendered_rows.clear();
+        }
+        let (start, end) = (self.hl_states.len().min(range.start), range.end.min(self.n_rows()));
+        for (y, line) in self.text.lines(start).enumerate().take(end.saturating_sub(start)) {
+            let y = start + y;
+            if y >= self.hl_states.len()
+                || (y >= range.start && !self.rendered_rows.contains_key(&y))
+            {
+                let hl_state = if y > 0 { self.hl_states[y - 1] } else { HlState::Normal };
+                let mut row = Row::new(line);
+                let hl_state = row.update(&self.syntax, hl_state, tab);
+                if y == self.hl_states.len() {
+                    self.hl_states.push(hl_state);
+                }
+                if y >= range.start {
+                    self.rendered_rows.insert(y, row);
+                }
+            }
+        }
+    }
+
+    /// Discard the rendered rows and the highlight states from row `y`, after
+    /// the text has been modified from this row.
+    pub fn invalidate_rows(&mut self, y: usize) {
+        self.hl_states.truncate(y);
+        self.rendered_rows.split_off(&y);
+    }
+
+    /// Update the rendered rows and the highlight states after rows `y` to
+    /// `y + removed` have been replaced with rows `y` to `y + added`. The
+    /// highlight states of the new rows are computed again, and the following
+    /// rows are kept, shifted, unless the highlight state at the end of the new
+    /// rows is different.
+    pub fn update_rows(&mut self, y: usize, removed: usize, added: usize, tab: NonZeroUsize) {
+        let following = self.rendered_rows.split_off(&y).split_off(&(y + removed + 1));
+        let Some(&old_state) = self.hl_states.get(y + removed) else {
+            return self.hl_states.truncate(y);
+        };
+        let mut hl_state = if y > 0 { self.hl_states[y - 1] } else { HlState::Normal };
+        let states = self.text.lines(y).take(added + 1).map(|line| {
+            hl_state = Row::new(line).update(&self.syntax, hl_state, tab);
+            hl_state
+        });
+        let states: Vec<_> = states.collect();
+        if states.last() == Some(&old_state) {
+            self.hl_states.splice(y..=y + removed, states);
+            let shifted = following.into_iter().map(|(k, row)| (k - removed + added, row));
+            self.rendered_rows.extend(shifted);
+        } else {
+            self.hl_states.truncate(y);
+            self.hl_states.extend(states);
+        }
+    }
+
+    /// If there is a bracket at position `(x, y)`, find the matching bracket
+    /// in the rows in `rows`, rendering them if needed. Return the positions
+    /// and the lengths of both brackets. Brackets in strings and comments are
+    /// ignored.
+    pub fn matching_bracket(
+        &mut self, (x, y): (usize, usize), rows: Range<usize>, tab: NonZeroUsize,
+    ) -> Option<[((usize, usize), usize); 2]> {
+        let pairs = self.syntax.bracket_pairs();
+        self.render_rows(y..y + 1, tab);
+        let (pair, opening, len) = bracket_at(self.rendered_row(y)?, x, &pairs)?;
+        // Scan forward from an opening bracket, backward from a closing bracket
+        let ys: Box<dyn Iterator<Item=usize>> = if opening {
+            Box::new(y..rows.end.min(self.n_rows()))
+        } else {
+            Box::new((rows.start..=y).rev())
+        };
+        let mut depth = 0usize;
+        for cy in ys {
+            self.render_rows(cy..cy + 1, tab);
+            let row = self.rendered_row(cy)?;
+            let xs: Box<dyn Iterator<Item=usize>> = match (opening, cy == y) {
+                (true, true) => Box::new(x..row.chars.len()),
+                (true, false) => Box::new(0..row.chars.len()),
+                (false, true) => Box::new((0..=x).rev()),
+                (false, false) => Box::new((0..row.chars.len()).rev()),
+            };
+            for cx in xs {
+                match bracket_at(row, cx, &pairs) {
+                    Some((p, o, _)) if p == pair && o == opening => depth += 1,
+                    Some((p, _, n)) if p == pair => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some([((x, y), len), ((cx, cy), n)]);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        None
+    }
+
+    /// Move the marks and the positions of the jump list after the text has
+    /// been edited, so that they stay on the same rows.
+    pub fn shift_positions(&mut self, edit: &Edit) {
+        let positions = self.marks.values_mut().chain(&mut self.jumps);
+        positions.for_each(|pos| *pos = shift_pos(*pos, edit));
+    }
+
+    /// Record the position `pos` the cursor is about to jump from in the jump
+    /// list. The positions reached by jumping back are forgotten.
+    pub fn push_jump(&mut self, pos: (usize, usize)) {
+        self.jumps.truncate(self.jump_idx);
+        if self.jumps.last() != Some(&pos) {
+            self.jumps.push(pos);
+        }
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.jump_idx = self.jumps.len();
+    }
+
+    /// Return the previous position of the jump list, if any. `current` is the
+    /// position of the cursor, which is recorded to jump forward to it again.
+    pub fn jump_back(&mut self, current: (usize, usize)) -> Option<(usize, usize)> {
+        self.jump_idx = self.jump_idx.checked_sub(1)?;
+        if self.jump_idx + 1 == self.jumps.len() {
+            self.jumps.push(current);
+        }
+        self.jumps.get(self.jump_idx).copied()
+    }
+
+    /// Return the next position of the jump list, after jumping back.
+    pub fn jump_forward(&mut self) -> Option<(usize, usize)> {
+        let pos = *self.jumps.get(self.jump_idx + 1)?;
+        self.jump_idx += 1;
+        Some(pos)
+    }
+
+    /// Return the text between positions `(x0, y0)` (included) and `(x1, y1)`
+    /// (excluded).
+    pub fn text_between(&self, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> Vec<u8> {
+        let mut text = Vec::new();
+        for (y, chars) in self.text.lines(y0).take((y1 + 1).saturating_sub(y0)).enumerate() {
+            let y = y0 + y;
+            let (start, end) =
+                (if y == y0 { x0 } else { 0 }, if y == y1 { x1 } else { chars.len() });
+            text.extend(&chars[start..end]);
+            if y != y1 {
+                text.push(b'\n');
+            }
+        }
+        text
+    }
+}
+
+/// If one of the brackets of `pairs` starts at index `x` of `row`, outside of
+/// a string or a comment, return the index of its pair, whether it is an
+/// opening bracket and its length. Brackets made of words, such as `begin`,
+/// must not be part of a longer word.
+fn bracket_at(row: &Row, x: usize, pairs: &[(String, String)]) -> Option<(usize, bool, usize)> {
+    let hl_type = row.hl.get(*row.cx2rx.get(x)?)?;
+    if matches!(hl_type, HlType::String | HlType::MlString | HlType::Comment | HlType::MlComment) {
+        return None;
+    }
+    let is_word = |b: Option<&u8>| b.is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_');
+    let (before, after) = row.chars.split_at(x);
+    let is_bracket = |token: &[u8]| {
+        after.starts_with(token)
+            && !(is_word(token.first()) && is_word(before.last()))
+            && !(is_word(token.last()) && is_word(after.get(token.len())))
+    };
+    pairs.iter().enumerate().find_map(|(i, (open, close))| {
+        [(open, true), (close, false)]
+            .into_iter()
+            .find(|(token, _)| is_bracket(token.as_bytes()))
+            .map(|(token, opening)| (i, opening, token.len()))
+    })
+}
@@ -0,0 +1,89 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Clipboard
+//!
+//! Access to the system clipboard. Copied text is written with the OSC 52
+//! escape sequence, which is supported by many terminals and also works over
+//! SSH, or with an external command such as `wl-copy` or `xclip`. Pasted text
+//! can only be read with an external command, such as `wl-paste` or `xclip -o`.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::{Config, transform::base64_encode};
+
+/// Copy `text` to the system clipboard. If a copy command is configured, the
+/// text is written to its standard input; if there is no such command or if it
+/// fails, the OSC 52 escape sequence is used when it is enabled.
+pub fn copy(config: &Config, text: &[u8]) -> Result<(), io::Error> {
+    match (&config.clipboard_copy_cmd, config.osc52_clipboard) {
+        (Some(cmd), osc52) => match run(cmd, Some(text)) {
+            Err(_) if osc52 => osc52_copy(text),
+            r => r.map(drop),
+        },
+        (None, true) => osc52_copy(text),
+        (None, false) => Ok(()),
+    }
+}
+
+/// Read the content of the system clipboard with the configured paste command.
+/// Return `None` if no paste command is configured.
+pub fn paste(config: &Config) -> Option<Result<Vec<u8>, io::Error>> {
+    config.clipboard_paste_cmd.as_deref().map(|cmd| run(cmd, None))
+}
+
+/// Write `text` to the clipboard with the OSC 52 escape sequence.
+fn osc52_copy(text: &[u8]) -> Result<(), io::Error> {
+    print!("\x1b]52;c;{}\x07", base64_encode(text));
+    io::stdout().flush()
+}
+
+/// Run a command, writing `input` to its standard input if it is given, and
+/// return its standard output. Return an error if the command is unsuccessful.
+fn run(cmd: &str, input: Option<&[u8]>) -> Result<Vec<u8>, io::Error> {
+    let mut args = cmd.split_whitespace();
+    let mut command = Command::new(args.next().unwrap_or_default());
+    command.args(args).stderr(Stdio::null());
+    let output = if let Some(input) = input {
+        // Some copy commands, such as `xclip`, fork a background process that
+        // serves the clipboard and keeps the output open: only the command itself
+        // is waited for, to know whether it succeeded, and its output is not read.
+        // Commands that serve the clipboard in the foreground block until then.
+        let mut child = command.stdin(Stdio::piped()).stdout(Stdio::null()).spawn()?;
+        child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(input))?;
+        (child.wait()?, Vec::new())
+    } else {
+        let output = command.stdin(Stdio::null()).output()?;
+        (output.status, output.stdout)
+    };
+    match output {
+        (status, stdout) if status.success() => Ok(stdout),
+        (status, _) => Err(io::Error::other(format!("{cmd}: {status}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_and_paste_with_commands() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("clipboard");
+        let config = Config {
+            osc52_clipboard: false,
+            clipboard_copy_cmd: Some(format!("tee {}", path.display())),
+            clipboard_paste_cmd: Some(format!("cat {}", path.display())),
+            ..Config::default()
+        };
+        copy(&config, b"Hello\nWorld").expect("Could not copy");
+        assert_eq!(paste(&config).map(Result::ok), Some(Some(b"Hello\nWorld".to_vec())));
+
+        let config = Config { clipboard_paste_cmd: Some(String::from("false")), ..config };
+        assert!(paste(&config).is_some_and(|r| r.is_err()));
+        assert!(paste(&Config::default()).is_none());
+    }
+}
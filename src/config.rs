@@ -13,16 +13,72 @@ use crate::sys::conf_dirs as cdirs;
 
 /// The global Kibi configuration.
 #[derive(Debug, PartialEq, Eq)]
+#[expect(clippy::struct_excessive_bools, reason = "The options are independent")]
 pub struct Config {
     /// The size of a tab. Must be > 0.
     pub tab_stop: NonZeroUsize,
-    /// The number of confirmations needed before quitting, when changes have
-    /// been made since the file was last changed.
+    /// Whether to indent with spaces instead of tabs, unless the indentation
+    /// of the file can be guessed.
+    pub expand_tab: bool,
+    /// The number of spaces per indentation level, when indenting with spaces.
+    pub indent_width: NonZeroUsize,
+    /// The number of confirmations needed before quitting or closing a buffer,
+    /// when changes have been made since the file was last changed.
     pub quit_times: usize,
     /// The duration for which messages are shown in the status bar.
     pub message_dur: Duration,
     /// Whether to display line numbers.
     pub show_line_num: bool,
+    /// Whether to write copied text to the system clipboard with the OSC 52
+    /// escape sequence, when no copy command is configured or when it fails.
+    pub osc52_clipboard: bool,
+    /// The command used to write copied text to the system clipboard. The text
+    /// is written to its standard input.
+    pub clipboard_copy_cmd: Option<String>,
+    /// The command used to read the system clipboard when pasting. The text is
+    /// read from its standard output.
+    pub clipboard_paste_cmd: Option<String>,
+    /// The number of cut or copied pieces of text kept in the kill ring.
+    pub kill_ring_size: usize,
+    /// Whether to insert the closing bracket or quote when an opening one is
+    /// typed.
+    pub auto_close: bool,
+    /// Whether to wrap long rows on several lines of the screen instead of
+    /// scrolling horizontally.
+    pub soft_wrap: bool,
+    /// The number of columns that rows fit in when reflowing a paragraph.
+    pub text_width: usize,
+    /// Whether to read the tab size, the indentation and the language of a
+    /// file from its Vim or Emacs modelines.
+    pub modelines: bool,
+    /// Whether to remove the whitespace at the end of the rows when saving.
+    pub trim_trailing_whitespace: bool,
+    /// Whether to end the file with a new line when saving.
+    pub insert_final_newline: bool,
+    /// The key bindings used to edit text.
+    pub keymap: Keymap,
+}
+
+/// The key bindings used to edit text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Keymap {
+    /// Key bindings with Ctrl and Alt, e.g. Ctrl+S to save
+    #[default]
+    Default,
+    /// Modal editing as in Vi, with normal, insert and visual modes
+    Vi,
+}
+
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "vi" => Ok(Self::Vi),
+            _ => Err(format!("Unknown keymap: {s}")),
+        }
+    }
 }
 
 impl Default for Config {
@@ -31,9 +87,23 @@ impl Default for Config {
         Self {
             #[expect(clippy::unwrap_used)]
             tab_stop: NonZeroUsize::new(4).unwrap(),
+            expand_tab: false,
+            #[expect(clippy::unwrap_used)]
+            indent_width: NonZeroUsize::new(4).unwrap(),
             quit_times: 2,
             message_dur: Duration::new(3, 0),
             show_line_num: true,
+            osc52_clipboard: false,
+            clipboard_copy_cmd: None,
+            clipboard_paste_cmd: None,
+            kill_ring_size: 16,
+            auto_close: true,
+            soft_wrap: false,
+            text_width: 80,
+            modelines: false,
+            trim_trailing_whitespace: false,
+            insert_final_newline: false,
+            keymap: Keymap::Default,
         }
     }
 }
@@ -60,11 +130,25 @@ impl Config {
             process_ini_file(path, &mut |key, value| {
                 match key {
                     "tab_stop" => conf.tab_stop = parse_value(value)?,
+                    "expand_tab" => conf.expand_tab = parse_value(value)?,
+                    "indent_width" => conf.indent_width = parse_value(value)?,
                     "quit_times" => conf.quit_times = parse_value(value)?,
                     "message_duration" =>
                         conf.message_dur = Duration::try_from_secs_f32(parse_value(value)?)
                             .map_err(|x| x.to_string())?,
                     "show_line_numbers" => conf.show_line_num = parse_value(value)?,
+                    "osc52_clipboard" => conf.osc52_clipboard = parse_value(value)?,
+                    "clipboard_copy_command" => conf.clipboard_copy_cmd = parse_command(value),
+                    "clipboard_paste_command" => conf.clipboard_paste_cmd = parse_command(value),
+                    "kill_ring_size" => conf.kill_ring_size = parse_value(value)?,
+                    "auto_close" => conf.auto_close = parse_value(value)?,
+                    "soft_wrap" => conf.soft_wrap = parse_value(value)?,
+                    "text_width" => conf.text_width = parse_value(value)?,
+                    "modelines" => conf.modelines = parse_value(value)?,
+                    "trim_trailing_whitespace" =>
+                        conf.trim_trailing_whitespace = parse_value(value)?,
+                    "insert_final_newline" => conf.insert_final_newline = parse_value(value)?,
+                    "keymap" => conf.keymap = parse_value(value)?,
                     _ => return Err(format!("Invalid key: {key}")),
                 }
                 Ok(())
@@ -83,14 +167,33 @@ impl Config {
 /// Will print warnings to stderr for invalid lines
 pub fn process_ini_file<F>(path: &Path, kv_fn: &mut F)
 where F: FnMut(&str, &str) -> Result<(), String> {
+    process_ini_sections(path, &mut |section, key, value| {
+        section.map_or_else(|| kv_fn(key, value), |s| Err(format!("unexpected section [{s}]")))
+    });
+}
+
+/// Process an INI file that may contain sections, i.e. lines such as
+/// `[section]`.
+///
+/// The `kv_fn` function will be called for each key-value pair in the file,
+/// with the name of the section the pair belongs to, or `None` for the pairs
+/// that come before the first section.
+///
+/// Will print warnings to stderr for invalid lines
+pub fn process_ini_sections<F>(path: &Path, kv_fn: &mut F)
+where F: FnMut(Option<&str>, &str, &str) -> Result<(), String> {
     read_to_string(path).map_or_else(
         |e| eprintln!("Could not read {}: {}", path.to_string_lossy(), e),
         |config| {
-            for (i, line) in config.lines().enumerate().map(|(i, line)| (i, line.trim_start())) {
+            let mut section = None;
+            for (i, line) in config.lines().enumerate().map(|(i, line)| (i, line.trim())) {
                 let warn = |msg: &str| eprintln!("{}:{}: {}", path.to_string_lossy(), i + 1, msg);
                 match (line.chars().next(), line.split_once('=')) {
                     (Some('#' | ';') | None, _) => (), // Comment or empty line
-                    (_, Some((k, v))) => kv_fn(k.trim_end(), v.trim()).unwrap_or_else(|r| warn(&format!("{k}: {r}"))),
+                    (Some('['), _) if line.ends_with(']') =>
+                        section = Some(line[1..line.len() - 1].trim()),
+                    (_, Some((k, v))) => kv_fn(section, k.trim_end(), v.trim())
+                        .unwrap_or_else(|r| warn(&format!("{k}: {r}"))),
                     (_, None) => warn("missing '='"),
                 }
             }
@@ -103,6 +206,12 @@ pub fn parse_value<T: FromStr<Err=E>, E: Display>(value: &str) -> Result<T, Stri
     value.parse().map_err(|e: E| e.to_string())
 }
 
+/// Parse a command (right-hand side of a key=command INI line). An empty value
+/// means that no command is used.
+fn parse_command(value: &str) -> Option<String> {
+    Some(String::from(value)).filter(|cmd| !cmd.is_empty())
+}
+
 /// Split a comma-separated list of values (right-hand side of a
 /// key=value1,value2,... INI line) and parse it as a Vec.
 pub fn parse_values<T: FromStr<Err=E>, E: Display>(values: &str) -> Result<Vec<T>, String> {
@@ -179,6 +288,34 @@ mod tests {
             (String::from("u"), String::from("v = w"))
         ]);
     }
+    #[test]
+    fn ini_processing_with_sections() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let file_path = tmp_dir.path().join("test_config.ini");
+        fs::write(&file_path, "a = b\n[*.rs]\nc = d\n  [ x ]\ne = f\n")
+            .expect("Could not write INI file");
+        let mut parsed = Vec::new();
+        process_ini_sections(&file_path, &mut |section: Option<&str>, key: &str, value: &str| {
+            parsed.push((section.map(String::from), String::from(key), String::from(value)));
+            Ok(())
+        });
+        let kv = |section: Option<&str>, key: &str, value: &str| {
+            (section.map(String::from), String::from(key), String::from(value))
+        };
+        assert_eq!(parsed, vec![
+            kv(None, "a", "b"),
+            kv(Some("*.rs"), "c", "d"),
+            kv(Some("x"), "e", "f")
+        ]);
+        // The pairs in sections are rejected when sections are not expected
+        let mut kvs = Vec::new();
+        process_ini_file(&file_path, &mut |key: &str, value: &str| {
+            kvs.push((String::from(key), String::from(value)));
+            Ok(())
+        });
+        assert_eq!(kvs, vec![(String::from("a"), String::from("b"))]);
+    }
+
     #[test]
     fn ini_processing_invalid_path() {
         let kv_fn = &mut |_: &str, _: &str| panic!("Should not be called");
@@ -2,13 +2,36 @@
 //
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use std::fmt::{Display, Write as _};
-use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, Write};
-use std::iter::{self, repeat, successors as scsr};
-use std::{fs::File, path::Path, process::Command, time::Instant};
-
-use crate::row::{HlState, Row};
-use crate::{Config, Error, ansi_escape::*, syntax::Conf as SyntaxConf, sys, terminal};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Write as _};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::iter::{once, successors as scsr};
+use std::time::Instant;
+use std::{
+    fs,
+    fs::File,
+    mem,
+    num::NonZeroUsize,
+    ops::{Range, RangeInclusive},
+    path::Path,
+    process::Command,
+};
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::encoding::Encoding;
+use crate::history::{Edit, end_pos, shift_pos};
+use crate::indent::Indent;
+use crate::kill_ring::{Clip, KillRing};
+use crate::line_ending::LineEnding;
+use crate::modeline::{self, Modeline};
+use crate::pane::{Layout, Pane};
+use crate::syntax::{Conf as SyntaxConf, HlType};
+use crate::vi::{self, Insertion, Motion, Operator, Vi};
+use crate::{Config, Error, ansi_escape::*, config::Keymap, sys, terminal};
+use crate::{block, buffer::Buffer, clipboard, editorconfig, macros};
+use crate::{reflow, rope::Rope, row::Row, transform::TRANSFORMS};
 
 const fn ctrl_key(key: u8) -> u8 { key & 0x1f }
 const EXIT: u8 = ctrl_key(b'Q');
@@ -23,7 +46,38 @@ const PASTE: u8 = ctrl_key(b'V');
 const DUPLICATE: u8 = ctrl_key(b'D');
 const EXECUTE: u8 = ctrl_key(b'E');
 const REMOVE_LINE: u8 = ctrl_key(b'R');
+const UNDO: u8 = ctrl_key(b'Z');
+const REDO: u8 = ctrl_key(b'Y');
+const OPEN: u8 = ctrl_key(b'O');
+const NEXT_BUFFER: u8 = ctrl_key(b'N');
+const PREVIOUS_BUFFER: u8 = ctrl_key(b'P');
+const SWITCH_BUFFER: u8 = ctrl_key(b'B');
+const CLOSE_BUFFER: u8 = ctrl_key(b'W');
 const TOGGLE_COMMENT: u8 = 31;
+const MATCHING_BRACKET: u8 = ctrl_key(b']');
+const TRANSPOSE_CHARS: u8 = ctrl_key(b'T');
+// Keys pressed with Alt
+const SPLIT_HORIZONTALLY: u8 = b's';
+const SPLIT_VERTICALLY: u8 = b'v';
+const NEXT_PANE: u8 = b'w';
+const CLOSE_PANE: u8 = b'q';
+const ADD_CURSOR_AT_NEXT_OCCURRENCE: u8 = b'd';
+const ADD_CURSORS_AT_MATCHES: u8 = b'a';
+const RECORD_MACRO: u8 = b'r';
+const PLAY_MACRO: u8 = b'p';
+const SAVE_MACRO: u8 = b'm';
+const PASTE_PREVIOUS: u8 = b'y';
+const KILL_RING: u8 = b'h';
+const SET_MARK: u8 = b'b';
+const JUMP_TO_MARK: u8 = b'j';
+const TRANSPOSE_LINES: u8 = b't';
+const JOIN_LINES: u8 = b'^';
+const TRANSFORM: u8 = b'x';
+const LINE_ENDING: u8 = b'l';
+const ENCODING: u8 = b'e';
+const CONVERT_INDENT: u8 = b'i';
+const REFLOW: u8 = b'g';
+const BLOCK_SELECTION: u8 = b'c';
 const BACKSPACE: u8 = 127;
 
 const WELCOME_MESSAGE: &str = concat!("Kibi ", env!("CARGO_PKG_VERSION"));
@@ -35,20 +89,143 @@ const HELP_MESSAGE: &str = "^S save | ^Q quit | ^F find | ^G go to | ^D duplicat
 macro_rules! set_status { ($editor:expr, $($arg:expr),*) => ($editor.status_msg = Some(StatusMessage::new(format!($($arg),*)))) }
 
 /// Enum of input keys
+#[derive(Clone)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum Key {
     Arrow(AKey),
     CtrlArrow(AKey),
+    ShiftArrow(AKey),
+    AltArrow(AKey),
+    CtrlShiftArrow(AKey),
     PageUp,
     PageDown,
+    ShiftPageUp,
+    ShiftPageDown,
     Home,
     End,
+    ShiftHome,
+    ShiftEnd,
     Delete,
     Escape,
     Char(u8),
+    Alt(u8),
+}
+
+impl Key {
+    /// If the key is a movement key combined with Shift, return the same key
+    /// without Shift.
+    fn unshifted(&self) -> Option<Self> {
+        #[expect(clippy::wildcard_enum_match_arm)]
+        match self {
+            Self::ShiftArrow(arrow) => Some(Self::Arrow(arrow.clone())),
+            Self::ShiftPageUp => Some(Self::PageUp),
+            Self::ShiftPageDown => Some(Self::PageDown),
+            Self::ShiftHome => Some(Self::Home),
+            Self::ShiftEnd => Some(Self::End),
+            _ => None,
+        }
+    }
+
+    /// Parse a sequence of keys written with the notation used by the `Display`
+    /// implementation, such as `<C-s>`. Other characters stand for themselves.
+    fn parse_keys(s: &str) -> Result<Vec<Self>, String> {
+        let mut keys = Vec::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            if c != '<' {
+                keys.extend(rest.bytes().take(c.len_utf8()).map(Self::Char));
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let (name, tail) = rest[1..].split_once('>').ok_or("Missing '>'")?;
+            keys.push(Self::from_name(name).ok_or_else(|| format!("Invalid key: <{name}>"))?);
+            rest = tail;
+        }
+        Ok(keys)
+    }
+
+    /// Return the key written as `<name>`, if any.
+    fn from_name(name: &str) -> Option<Self> {
+        let byte = |s: &str| match s.as_bytes() {
+            [b] => Some(*b),
+            _ => u8::from_str_radix(s.strip_prefix("0x")?, 16).ok(),
+        };
+        let key = match name {
+            "lt" => Self::Char(b'<'),
+            "Space" => Self::Char(b' '),
+            "Tab" => Self::Char(b'\t'),
+            "CR" => Self::Char(b'\r'),
+            "BS" => Self::Char(BACKSPACE),
+            "Esc" => Self::Escape,
+            "Del" => Self::Delete,
+            "PageUp" => Self::PageUp,
+            "PageDown" => Self::PageDown,
+            "S-PageUp" => Self::ShiftPageUp,
+            "S-PageDown" => Self::ShiftPageDown,
+            "Home" => Self::Home,
+            "End" => Self::End,
+            "S-Home" => Self::ShiftHome,
+            "S-End" => Self::ShiftEnd,
+            _ => {
+                return match name.split_once('-') {
+                    None => AKey::from_name(name)
+                        .map(Self::Arrow)
+                        .or_else(|| byte(name).map(Self::Char)),
+                    Some(("C", k)) => AKey::from_name(k)
+                        .map(Self::CtrlArrow)
+                        .or_else(|| {
+                            k.strip_prefix("S-").and_then(AKey::from_name).map(Self::CtrlShiftArrow)
+                        })
+                        .or_else(|| {
+                            byte(k).filter(u8::is_ascii_graphic).map(|b| Self::Char(ctrl_key(b)))
+                        }),
+                    Some(("S", k)) => AKey::from_name(k).map(Self::ShiftArrow),
+                    Some(("M", k)) =>
+                        AKey::from_name(k).map(Self::AltArrow).or_else(|| byte(k).map(Self::Alt)),
+                    Some(_) => None,
+                };
+            }
+        };
+        Some(key)
+    }
+}
+
+impl Display for Key {
+    /// Write the key as it is written in saved macros. Printable characters are
+    /// written as is, other keys are written between angle brackets.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arrow(arrow) => write!(f, "<{arrow}>"),
+            Self::CtrlArrow(arrow) => write!(f, "<C-{arrow}>"),
+            Self::ShiftArrow(arrow) => write!(f, "<S-{arrow}>"),
+            Self::AltArrow(arrow) => write!(f, "<M-{arrow}>"),
+            Self::CtrlShiftArrow(arrow) => write!(f, "<C-S-{arrow}>"),
+            Self::PageUp => f.write_str("<PageUp>"),
+            Self::PageDown => f.write_str("<PageDown>"),
+            Self::ShiftPageUp => f.write_str("<S-PageUp>"),
+            Self::ShiftPageDown => f.write_str("<S-PageDown>"),
+            Self::Home => f.write_str("<Home>"),
+            Self::End => f.write_str("<End>"),
+            Self::ShiftHome => f.write_str("<S-Home>"),
+            Self::ShiftEnd => f.write_str("<S-End>"),
+            Self::Delete => f.write_str("<Del>"),
+            Self::Escape => f.write_str("<Esc>"),
+            Self::Char(b'<') => f.write_str("<lt>"),
+            Self::Char(b' ') => f.write_str("<Space>"),
+            Self::Char(b'\t') => f.write_str("<Tab>"),
+            Self::Char(b'\r') => f.write_str("<CR>"),
+            Self::Char(BACKSPACE) => f.write_str("<BS>"),
+            Self::Char(c @ 0..32) => write!(f, "<C-{}>", ((c | 0x40).to_ascii_lowercase()) as char),
+            Self::Char(c) if c.is_ascii_graphic() => write!(f, "{}", *c as char),
+            Self::Char(c) => write!(f, "<0x{c:02x}>"),
+            Self::Alt(c) if c.is_ascii_graphic() && *c != b'>' => write!(f, "<M-{}>", *c as char),
+            Self::Alt(c) => write!(f, "<M-0x{c:02x}>"),
+        }
+    }
 }
 
 /// Enum of arrow keys
+#[derive(Clone)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 enum AKey {
     Left,
@@ -57,9 +234,33 @@ enum AKey {
     Down,
 }
 
+impl AKey {
+    /// Return the arrow key with the given name, as written by `Display`.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Left" => Some(Self::Left),
+            "Right" => Some(Self::Right),
+            "Up" => Some(Self::Up),
+            "Down" => Some(Self::Down),
+            _ => None,
+        }
+    }
+}
+
+impl Display for AKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Left => "Left",
+            Self::Right => "Right",
+            Self::Up => "Up",
+            Self::Down => "Down",
+        })
+    }
+}
+
 /// Describes the cursor position and the screen offset
-#[derive(Debug, Default, Clone, PartialEq)]
-struct CursorState {
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CursorState {
     /// x position (indexing the characters, not the columns)
     x: usize,
     /// y position (row number, 0-indexed)
@@ -86,46 +287,85 @@ impl CursorState {
 /// editor.
 #[derive(Default)]
 pub struct Editor {
-    /// If not `None`, the current prompt mode (`Save`, `Find`, `GoTo`, or
-    /// `Execute`). If `None`, we are in regular edition mode.
+    /// If not `None`, the current prompt mode (`Save`, `Find`, `GoTo`, `Open`,
+    /// etc.). If `None`, we are in regular edition mode.
     prompt_mode: Option<PromptMode>,
     /// The current state of the cursor.
     cursor: CursorState,
-    /// The padding size used on the left for line numbering.
+    /// The padding size used on the left of the current pane for line
+    /// numbering.
     ln_pad: usize,
-    /// The width of the current window. Will be updated when the window is
-    /// resized.
+    /// The size of the terminal `(rows, columns)`. Will be updated when the
+    /// window is resized.
+    term_size: (usize, usize),
+    /// The width of the current pane. Will be updated when the window is
+    /// resized or split.
     window_width: usize,
-    /// The number of rows that can be used for the editor, excluding the status
-    /// bar and the message bar
+    /// The number of rows that can be used for the current pane, excluding its
+    /// status bar
     screen_rows: usize,
-    /// The number of columns that can be used for the editor, excluding the
-    /// part used for line numbers
+    /// The number of columns that can be used for the current pane, excluding
+    /// the part used for line numbers
     screen_cols: usize,
-    /// The collection of rows, including the content and the syntax
-    /// highlighting information.
-    rows: Vec<Row>,
-    /// Whether the document has been modified since it was open.
-    dirty: bool,
+    /// The layout of the panes. The cursor state and buffer of the current
+    /// pane are only saved in the layout when switching to another pane.
+    layout: Layout,
+    /// The index of the current pane in the layout.
+    pane_idx: usize,
+    /// The current buffer.
+    buf: Buffer,
+    /// The other open buffers, with the cursor state to restore when switching
+    /// back to them.
+    inactive: Vec<(Buffer, CursorState)>,
+    /// The index of the current buffer among all open buffers. The buffers
+    /// before it are the first `buf_idx` elements of `inactive`.
+    buf_idx: usize,
     /// The configuration for the editor.
     config: Config,
     /// The number of consecutive times the user has tried to quit without
     /// saving. After `config.quit_times`, the program will exit.
     quit_times: usize,
-    /// The file name. If None, the user will be prompted for a file name the
-    /// first time they try to save.
-    // TODO: It may be better to store a PathBuf instead
-    file_name: Option<String>,
+    /// The number of consecutive times the user has tried to close a buffer
+    /// without saving. After `config.quit_times`, the buffer will be closed.
+    close_times: usize,
     /// The current status message being shown.
     status_msg: Option<StatusMessage>,
-    /// The syntax configuration corresponding to the current file's extension.
-    syntax: SyntaxConf,
-    /// The number of bytes contained in `rows`. This excludes new lines.
-    n_bytes: u64,
-    /// The copied buffer of a row
-    copied_row: Vec<u8>,
+    /// The position `(x, y)` where the selection starts, if any. The selection
+    /// spans from this anchor to the cursor.
+    anchor: Option<(usize, usize)>,
+    /// Whether the selection is a block: the rectangle of rendered columns
+    /// between the anchor and the cursor, on the rows between them.
+    block: bool,
+    /// The positions `(x, y)` of the extra cursors, sorted. Text typed or
+    /// deleted is typed or deleted at each of them as well as at the cursor.
+    cursors: Vec<(usize, usize)>,
+    /// The rows and the ranges of rendered characters of the bracket under the
+    /// cursor and of the matching bracket, if they are shown.
+    matched_brackets: Vec<(usize, Range<usize>)>,
+    /// The keys recorded so far, if a macro is being recorded.
+    recording: Option<Vec<Key>>,
+    /// The keys of the last recorded macro.
+    last_macro: Vec<Key>,
+    /// The keys of the macros being played that have not been processed yet.
+    /// They are processed before reading new keys from the input.
+    macro_keys: VecDeque<Key>,
+    /// The last cut or copied pieces of text.
+    kill_ring: KillRing,
+    /// If the last key pasted text, the index of the pasted clip in the kill
+    /// ring and the range of positions of the inserted text.
+    last_paste: Option<(usize, Range<(usize, usize)>)>,
+    /// Whether the last key was a cut. Consecutive cuts are appended to the
+    /// same clip of the kill ring.
+    cutting: bool,
     /// Whether to use ANSI color escape codes for rendering
     use_color: bool,
+    /// The mode and the pending command, with the `vi` keymap.
+    vi: Vi,
+    /// The keys typed since the start of the current command, with the `vi`
+    /// keymap, to be repeated with `.` if it changes the text.
+    vi_keys: Vec<Key>,
+    /// The keys of the last change made with the `vi` keymap.
+    last_change: Vec<Key>,
 }
 
 /// Describes a status message, shown at the bottom at the screen.
@@ -141,6 +381,53 @@ impl StatusMessage {
     fn new(msg: String) -> Self { Self { msg, time: Instant::now() } }
 }
 
+/// Return the index of the line that contains column `rx` in a wrapped row,
+/// given the starts of its lines returned by `Row::wrap`.
+fn visual_line(starts: &[(usize, usize)], rx: usize) -> usize {
+    starts.iter().rposition(|(_, start)| *start <= rx).unwrap_or(0)
+}
+
+/// Return whether `b` is a space or a tab.
+const fn is_blank(b: u8) -> bool { matches!(b, b' ' | b'\t') }
+
+/// Return whether `c` is a control character bound to a command, rather than
+/// a character that is inserted as text (e.g. Tab).
+const fn is_command(c: u8) -> bool {
+    matches!(
+        c,
+        EXIT | REFRESH_SCREEN
+            | SAVE
+            | FIND
+            | GOTO
+            | CUT
+            | COPY
+            | PASTE
+            | DUPLICATE
+            | EXECUTE
+            | REMOVE_LINE
+            | UNDO
+            | REDO
+            | OPEN
+            | NEXT_BUFFER
+            | PREVIOUS_BUFFER
+            | SWITCH_BUFFER
+            | CLOSE_BUFFER
+            | TOGGLE_COMMENT
+            | MATCHING_BRACKET
+            | TRANSPOSE_CHARS
+    )
+}
+
+/// Return the longest prefix of `s` that fits in `width` columns.
+fn truncate_to_width(s: &str, width: usize) -> &str {
+    let mut w = 0;
+    let end = s.char_indices().find(|(_, c)| {
+        w += c.width().unwrap_or(0);
+        w > width
+    });
+    end.map_or(s, |(i, _)| &s[..i])
+}
+
 /// Pretty-format a size in bytes.
 fn format_size(n: u64) -> String {
     if n < 1024 {
@@ -155,6 +442,23 @@ fn format_size(n: u64) -> String {
     format!("{}.{:02}{}B", q / 100, q % 100, b" kMGTPEZ"[i as usize] as char)
 }
 
+/// Move the cursor to the start of a line of a pane, given its 0-indexed
+/// position `(y, x)` on the screen, and clear `width` characters. If the pane
+/// is not on the left of the screen, draw a vertical separator before it.
+fn start_pane_line(buffer: &mut String, (y, x): (usize, usize), width: usize) -> Result<(), Error> {
+    if x > 0 {
+        // \u{2502}: pipe "│"
+        write!(buffer, "\x1b[{};{x}H\u{2502}", y + 1)?;
+    } else {
+        write!(buffer, "\x1b[{};1H", y + 1)?;
+    }
+    if width > 0 {
+        // Erase `width` characters, without moving the cursor
+        write!(buffer, "\x1b[{width}X")?;
+    }
+    Ok(())
+}
+
 /// Return an Arrow Key given an ANSI code.
 ///
 /// The argument must be a valide arrow key ANSI code (`a`, `b`, `c` or `d`),
@@ -172,7 +476,9 @@ fn get_akey(c: u8) -> AKey {
 impl Editor {
     /// Return the current row if the cursor points to an existing row, `None`
     /// otherwise.
-    fn current_row(&self) -> Option<&Row> { self.rows.get(self.cursor.y) }
+    fn current_row(&self) -> Option<Cow<'_, Row>> {
+        self.buf.row(self.cursor.y, self.tab_stop_of(&self.buf))
+    }
 
     /// Return the position of the cursor, in terms of rendered characters (as
     /// opposed to `self.cursor.x`, which is the position of the cursor in
@@ -181,7 +487,8 @@ impl Editor {
 
     /// Move the cursor following an arrow key (← → ↑ ↓).
     fn move_cursor(&mut self, key: &AKey, ctrl: bool) {
-        match (key, self.current_row()) {
+        self.buf.history.break_merge();
+        match (key, self.current_row().as_deref()) {
             (AKey::Left, Some(row)) if self.cursor.x > 0 => {
                 let mut cursor_x = self.cursor.x - row.get_char_size(row.cx2rx[self.cursor.x] - 1);
                 // ← moving to previous word
@@ -204,6 +511,9 @@ impl Editor {
                 self.cursor.x = cursor_x;
             }
             (AKey::Right, Some(_)) => self.cursor.move_to_next_line(),
+            (AKey::Up | AKey::Down, Some(row)) if self.config.soft_wrap =>
+                (self.cursor.x, self.cursor.y) =
+                    self.wrapped_vertical_move(row, matches!(key, AKey::Up)),
             // TODO: For Up and Down, move self.cursor.x to be consistent with tabs and UTF-8
             //  characters, i.e. according to rx
             (AKey::Up, _) if self.cursor.y > 0 => self.cursor.y -= 1,
@@ -213,6 +523,29 @@ impl Editor {
         self.update_cursor_x_position();
     }
 
+    /// Return the position `(x, y)` of the cursor after moving it up or down by
+    /// one line on the screen when long rows are wrapped, keeping its column.
+    /// `row` is the current row.
+    fn wrapped_vertical_move(&self, row: &Row, up: bool) -> (usize, usize) {
+        let (tab, width, y) = (self.tab_stop_of(&self.buf), self.screen_cols, self.cursor.y);
+        let (starts, rx) = (row.wrap(width), row.cx2rx[self.cursor.x]);
+        let k = visual_line(&starts, rx);
+        let col = rx - starts[k].1;
+        let (y, k) = match (up, k) {
+            (true, 0) if y == 0 => return (self.cursor.x, y),
+            (true, 0) => (y - 1, usize::MAX),
+            (true, _) => (y, k - 1),
+            (false, _) if k + 1 < starts.len() => (y, k + 1),
+            (false, _) => (y + 1, 0),
+        };
+        let Some(row) = self.buf.row(y, tab) else { return (0, y) };
+        let starts = row.wrap(width);
+        let k = k.min(starts.len() - 1);
+        // The last column of the line, or the end of the row on its last line
+        let end = starts.get(k + 1).map_or(row.cx2rx[row.chars.len()], |(_, next)| next - 1);
+        (row.rx2cx[(starts[k].1 + col).min(end)], y)
+    }
+
     /// Update the cursor x position. If the cursor y position has changed, the
     /// current position might be illegal (x is further right than the last
     /// character of the row). If that is the case, clamp `self.cursor.x`.
@@ -245,26 +578,40 @@ impl Editor {
                         (b'[' | b'O', Some(b'H')) => Key::Home,
                         (b'[' | b'O', Some(b'F')) => Key::End,
                         (b'[', mut c @ Some(b'0'..=b'8')) => {
-                            let mut d = bytes.next().transpose()?;
+                            let (mut d, mut shift) = (bytes.next().transpose()?, false);
                             if (c, d) == (Some(b'1'), Some(b';')) {
                                 // 1 is the default modifier value. Therefore, <ESC>[1;5C is
                                 // equivalent to <ESC>[5C, etc.
                                 c = bytes.next().transpose()?;
                                 d = bytes.next().transpose()?;
+                            } else if d == Some(b';') {
+                                // Modifier after the key code, e.g. <ESC>[5;2~ for Shift+PageUp
+                                shift = bytes.next().transpose()? == Some(b'2');
+                                d = bytes.next().transpose()?;
                             }
                             match (c, d) {
                                 (Some(c), Some(b'~')) if c == b'1' || c == b'7' => Key::Home,
                                 (Some(c), Some(b'~')) if c == b'4' || c == b'8' => Key::End,
                                 (Some(b'3'), Some(b'~')) => Key::Delete,
+                                (Some(b'5'), Some(b'~')) if shift => Key::ShiftPageUp,
+                                (Some(b'6'), Some(b'~')) if shift => Key::ShiftPageDown,
                                 (Some(b'5'), Some(b'~')) => Key::PageUp,
                                 (Some(b'6'), Some(b'~')) => Key::PageDown,
                                 (Some(b'5'), Some(d @ b'A'..=b'D')) => Key::CtrlArrow(get_akey(d)),
+                                (Some(b'2'), Some(d @ b'A'..=b'D')) => Key::ShiftArrow(get_akey(d)),
+                                (Some(b'3'), Some(d @ b'A'..=b'D')) => Key::AltArrow(get_akey(d)),
+                                (Some(b'6'), Some(d @ b'A'..=b'D')) =>
+                                    Key::CtrlShiftArrow(get_akey(d)),
+                                (Some(b'2'), Some(b'H')) => Key::ShiftHome,
+                                (Some(b'2'), Some(b'F')) => Key::ShiftEnd,
                                 _ => Key::Escape,
                             }
                         }
                         (b'O', Some(c @ b'a'..=b'd')) => Key::CtrlArrow(get_akey(c)),
+                        (b'[', Some(c @ b'a'..=b'd')) => Key::ShiftArrow(get_akey(c)),
                         _ => Key::Escape,
                     },
+                    Some(c @ b' '..=b'~') => Key::Alt(c),
                     _ => Key::Escape,
                 });
             }
@@ -275,459 +622,2337 @@ impl Editor {
     /// attributes.
     fn update_window_size(&mut self) -> Result<(), Error> {
         let wsize = sys::get_window_size().or_else(|_| terminal::get_window_size_using_cursor())?;
-        // Make room for the status bar and status message
-        (self.screen_rows, self.window_width) = (wsize.0.saturating_sub(2), wsize.1);
-        self.update_screen_cols();
+        self.term_size = wsize;
+        self.update_layout();
         Ok(())
     }
 
+    /// Update the part of the screen used by each pane, keeping the last row
+    /// for the message bar. Then update the `screen_rows`, `window_width`,
+    /// `screen_cols` and `ln_padding` attributes for the current pane.
+    fn update_layout(&mut self) {
+        self.layout.resize((0, 0, self.term_size.0.saturating_sub(1), self.term_size.1));
+        let (.., height, width) = self.layout.pane_mut(self.pane_idx).rect;
+        // Make room for the status bar of the pane
+        (self.screen_rows, self.window_width) = (height.saturating_sub(1), width);
+        self.update_screen_cols();
+    }
+
     /// Update the `screen_cols` and `ln_padding` attributes based on the
     /// maximum number of digits for line numbers (since the left padding
     /// depends on this number of digits).
     fn update_screen_cols(&mut self) {
+        self.ln_pad = self.line_number_padding(self.buf.n_rows(), self.window_width);
+        self.screen_cols = self.window_width.saturating_sub(self.ln_pad);
+    }
+
+    /// Return the padding size used for line numbers in a pane of width
+    /// `width` showing `n_rows` rows.
+    fn line_number_padding(&self, n_rows: usize, width: usize) -> usize {
         // The maximum number of digits to use for the line number is the number of
         // digits of the last line number. This is equal to the number of times
         // we can divide this number by ten, computed below using `successors`.
-        let n_digits = scsr(Some(self.rows.len()), |u| Some(u / 10).filter(|u| *u > 0)).count();
-        let show_line_num = self.config.show_line_num && n_digits + 2 < self.window_width / 4;
-        self.ln_pad = if show_line_num { n_digits + 2 } else { 0 };
-        self.screen_cols = self.window_width.saturating_sub(self.ln_pad);
+        let n_digits = scsr(Some(n_rows), |u| Some(u / 10).filter(|u| *u > 0)).count();
+        let show_line_num = self.config.show_line_num && n_digits + 2 < width / 4;
+        if show_line_num { n_digits + 2 } else { 0 }
+    }
+
+    /// Insert `text` at position `(x, y)` without recording the edit, and
+    /// return the position right after the inserted text. `text` may contain
+    /// new lines.
+    fn raw_insert(&mut self, (x, y): (usize, usize), text: &[u8]) -> (usize, usize) {
+        self.buf.text.insert((x, y), text);
+        let (end, tab) = (end_pos((x, y), text), self.tab_stop_of(&self.buf));
+        self.buf.update_rows(y, 0, end.1 - y, tab);
+        // The number of rows may have changed. The left padding may need to be updated.
+        self.update_screen_cols();
+        end
+    }
+
+    /// Delete the text between positions `(x0, y0)` (included) and `(x1, y1)`
+    /// (excluded) without recording the edit, and return the deleted text.
+    fn raw_delete(&mut self, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> Vec<u8> {
+        let text = self.buf.text.delete((x0, y0), (x1, y1));
+        let tab = self.tab_stop_of(&self.buf);
+        self.buf.update_rows(y0, y1 - y0, 0, tab);
+        // The number of rows may have changed. The left padding may need to be updated.
+        self.update_screen_cols();
+        text
     }
 
-    /// Update a row, given its index. If `ignore_following_rows` is `false` and
-    /// the highlight state has changed during the update (for instance, it
-    /// is now in "multi-line comment" state, keep updating the next rows
-    fn update_row(&mut self, y: usize, ignore_following_rows: bool) {
-        let mut hl_state = if y > 0 { self.rows[y - 1].hl_state } else { HlState::Normal };
-        for row in self.rows.iter_mut().skip(y) {
-            let previous_hl_state = row.hl_state;
-            hl_state = row.update(&self.syntax, hl_state, self.config.tab_stop);
-            if ignore_following_rows || hl_state == previous_hl_state {
-                return;
+    /// Apply an edit without recording it. Return the position of the end of
+    /// the inserted text, or of the start of the deleted text.
+    fn apply(&mut self, edit: &Edit) -> (usize, usize) {
+        self.buf.shift_positions(edit);
+        match edit {
+            Edit::Insert(pos, text) => self.raw_insert(*pos, text),
+            Edit::Delete(pos, text) => {
+                self.raw_delete(*pos, end_pos(*pos, text));
+                *pos
             }
-            // If the state has changed (for instance, a multi-line comment
-            // started in this row), continue updating the following
-            // rows
         }
     }
 
-    /// Update all the rows.
-    fn update_all_rows(&mut self) {
-        let mut hl_state = HlState::Normal;
-        for row in &mut self.rows {
-            hl_state = row.update(&self.syntax, hl_state, self.config.tab_stop);
+    /// Insert `text` at position `(x, y)`, record the edit in the history and
+    /// return the position right after the inserted text. If `y` is the index
+    /// after the last row, the text is inserted on a new row. Consecutive typed
+    /// characters (`typing` is true) are grouped into a single change. The
+    /// extra cursors are moved accordingly.
+    fn insert_text(&mut self, (x, y): (usize, usize), text: &[u8], typing: bool) -> (usize, usize) {
+        let n_rows = self.buf.n_rows();
+        let edit = if y == n_rows && n_rows > 0 {
+            Edit::Insert((self.buf.line(y - 1).len(), y - 1), [b"\n", text].concat())
+        } else {
+            Edit::Insert((x, y), text.to_vec())
+        };
+        let end = self.apply(&edit);
+        self.cursors.iter_mut().for_each(|pos| *pos = shift_pos(*pos, &edit));
+        self.buf.history.push(edit, &self.cursor, typing);
+        self.update_dirty();
+        end
+    }
+
+    /// Delete the text between two positions and record the edit in the
+    /// history. The extra cursors are moved accordingly.
+    fn delete_text(&mut self, from: (usize, usize), to: (usize, usize)) {
+        let edit = Edit::Delete(from, self.raw_delete(from, to));
+        self.buf.shift_positions(&edit);
+        self.cursors.iter_mut().for_each(|pos| *pos = shift_pos(*pos, &edit));
+        self.buf.history.push(edit, &self.cursor, false);
+        self.update_dirty();
+    }
+
+    /// Update the `dirty` attribute: the document is dirty if it differs from
+    /// its saved state, unless it is a new empty document.
+    fn update_dirty(&mut self) {
+        let modified = !self.buf.history.is_saved()
+            || self.buf.line_ending != self.buf.saved_line_ending
+            || self.buf.encoding != self.buf.saved_encoding;
+        self.buf.dirty = modified && (self.buf.file_name.is_some() || !self.buf.is_empty());
+    }
+
+    /// Undo the last change and restore the cursor to its previous state.
+    fn undo(&mut self) {
+        let Some((edits, cursor)) = self.buf.history.undo() else {
+            return set_status!(self, "Nothing to undo");
+        };
+        for edit in &edits {
+            self.apply(edit);
+        }
+        self.cursor = cursor;
+        self.update_dirty();
+    }
+
+    /// Redo the last undone change and move the cursor to the end of the
+    /// change.
+    fn redo(&mut self) {
+        let Some(edits) = self.buf.history.redo() else {
+            return set_status!(self, "Nothing to redo");
+        };
+        for edit in &edits {
+            (self.cursor.x, self.cursor.y) = self.apply(edit);
         }
+        self.update_dirty();
+    }
+
+    /// Insert `text` at the current cursor position and move the cursor to the
+    /// end of the inserted text.
+    fn insert_at_cursor(&mut self, text: &[u8], typing: bool) {
+        (self.cursor.x, self.cursor.y) =
+            self.insert_text((self.cursor.x, self.cursor.y), text, typing);
     }
 
     /// Insert a byte at the current cursor position. If there is no row at the
-    /// current cursor position, add a new row and insert the byte.
+    /// current cursor position, add a new row and insert the byte. If the byte
+    /// closes an indented block (e.g. `}`) and is typed after the indentation
+    /// of the row, one indentation level is removed.
+    ///
+    /// If `auto_close` is enabled, typing an opening bracket or quote also
+    /// inserts the closing one, and typing a closing bracket or quote right
+    /// before the same character steps over it. Quotes are not closed next to
+    /// a word, e.g. in "don't".
     fn insert_byte(&mut self, c: u8) {
-        if let Some(row) = self.rows.get_mut(self.cursor.y) {
-            row.chars.insert(self.cursor.x, c);
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        let line = self.buf.line(y);
+        let pairs = if self.config.auto_close { self.auto_closed_pairs() } else { Vec::new() };
+        let is_word = |b: Option<&u8>| b.is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_');
+        let (indent, next) = (&line[..x], line.get(x));
+        if pairs.iter().any(|(_, close)| *close == c) && next == Some(&c) {
+            self.cursor.x += 1;
+        } else if let Some(&(open, close)) = pairs.iter().find(|(open, _)| *open == c)
+            && (open != close || !(is_word(indent.last()) || is_word(next)))
+        {
+            self.insert_at_cursor(&[open, close], true);
+            self.cursor.x -= 1;
+        } else if c == b'\t'
+            && let Indent::Spaces(width) = self.indent_of(&self.buf)
+        {
+            // Insert spaces up to the next multiple of the indentation width
+            let rx = self.current_row().map_or(0, |row| row.cx2rx[x]);
+            self.insert_at_cursor(&vec![b' '; width.get() - rx % width.get()], true);
+        } else if x > 0
+            && indent.iter().all(|b| is_blank(*b))
+            && self.buf.syntax.dedent_on.contains(&char::from(c))
+        {
+            let n_spaces = indent.iter().rev().take_while(|b| **b == b' ').count();
+            let width = self.indent_of(&self.buf).width(self.tab_stop_of(&self.buf)).get();
+            let n = if indent[x - 1] == b'\t' { 1 } else { n_spaces.min(width) };
+            let checkpoint = self.buf.history.checkpoint();
+            self.delete_text((x - n, y), (x, y));
+            self.cursor.x -= n;
+            self.insert_at_cursor(&[c], true);
+            self.buf.history.merge_since(checkpoint);
         } else {
-            self.rows.push(Row::new(vec![c]));
-            // The number of rows has changed. The left padding may need to be updated.
-            self.update_screen_cols();
+            self.insert_at_cursor(&[c], true);
+        }
+    }
+
+    /// Return the pairs of opening and closing characters that are closed
+    /// automatically: the brackets made of one character and the quotes of
+    /// single-line strings.
+    fn auto_closed_pairs(&self) -> Vec<(u8, u8)> {
+        let brackets = self.buf.syntax.bracket_pairs().into_iter();
+        let brackets =
+            brackets.filter_map(|(open, close)| match (open.as_bytes(), close.as_bytes()) {
+                (&[open], &[close]) => Some((open, close)),
+                _ => None,
+            });
+        let quotes = self.buf.syntax.sl_string_quotes.iter().filter_map(|q| u8::try_from(*q).ok());
+        brackets.chain(quotes.map(|q| (q, q))).collect()
+    }
+
+    /// Return the text of one indentation level, given the indentation of the
+    /// current row: a tab if it starts with a tab, spaces if it starts with a
+    /// space, and the indentation of the buffer otherwise.
+    fn indent_unit(&self, indent: &[u8]) -> Vec<u8> {
+        let buf_indent = self.indent_of(&self.buf);
+        match (indent.first(), buf_indent) {
+            (Some(b'\t'), _) => vec![b'\t'],
+            (Some(b' '), Indent::Tabs) => vec![b' '; self.config.indent_width.get()],
+            _ => buf_indent.unit(),
+        }
+    }
+
+    /// Return the indentation of a buffer: the one guessed from its file if
+    /// there is one, or else the one configured for its language or globally.
+    fn indent_of(&self, buf: &Buffer) -> Indent {
+        let syntax = &buf.syntax;
+        buf.indent.unwrap_or_else(|| {
+            if syntax.expand_tab.unwrap_or(self.config.expand_tab) {
+                Indent::Spaces(syntax.indent_width.unwrap_or(self.config.indent_width))
+            } else {
+                Indent::Tabs
+            }
+        })
+    }
+
+    /// Return the size of a tab in a buffer: the one set for its file if there
+    /// is one, or else the configured one.
+    fn tab_stop_of(&self, buf: &Buffer) -> NonZeroUsize {
+        buf.tab_stop.unwrap_or(self.config.tab_stop)
+    }
+
+    /// Convert the leading whitespace of every row to the indentation given by
+    /// `args` (`tabs` or `spaces [<width>]`), and use this indentation for the
+    /// buffer. The change is undone at once.
+    fn convert_indent(&mut self, args: &str) {
+        let (from, tab_stop) = (self.indent_of(&self.buf), self.tab_stop_of(&self.buf));
+        let Some(to) = Indent::parse(args, from.width(tab_stop)) else {
+            return set_status!(self, "Expected tabs or spaces [<width>], got {args}");
+        };
+        let checkpoint = self.buf.history.checkpoint();
+        for y in 0..self.buf.n_rows() {
+            let line = self.buf.line(y).into_owned();
+            let old = &line[..line.iter().take_while(|b| is_blank(**b)).count()];
+            let new = to.convert(old, from, tab_stop);
+            if new[..] != *old {
+                self.delete_text((0, y), (old.len(), y));
+                self.insert_text((0, y), &new, false);
+                if y == self.cursor.y {
+                    self.cursor.x = (self.cursor.x + new.len()).saturating_sub(old.len());
+                }
+            }
         }
-        self.update_row(self.cursor.y, false);
-        (self.cursor.x, self.n_bytes, self.dirty) = (self.cursor.x + 1, self.n_bytes + 1, true);
+        self.buf.history.merge_since(checkpoint);
+        self.buf.indent = Some(to);
     }
 
     /// Insert a new line at the current cursor position and move the cursor to
     /// the start of the new line. If the cursor is in the middle of a row,
     /// split off that row.
+    ///
+    /// The new row starts with the indentation of the current row, plus one
+    /// level if the cursor follows a character that opens an indented block
+    /// (e.g. `{`). If the cursor is also followed by a character that closes
+    /// the block, this character is moved to a third row.
     fn insert_new_line(&mut self) {
-        let (position, new_row_chars) = if self.cursor.x == 0 {
-            (self.cursor.y, Vec::new())
-        } else {
-            // self.rows[self.cursor.y] must exist, since cursor.x = 0 for any cursor.y ≥
-            // row.len()
-            let new_chars = self.rows[self.cursor.y].chars.split_off(self.cursor.x);
-            self.update_row(self.cursor.y, false);
-            (self.cursor.y + 1, new_chars)
-        };
-        self.rows.insert(position, Row::new(new_row_chars));
-        self.update_row(position, false);
-        self.update_screen_cols();
-        self.cursor.move_to_next_line();
-        self.dirty = true;
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if y >= self.buf.n_rows() {
+            // After the last row, inserting an empty text adds a new row.
+            self.insert_text((x, y), b"", false);
+            return self.cursor.move_to_next_line();
+        }
+        let line = self.buf.line(y);
+        let (before, after) = line.split_at(x);
+        let indent = &before[..before.iter().take_while(|b| is_blank(**b)).count()];
+        let mut text = [b"\n", indent].concat();
+        let syntax = &self.buf.syntax;
+        let is_in =
+            |chars: &[char], b: Option<&u8>| b.is_some_and(|b| chars.contains(&char::from(*b)));
+        if is_in(&syntax.indent_after, before.iter().rfind(|b| !is_blank(**b))) {
+            text.extend(self.indent_unit(indent));
+            if is_in(&syntax.dedent_on, after.iter().find(|b| !is_blank(**b))) {
+                let cursor_x = text.len() - 1;
+                text.extend([b"\n", indent].concat());
+                self.insert_text((x, y), &text, false);
+                return (self.cursor.x, self.cursor.y) = (cursor_x, y + 1);
+            }
+        }
+        (self.cursor.x, self.cursor.y) = self.insert_text((x, y), &text, false);
     }
 
     /// Delete a character at the current cursor position. If the cursor is
     /// located at the beginning of a row that is not the first or last row,
     /// merge the current row and the previous row. If the cursor is located
     /// after the last row, move up to the last character of the previous row.
-    fn delete_char(&mut self) {
-        if self.cursor.x > 0 {
-            let row = &mut self.rows[self.cursor.y];
+    /// If `auto_close` is enabled and Backspace (`backspace`) is pressed
+    /// between an opening and a closing bracket or quote, delete both. If
+    /// Backspace is pressed in leading spaces, delete back to the previous
+    /// indentation level.
+    fn delete_char(&mut self, backspace: bool) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        let line = self.buf.line(y);
+        if backspace
+            && self.config.auto_close
+            && let (Some(open), Some(close)) =
+                (x.checked_sub(1).and_then(|i| line.get(i)), line.get(x))
+            && self.auto_closed_pairs().contains(&(*open, *close))
+        {
+            self.delete_text((x - 1, y), (x + 1, y));
+            self.cursor.x -= 1;
+        } else if backspace
+            && let Indent::Spaces(width) = self.indent_of(&self.buf)
+            && x > 0
+            && line[..x].iter().all(|b| *b == b' ')
+        {
+            // In leading spaces, delete back to the previous indentation level
+            let n = (x - 1) % width.get() + 1;
+            self.delete_text((x - n, y), (x, y));
+            self.cursor.x -= n;
+        } else if let Some(row) = self.current_row().filter(|_| x > 0) {
             // Obtain the number of bytes to be removed: could be 1-4 (UTF-8 character
             // size).
-            let n_bytes_to_remove = row.get_char_size(row.cx2rx[self.cursor.x] - 1);
-            row.chars.splice(self.cursor.x - n_bytes_to_remove..self.cursor.x, iter::empty());
-            self.update_row(self.cursor.y, false);
+            let n_bytes_to_remove = row.get_char_size(row.cx2rx[x] - 1);
+            self.delete_text((x - n_bytes_to_remove, y), (x, y));
             self.cursor.x -= n_bytes_to_remove;
-            self.dirty = if self.is_empty() { self.file_name.is_some() } else { true };
-            self.n_bytes -= n_bytes_to_remove as u64;
-        } else if self.cursor.y < self.rows.len() && self.cursor.y > 0 {
-            let row = self.rows.remove(self.cursor.y);
-            let previous_row = &mut self.rows[self.cursor.y - 1];
-            self.cursor.x = previous_row.chars.len();
-            previous_row.chars.extend(&row.chars);
-            self.update_row(self.cursor.y - 1, true);
-            self.update_row(self.cursor.y, false);
-            // The number of rows has changed. The left padding may need to be updated.
-            self.update_screen_cols();
-            (self.dirty, self.cursor.y) = (true, self.cursor.y - 1);
-        } else if self.cursor.y == self.rows.len() {
+        } else if y < self.buf.n_rows() && y > 0 {
+            let previous_row_len = self.buf.line(y - 1).len();
+            self.delete_text((previous_row_len, y - 1), (0, y));
+            (self.cursor.x, self.cursor.y) = (previous_row_len, y - 1);
+        } else if y == self.buf.n_rows() {
             // If the cursor is located after the last row, pressing backspace is equivalent
             // to pressing the left arrow key.
             self.move_cursor(&AKey::Left, false);
         }
     }
 
+    /// Delete the character under the cursor, as with the Delete key.
+    fn delete_next_char(&mut self) {
+        self.move_cursor(&AKey::Right, false);
+        self.delete_char(false);
+    }
+
+    /// Delete the current row, including its new line character.
     fn delete_current_row(&mut self) {
-        if self.cursor.y < self.rows.len() {
-            self.rows[self.cursor.y].chars.clear();
-            self.cursor.x = 0;
-            self.cursor.y = std::cmp::min(self.cursor.y + 1, self.rows.len() - 1);
-            self.delete_char();
-            self.cursor.x = 0;
+        let y = self.cursor.y;
+        if y >= self.buf.n_rows() {
+            return;
         }
+        let row_len = self.buf.line(y).len();
+        if y + 1 < self.buf.n_rows() {
+            self.delete_text((0, y), (0, y + 1));
+        } else if y > 0 {
+            // Last row: delete the new line character at the end of the previous row
+            self.delete_text((self.buf.line(y - 1).len(), y - 1), (row_len, y));
+            self.cursor.y -= 1;
+        } else {
+            self.delete_text((0, y), (row_len, y));
+        }
+        self.cursor.x = 0;
     }
 
     fn duplicate_current_row(&mut self) {
-        self.copy_current_row();
-        self.paste_current_row();
+        if self.cursor.y < self.buf.n_rows() {
+            let clip =
+                Clip { text: self.buf.line(self.cursor.y).into_owned(), rows: true, block: false };
+            self.insert_clip(&clip);
+        }
     }
 
-    fn copy_current_row(&mut self) {
-        if let Some(row) = self.current_row() {
-            self.copied_row = row.chars.clone();
-        }
+    /// Copy the selected text, or the current row if nothing is selected, to
+    /// the kill ring and to the system clipboard. If `append` is true, the text
+    /// is appended to the last clip of the kill ring.
+    fn copy(&mut self, selection: Option<((usize, usize), (usize, usize))>, append: bool) {
+        let (text, rows) = match selection {
+            Some((start, end)) => (self.buf.text_between(start, end), false),
+            None if self.cursor.y < self.buf.n_rows() =>
+                (self.buf.line(self.cursor.y).into_owned(), true),
+            None => return,
+        };
+        self.push_clip(Clip { text, rows, block: false }, append);
     }
 
-    fn paste_current_row(&mut self) {
-        if self.copied_row.is_empty() {
-            return;
+    /// Add a clip to the kill ring and copy it to the system clipboard. If
+    /// `append` is true, the clip is appended to the last clip of the kill
+    /// ring.
+    fn push_clip(&mut self, clip: Clip, append: bool) {
+        if append {
+            self.kill_ring.append(clip, self.config.kill_ring_size);
+        } else {
+            self.kill_ring.push(clip, self.config.kill_ring_size);
+        }
+        let text = self.kill_ring.get(0).map(Clip::clipboard_text).unwrap_or_default();
+        if let Err(err) = clipboard::copy(&self.config, &text) {
+            set_status!(self, "Can't copy to the clipboard: {err}");
         }
-        self.n_bytes += self.copied_row.len() as u64;
-        let y = (self.cursor.y + 1).min(self.rows.len());
-        self.rows.insert(y, Row::new(self.copied_row.clone()));
-        self.update_row(y, false);
-        (self.cursor.y, self.dirty) = (y, true);
-        self.update_screen_cols();
     }
 
-    /// Toggle comment on the current line using the appropriate comment symbol
-    /// from the syntax configuration. If the line is already commented,
-    /// uncomment it. If not, add a comment symbol at the beginning.
-    fn toggle_comment(&mut self) {
-        // Get the first single-line comment start symbol from syntax config
-        let Some(sym) = self.syntax.sl_comment_start.first() else { return };
-        let Some(row) = self.rows.get_mut(self.cursor.y) else { return };
-        // Find the first non-whitespace character position
-        let pos = row.chars.iter().position(|&c| !(c as char).is_whitespace()).unwrap_or(0);
-
-        // Check if the line is already commented
-        let n_update = if row.chars.get(pos..pos + sym.len()) == Some(sym.as_bytes()) {
-            let to_remove = sym.len() + usize::from(row.chars.get(pos + sym.len()) == Some(&b' '));
-            // Remove the comment and return the removed size as a negative integer
-            0isize.saturating_sub_unsigned(row.chars.drain(pos..pos + to_remove).len())
-        } else {
-            // Insert comment at the first non-whitespace position
-            row.chars.splice(pos..pos, iter::chain(sym.bytes(), iter::once(b' ')));
-            1isize.saturating_add_unsigned(sym.len())
-        };
-        self.n_bytes = self.n_bytes.saturating_add_signed(n_update as i64);
-        if self.cursor.x >= pos {
-            self.cursor.x = self.cursor.x.saturating_add_signed(n_update);
+    /// Cut the selected text, or the current row if nothing is selected. If
+    /// the last key was also a cut, the text is appended to the same clip.
+    fn cut(&mut self, selection: Option<((usize, usize), (usize, usize))>, append: bool) {
+        self.copy(selection, append);
+        if !self.delete_selection(selection) {
+            self.delete_current_row();
         }
+        self.cutting = true;
+    }
 
-        self.update_row(self.cursor.y, false);
-        // Update cursor position to ensure it's valid after row update
-        self.update_cursor_x_position();
-        self.dirty = true;
+    /// Paste the content of the system clipboard if a paste command is
+    /// configured and the clipboard was changed outside of the editor, or the
+    /// last clip of the kill ring otherwise.
+    fn paste(&mut self) {
+        self.read_clipboard();
+        self.paste_clip(0);
     }
 
-    /// Try to load a file. If found, load the rows and update the render and
-    /// syntax highlighting. If not found, do not return an error.
-    fn load(&mut self, path: &Path) -> Result<(), Error> {
-        let mut file = match File::open(path) {
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                self.rows.push(Row::new(Vec::new()));
-                return Ok(());
-            }
-            r => r,
-        }?;
-        let ft = file.metadata()?.file_type();
-        if !(ft.is_file() || ft.is_symlink()) {
-            return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid input file type").into());
+    /// If a paste command is configured and the system clipboard was changed
+    /// outside of the editor, add its content to the kill ring.
+    fn read_clipboard(&mut self) {
+        let last = self.kill_ring.get(0).map(Clip::clipboard_text);
+        match clipboard::paste(&self.config) {
+            Some(Ok(text)) if Some(&text) != last.as_ref() =>
+                self.kill_ring.push(Clip { text, ..Clip::default() }, self.config.kill_ring_size),
+            Some(Err(err)) => set_status!(self, "Can't paste from the clipboard: {err}"),
+            Some(Ok(_)) | None => (),
         }
-        for line in BufReader::new(&file).split(b'\n') {
-            self.rows.push(Row::new(line?));
-        }
-        // If the file ends with an empty line or is empty, we need to append an empty
-        // row to `self.rows`. Unfortunately, BufReader::split doesn't yield an
-        // empty Vec in this case, so we need to check the last byte directly.
-        file.seek(io::SeekFrom::End(0))?;
-        #[expect(clippy::unbuffered_bytes)]
-        if file.bytes().next().transpose()?.is_none_or(|b| b == b'\n') {
-            self.rows.push(Row::new(Vec::new()));
-        }
-        self.update_all_rows();
-        // The number of rows has changed. The left padding may need to be updated.
-        self.update_screen_cols();
-        self.n_bytes = self.rows.iter().map(|row| row.chars.len() as u64).sum();
-        Ok(())
     }
 
-    /// Save the text to a file, given its name.
-    fn save(&self, file_name: &str) -> Result<usize, io::Error> {
-        let mut file = File::create(file_name)?;
-        let mut written = 0;
-        for (i, row) in self.rows.iter().enumerate() {
-            file.write_all(&row.chars)?;
-            written += row.chars.len();
-            if i != (self.rows.len() - 1) {
-                file.write_all(b"\n")?;
-                written += 1;
-            }
+    /// Insert the `i`-th clip of the kill ring, and remember where it was
+    /// inserted so that it can be replaced with the previous clip.
+    fn paste_clip(&mut self, i: usize) {
+        if let Some(clip) = self.kill_ring.get(i).cloned() {
+            let (start, end) = self.insert_clip(&clip);
+            // A block is not a range of text, it can't be replaced
+            self.last_paste = (!clip.block).then_some((i, start..end));
         }
-        file.sync_all()?;
-        Ok(written)
     }
 
-    /// Save the text to a file and handle all errors. Errors and success
-    /// messages will be printed to the status bar. Return whether the file
-    /// was successfully saved.
-    fn save_and_handle_io_errors(&mut self, file_name: &str) -> bool {
-        let saved = self.save(file_name);
-        // Print error or success message to the status bar
-        match saved.as_ref() {
-            Ok(w) => set_status!(self, "{} written to {}", format_size(*w as u64), file_name),
-            Err(err) => set_status!(self, "Can't save! I/O error: {err}"),
-        }
-        // If save was successful, set dirty to false.
-        self.dirty &= saved.is_err();
-        saved.is_ok()
+    /// Replace the text inserted by the last key, if it pasted text, with the
+    /// previous clip of the kill ring. Both changes are undone at once.
+    fn paste_previous(&mut self, last_paste: Option<&(usize, Range<(usize, usize)>)>) {
+        let Some(&(i, Range { start, end })) = last_paste else {
+            set_status!(self, "Nothing was pasted just before");
+            return;
+        };
+        let checkpoint = self.buf.history.checkpoint();
+        self.delete_text(start, end);
+        (self.cursor.x, self.cursor.y) = start;
+        self.paste_clip((i + 1) % self.kill_ring.len());
+        self.buf.history.merge_since(checkpoint);
     }
 
-    /// Save to a file after obtaining the file path from the prompt. If
-    /// successful, the `file_name` attribute of the editor will be set and
-    /// syntax highlighting will be updated.
-    fn save_as(&mut self, file_name: String) {
-        if self.save_and_handle_io_errors(&file_name) {
-            // If save was successful
-            self.syntax = SyntaxConf::find(&file_name, &sys::data_dirs());
-            self.file_name = Some(file_name);
-            self.update_all_rows();
-        }
+    /// Return the clips of the kill ring as shown in the status bar, numbered
+    /// from 1, each shortened to its first characters.
+    fn kill_ring_list(&self) -> String {
+        let summary = |clip: &Clip| {
+            let text = String::from_utf8_lossy(&clip.text).replace('\n', "↵");
+            let mut chars = text.chars();
+            let summary: String = chars.by_ref().take(16).collect();
+            if chars.next().is_some() { summary + "…" } else { summary }
+        };
+        let clips = self.kill_ring.iter().enumerate();
+        clips.map(|(i, clip)| format!("{}:{}", i + 1, summary(clip))).collect::<Vec<_>>().join(" ")
     }
 
-    /// Draw the left part of the screen: line numbers and vertical bar.
-    fn draw_left_padding<T: Display>(&self, buffer: &mut String, val: T) {
-        if self.ln_pad >= 2 {
-            // \u{2502}: pipe "│"
-            let s = format!("{:>1$} \u{2502}", val, self.ln_pad - 2);
-            // \x1b[38;5;240m: Dark grey color
-            push_colored(buffer, "\x1b[38;5;240m", &s, self.use_color);
+    /// Insert a clip, and return the start and end positions of the inserted
+    /// text. Whole rows are inserted below the current row, other text is
+    /// inserted at the cursor position.
+    fn insert_clip(&mut self, clip: &Clip) -> ((usize, usize), (usize, usize)) {
+        let start = (self.cursor.x, self.cursor.y);
+        if clip.block {
+            self.insert_block(&clip.text);
+            return (start, start);
         }
+        if !clip.rows {
+            self.insert_at_cursor(&clip.text, false);
+            return (start, (self.cursor.x, self.cursor.y));
+        }
+        let (start, text) = match self.buf.n_rows() {
+            0 => ((0, 0), clip.text.clone()),
+            n => {
+                let y = self.cursor.y.min(n - 1);
+                ((self.buf.line(y).len(), y), [b"\n", &clip.text[..]].concat())
+            }
+        };
+        let end = self.insert_text(start, &text, false);
+        self.cursor.y = end.1;
+        self.update_cursor_x_position();
+        (start, end)
     }
 
-    /// Return whether the file being edited is empty or not. If there is more
-    /// than one row, even if all the rows are empty, `is_empty` returns
-    /// `false`, since the text contains new lines.
-    const fn is_empty(&self) -> bool { self.rows.len() <= 1 && self.n_bytes == 0 }
+    /// Return the start and end positions `(x, y)` of the selection, if the
+    /// selection is not empty and is not a block.
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        if self.block {
+            return None;
+        }
+        // Positions after the last row are mapped to the end of the last row
+        let n_rows = self.buf.n_rows();
+        let clamp = |(x, y): (usize, usize)| match n_rows {
+            n if y >= n && n > 0 => (self.buf.line(n - 1).len(), n - 1),
+            _ => (x, y),
+        };
+        let (a, c) = (clamp(self.anchor?), clamp((self.cursor.x, self.cursor.y)));
+        let (start, end) = if (a.1, a.0) <= (c.1, c.0) { (a, c) } else { (c, a) };
+        (start != end).then_some((start, end))
+    }
 
-    /// Draw rows of text and empty rows on the terminal, by adding characters
-    /// to the buffer.
-    fn draw_rows(&self, buffer: &mut String) -> Result<(), Error> {
-        let row_it = self.rows.iter().map(Some).chain(repeat(None)).enumerate();
-        for (i, row) in row_it.skip(self.cursor.roff).take(self.screen_rows) {
-            buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
-            if let Some(row) = row {
-                // Draw a row of text
-                self.draw_left_padding(buffer, i + 1);
-                row.draw(self.cursor.coff, self.screen_cols, buffer, self.use_color);
-            } else {
-                // Draw an empty row
-                self.draw_left_padding(buffer, '~');
-                if self.is_empty() && i == self.screen_rows / 3 {
-                    write!(buffer, "{:^1$.1$}", WELCOME_MESSAGE, self.screen_cols)?;
-                }
-            }
-            buffer.push_str("\r\n");
+    /// Return the ranges of rendered characters of row `y` that are selected
+    /// or under an extra cursor.
+    fn selected_rx_ranges(&self, y: usize) -> Vec<Range<usize>> {
+        let Some(row) = self.buf.row(y, self.tab_stop_of(&self.buf)) else { return Vec::new() };
+        let cursors = self.cursors.iter().filter(|(_, cy)| *cy == y);
+        let mut ranges: Vec<_> =
+            cursors.filter_map(|(x, _)| row.cx2rx.get(*x)).map(|rx| *rx..rx + 1).collect();
+        let selection = match self.vi.mode {
+            vi::Mode::Visual => self.anchor.filter(|_| !self.block).map(|_| self.vi_selection()),
+            vi::Mode::Normal | vi::Mode::Insert => self.selection(),
+        };
+        if let Some(((x0, y0), (x1, y1))) = selection.filter(|(s, e)| (s.1..=e.1).contains(&y)) {
+            let (start, end) =
+                (if y == y0 { x0 } else { 0 }, if y == y1 { x1 } else { row.chars.len() });
+            ranges.push(row.cx2rx[start]..row.cx2rx[end]);
         }
-        Ok(())
+        if let Some((_, cols)) = self.block_area().filter(|(rows, _)| rows.contains(&y)) {
+            let cx_range = block::cx_range(&row, &cols);
+            let (start, end) = (row.cx2rx[cx_range.start], row.cx2rx[cx_range.end]);
+            // An empty block is shown as a column of cursors
+            ranges.push(if cols.is_empty() { start..start + 1 } else { start..end });
+        }
+        ranges
     }
 
-    /// Draw the status bar on the terminal, by adding characters to the buffer.
-    fn draw_status_bar(&self, buffer: &mut String) {
-        // Left part of the status bar
-        let modified = if self.dirty { " (modified)" } else { "" };
-        let mut left =
-            format!("{:.30}{modified}", self.file_name.as_deref().unwrap_or("[No Name]"));
-        left.truncate(self.window_width);
+    /// If the selection is a block, return its rows and its rendered columns,
+    /// which are between the columns of the anchor and of the cursor.
+    fn block_area(&self) -> Option<(RangeInclusive<usize>, Range<usize>)> {
+        let (n_rows, tab) = (self.buf.n_rows(), self.tab_stop_of(&self.buf));
+        let (ax, ay) = self.anchor.filter(|_| self.block && n_rows > 0)?;
+        let rx = |x: usize, y: usize| {
+            let y = y.min(n_rows - 1);
+            (y, self.buf.row(y, tab).map_or(0, |row| row.cx2rx[x.min(row.chars.len())]))
+        };
+        let ((ay, arx), (cy, crx)) = (rx(ax, ay), rx(self.cursor.x, self.cursor.y));
+        Some(block::area((arx, ay), (crx, cy)))
+    }
 
-        // Right part of the status bar
-        let size = format_size(self.n_bytes + self.rows.len().saturating_sub(1) as u64);
-        let right =
-            format!("{} | {size} | {}:{}", self.syntax.name, self.cursor.y + 1, self.rx() + 1);
+    /// Return the rows of the block selection with the range of characters of
+    /// each row that are in the block, found with `block::cx_range`.
+    fn block_ranges(&self) -> Vec<(usize, Range<usize>)> {
+        let tab = self.tab_stop_of(&self.buf);
+        let Some((rows, cols)) = self.block_area() else { return Vec::new() };
+        rows.filter_map(|y| Some((y, block::cx_range(&*self.buf.row(y, tab)?, &cols)))).collect()
+    }
 
-        // Draw
-        let rw = self.window_width.saturating_sub(left.len());
-        push_colored(buffer, WBG, &format!("{left}{right:>rw$.rw$}\r\n"), self.use_color);
+    /// Start a block selection at the cursor, or turn the current selection
+    /// into a block, or a block selection back into a normal selection.
+    fn toggle_block_selection(&mut self, anchor: Option<(usize, usize)>) {
+        self.anchor = anchor.or(Some((self.cursor.x, self.cursor.y)));
+        self.block = !self.block;
     }
 
-    /// Draw the message bar on the terminal, by adding characters to the
-    /// buffer.
-    fn draw_message_bar(&self, buffer: &mut String) {
-        buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
-        let msg_duration = self.config.message_dur;
-        if let Some(sm) = self.status_msg.as_ref().filter(|sm| sm.time.elapsed() < msg_duration) {
-            buffer.push_str(&sm.msg[..sm.msg.len().min(self.window_width)]);
+    /// Delete the characters of a block selection, given by `block_ranges`,
+    /// and move the cursor to the top left corner of the block.
+    fn delete_block(&mut self, ranges: &[(usize, Range<usize>)]) {
+        for (y, range) in ranges.iter().filter(|(_, range)| !range.is_empty()) {
+            self.delete_text((range.start, *y), (range.end, *y));
+        }
+        if let Some((y, range)) = ranges.first() {
+            (self.cursor.x, self.cursor.y) = (range.start, *y);
         }
     }
 
-    /// Refresh the screen: update the offsets, draw the rows, the status bar,
-    /// the message bar, and move the cursor to the correct position.
-    fn refresh_screen(&mut self) -> Result<(), Error> {
-        self.cursor.scroll(self.rx(), self.screen_rows, self.screen_cols);
-        let mut buffer = format!("{HIDE_CURSOR}{MOVE_CURSOR_TO_START}");
-        self.draw_rows(&mut buffer)?;
-        self.draw_status_bar(&mut buffer);
-        self.draw_message_bar(&mut buffer);
-        let (cursor_x, cursor_y) = if self.prompt_mode.is_none() {
-            // If not in prompt mode, position the cursor according to the `cursor`
-            // attributes.
-            (self.rx() - self.cursor.coff + 1 + self.ln_pad, self.cursor.y - self.cursor.roff + 1)
-        } else {
-            // If in prompt mode, position the cursor on the prompt line at the end of the
-            // line.
-            (self.status_msg.as_ref().map_or(0, |sm| sm.msg.len() + 1), self.screen_rows + 2)
-        };
-        // Finally, print `buffer` and move the cursor
-        print!("{buffer}\x1b[{cursor_y};{cursor_x}H{SHOW_CURSOR}");
-        io::stdout().flush().map_err(Error::from)
+    /// Insert the lines of `text` at the rendered column of the cursor, in the
+    /// current row and the following ones. Rows are added at the end of the
+    /// buffer and short rows are padded with spaces if needed. The cursor does
+    /// not move, and the change is undone at once.
+    fn insert_block(&mut self, text: &[u8]) {
+        let (rx, y0, tab) = (self.rx(), self.cursor.y, self.tab_stop_of(&self.buf));
+        let checkpoint = self.buf.history.checkpoint();
+        for (y, line) in (y0..).zip(text.split(|b| *b == b'\n')) {
+            let (x, padding) = block::insert_position(self.buf.row(y, tab).as_deref(), rx);
+            let padding = vec![b' '; padding];
+            self.insert_text((x, y), &[&padding[..], line].concat(), false);
+        }
+        self.buf.history.merge_since(checkpoint);
     }
 
-    /// Process a key that has been pressed, when not in prompt mode. Returns
-    /// whether the program should exit, and optionally the prompt mode to
-    /// switch to.
-    fn process_keypress(&mut self, key: &Key) -> (bool, Option<PromptMode>) {
-        // This won't be mutated, unless key is Key::Character(EXIT)
-        let mut reset_quit_times = true;
-        let mut prompt_mode = None;
-
+    /// If the selection is a block and `key` copies, cuts, pastes, deletes or
+    /// types text, process it for the block and return `true`. Typed text
+    /// replaces the characters of the block in each of its rows, where extra
+    /// cursors are added so that typing can go on in every row.
+    fn process_keypress_in_block(&mut self, key: &Key) -> bool {
+        let ranges = self.block_ranges();
+        if ranges.is_empty() {
+            return false;
+        }
+        let lines = ranges.iter().map(|(y, range)| self.buf.line(*y)[range.clone()].to_vec());
+        let text = lines.collect::<Vec<_>>().join(&b'\n');
+        let (is_empty, y) = (ranges.iter().all(|(_, range)| range.is_empty()), self.cursor.y);
+        let checkpoint = self.buf.history.checkpoint();
+        #[expect(clippy::wildcard_enum_match_arm)]
         match key {
-            Key::Arrow(arrow) => self.move_cursor(arrow, false),
-            Key::CtrlArrow(arrow) => self.move_cursor(arrow, true),
-            Key::PageUp => {
-                self.cursor.y = self.cursor.roff.saturating_sub(self.screen_rows);
-                self.update_cursor_x_position();
+            Key::Char(COPY) => {
+                self.push_clip(Clip { text, rows: false, block: true }, false);
+                return true;
             }
-            Key::PageDown => {
-                self.cursor.y = (self.cursor.roff + 2 * self.screen_rows - 1).min(self.rows.len());
-                self.update_cursor_x_position();
+            Key::Char(CUT) => {
+                self.push_clip(Clip { text, rows: false, block: true }, false);
+                self.delete_block(&ranges);
             }
-            Key::Home => self.cursor.x = 0,
-            Key::End => self.cursor.x = self.current_row().map_or(0, |row| row.chars.len()),
-            Key::Char(b'\r' | b'\n') => self.insert_new_line(), // Enter
-            Key::Char(BACKSPACE | DELETE_BIS) => self.delete_char(), // Backspace or Ctrl + H
-            Key::Char(REMOVE_LINE) => self.delete_current_row(),
-            Key::Delete => {
-                self.move_cursor(&AKey::Right, false);
-                self.delete_char();
+            Key::Char(PASTE) => {
+                self.delete_block(&ranges);
+                self.paste();
             }
-            Key::Escape | Key::Char(REFRESH_SCREEN) => (),
-            Key::Char(EXIT) => {
-                if !self.dirty || self.quit_times + 1 >= self.config.quit_times {
-                    return (true, None);
-                }
-                let r = self.config.quit_times - self.quit_times - 1;
-                set_status!(self, "Press Ctrl+Q {0} more time{1:.2$} to quit.", r, "s", r - 1);
-                reset_quit_times = false;
+            Key::Char(BACKSPACE | DELETE_BIS) | Key::Delete if !is_empty =>
+                self.delete_block(&ranges),
+            Key::Char(BACKSPACE | DELETE_BIS) => {
+                self.add_block_cursors(&ranges, y);
+                self.at_each_cursor(|ed| ed.delete_char(true));
             }
-            Key::Char(SAVE) if let Some(file_name) = self.file_name.take() => {
-                self.save_and_handle_io_errors(&file_name);
-                self.file_name = Some(file_name);
+            Key::Delete => {
+                self.add_block_cursors(&ranges, y);
+                self.at_each_cursor(Self::delete_next_char);
             }
-            Key::Char(SAVE) => prompt_mode = Some(PromptMode::Save(String::new())),
-            Key::Char(FIND) =>
-                prompt_mode = Some(PromptMode::Find(String::new(), self.cursor.clone(), None)),
-            Key::Char(GOTO) => prompt_mode = Some(PromptMode::GoTo(String::new())),
-            Key::Char(DUPLICATE) => self.duplicate_current_row(),
-            Key::Char(CUT) => {
-                self.copy_current_row();
-                self.delete_current_row();
+            Key::Char(c) if *c == b'\t' || !c.is_ascii_control() => {
+                self.delete_block(&ranges);
+                self.add_block_cursors(&ranges, y);
+                self.at_each_cursor(|ed| ed.insert_byte(*c));
             }
-            Key::Char(COPY) => self.copy_current_row(),
-            Key::Char(PASTE) => self.paste_current_row(),
-            Key::Char(TOGGLE_COMMENT) => self.toggle_comment(),
-            Key::Char(EXECUTE) => prompt_mode = Some(PromptMode::Execute(String::new())),
-            Key::Char(c) => self.insert_byte(*c),
+            _ => return false,
         }
-        self.quit_times = if reset_quit_times { 0 } else { self.quit_times + 1 };
-        (false, prompt_mode)
+        self.buf.history.merge_since(checkpoint);
+        (self.anchor, self.block) = (None, false);
+        true
     }
 
-    /// Try to find a query, this is called after pressing Ctrl-F and for each
-    /// key that is pressed. `last_match` is the last row that was matched,
-    /// `forward` indicates whether to search forward or backward. Returns
-    /// the row of a new match, or `None` if the search was unsuccessful.
-    fn find(&mut self, query: &str, last_match: Option<usize>, forward: bool) -> Option<usize> {
-        // Number of rows to search
-        let num_rows = if query.is_empty() { 0 } else { self.rows.len() };
-        let mut current = last_match.unwrap_or_else(|| num_rows.saturating_sub(1));
-        // TODO: Handle multiple matches per line
-        for _ in 0..num_rows {
-            current = (current + if forward { 1 } else { num_rows - 1 }) % num_rows;
-            let row = &mut self.rows[current];
-            if let Some(cx) = row.chars.windows(query.len()).position(|w| w == query.as_bytes()) {
-                // self.cursor.coff: Try to reset the column offset; if the match is after the
-                // offset, this will be updated in self.cursor.scroll() so that
-                // the result is visible
-                (self.cursor.x, self.cursor.y, self.cursor.coff) = (cx, current, 0);
-                let rx = row.cx2rx[cx];
-                row.match_segment = Some(rx..rx + query.len());
-                return Some(current);
+    /// Put a cursor at the start of the block in each of its rows, the main
+    /// cursor being on row `y`.
+    fn add_block_cursors(&mut self, ranges: &[(usize, Range<usize>)], y: usize) {
+        let y = y.min(self.buf.n_rows().saturating_sub(1));
+        for (by, range) in ranges {
+            if *by == y {
+                self.cursor.x = range.start;
+            } else {
+                self.cursors.push((range.start, *by));
             }
         }
-        None
+        self.cursor.y = y;
+        self.merge_cursors();
     }
 
-    /// If `file_name` is not None, load the file. Then run the text editor.
-    ///
-    /// # Errors
-    ///
-    /// Will Return `Err` if any error occur.
-    pub fn run<I: BufRead>(&mut self, file_name: Option<&str>, input: &mut I) -> Result<(), Error> {
-        self.update_window_size()?;
-        set_status!(self, "{HELP_MESSAGE}");
+    /// Delete the selected text, if any, and move the cursor to the start of
+    /// the selection. Return whether text was deleted.
+    fn delete_selection(&mut self, selection: Option<((usize, usize), (usize, usize))>) -> bool {
+        let Some((start, end)) = selection else { return false };
+        self.delete_text(start, end);
+        (self.cursor.x, self.cursor.y) = start;
+        true
+    }
+
+    /// Call `f` at each cursor, starting with the main cursor, so that text is
+    /// typed or deleted at every cursor. The edits are merged into a single
+    /// change.
+    fn at_each_cursor(&mut self, mut f: impl FnMut(&mut Self)) {
+        let checkpoint = self.buf.history.checkpoint();
+        // Each cursor becomes the main cursor in turn, while the other ones are moved
+        // by the edits.
+        for _ in 0..=self.cursors.len() {
+            f(self);
+            self.cursors.push((self.cursor.x, self.cursor.y));
+            (self.cursor.x, self.cursor.y) = self.cursors.remove(0);
+        }
+        self.buf.history.merge_since(checkpoint);
+        self.merge_cursors();
+    }
+
+    /// Sort the extra cursors and remove the duplicates, as well as the extra
+    /// cursors at the position of the main cursor.
+    fn merge_cursors(&mut self) {
+        let main = (self.cursor.x, self.cursor.y);
+        self.cursors.sort_unstable_by_key(|&(x, y)| (y, x));
+        self.cursors.dedup();
+        self.cursors.retain(|pos| *pos != main);
+    }
+
+    /// Add a cursor at the position of the main cursor, then move the main
+    /// cursor to `pos`.
+    fn add_cursor(&mut self, pos: (usize, usize)) {
+        self.cursors.push((self.cursor.x, self.cursor.y));
+        (self.cursor.x, self.cursor.y) = pos;
+        self.merge_cursors();
+    }
+
+    /// Add a cursor on the row above or below the main cursor, which becomes
+    /// the main cursor.
+    fn add_cursor_vertically(&mut self, arrow: &AKey) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        self.move_cursor(arrow, false);
+        if self.cursor.y != y {
+            let pos = (self.cursor.x, self.cursor.y);
+            (self.cursor.x, self.cursor.y) = (x, y);
+            self.add_cursor(pos);
+        }
+    }
+
+    /// Add a cursor at the next occurrence of the word under the main cursor,
+    /// at the same position in the word, wrapping around to the start of the
+    /// buffer. The new cursor becomes the main cursor.
+    fn add_cursor_at_next_occurrence(&mut self) {
+        let ((x, y), line) = ((self.cursor.x, self.cursor.y), self.buf.line(self.cursor.y));
+        let is_word = |c: &u8| c.is_ascii_alphanumeric() || *c == b'_' || !c.is_ascii();
+        let start = line[..x].iter().rposition(|c| !is_word(c)).map_or(0, |i| i + 1);
+        let end = line[x..].iter().position(|c| !is_word(c)).map_or(line.len(), |i| x + i);
+        let Some(word) = line.get(start..end).filter(|word| !word.is_empty()) else {
+            return set_status!(self, "No word under the cursor");
+        };
+        let mut occurrences = Vec::new();
+        for (row, chars) in self.buf.text.lines(0).enumerate() {
+            for (i, w) in chars.windows(word.len()).enumerate() {
+                let after = chars.get(i + word.len());
+                if w == word
+                    && (i == 0 || !is_word(&chars[i - 1]))
+                    && after.is_none_or(|c| !is_word(c))
+                {
+                    occurrences.push((i + x - start, row));
+                }
+            }
+        }
+        let free = |pos: &&(usize, usize)| **pos != (x, y) && !self.cursors.contains(pos);
+        let next = occurrences.iter().filter(|(ox, oy)| (*oy, *ox) > (y, x)).find(free);
+        match next.or_else(|| occurrences.iter().find(free)) {
+            Some(&pos) => self.add_cursor(pos),
+            None => set_status!(self, "No other occurrence of {}", String::from_utf8_lossy(word)),
+        }
+    }
+
+    /// Add a cursor at every match of `query`. The main cursor stays where it
+    /// is, normally on the current match.
+    fn add_cursors_at_matches(&mut self, query: &[u8]) {
+        self.buf.match_segment = None;
+        if query.is_empty() {
+            return;
+        }
+        for (y, chars) in self.buf.text.lines(0).enumerate() {
+            let matches = chars.windows(query.len()).enumerate().filter(|(_, w)| *w == query);
+            self.cursors.extend(matches.map(|(x, _)| (x, y)));
+        }
+        self.merge_cursors();
+    }
+
+    /// If there are extra cursors and `key` types or deletes text or moves the
+    /// cursor, process it at every cursor and return `true`. Other keys remove
+    /// the extra cursors, except keys that add cursors.
+    fn process_keypress_at_cursors(&mut self, key: &Key) -> bool {
+        if self.cursors.is_empty() {
+            return false;
+        }
+        #[expect(clippy::wildcard_enum_match_arm)]
+        match key {
+            Key::Arrow(arrow) => self.at_each_cursor(|ed| ed.move_cursor(arrow, false)),
+            Key::CtrlArrow(arrow) => self.at_each_cursor(|ed| ed.move_cursor(arrow, true)),
+            Key::Home => self.at_each_cursor(|ed| ed.cursor.x = 0),
+            Key::End => self.at_each_cursor(|ed| ed.cursor.x = ed.buf.line(ed.cursor.y).len()),
+            Key::Char(b'\r' | b'\n') => self.at_each_cursor(Self::insert_new_line),
+            Key::Char(BACKSPACE | DELETE_BIS) => self.at_each_cursor(|ed| ed.delete_char(true)),
+            Key::Delete => self.at_each_cursor(Self::delete_next_char),
+            Key::Char(c) if *c == b'\t' || !c.is_ascii_control() =>
+                self.at_each_cursor(|ed| ed.insert_byte(*c)),
+            Key::AltArrow(AKey::Up | AKey::Down) | Key::Alt(ADD_CURSOR_AT_NEXT_OCCURRENCE) =>
+                return false,
+            _ => {
+                self.cursors.clear();
+                return false;
+            }
+        }
+        (self.anchor, self.block) = (None, false);
+        true
+    }
+
+    /// Move row `from` so that it becomes row `to`, the rows in between being
+    /// shifted by one row.
+    fn move_row(&mut self, from: usize, to: usize) {
+        let line = self.buf.line(from).into_owned();
+        if from + 1 < self.buf.n_rows() {
+            self.delete_text((0, from), (0, from + 1));
+        } else {
+            self.delete_text((self.buf.line(from - 1).len(), from - 1), (line.len(), from));
+        }
+        if to < self.buf.n_rows() {
+            self.insert_text((0, to), &[&line[..], b"\n"].concat(), false);
+        } else {
+            let y = self.buf.n_rows() - 1;
+            self.insert_text((self.buf.line(y).len(), y), &[b"\n", &line[..]].concat(), false);
+        }
+    }
+
+    /// Move the current row, or the rows of the selection, one row up or
+    /// down. The selection, which starts at `anchor`, is kept.
+    fn move_lines(
+        &mut self, arrow: &AKey, selection: Option<((usize, usize), (usize, usize))>,
+        anchor: Option<(usize, usize)>,
+    ) {
+        let (y, n_rows) = (self.cursor.y, self.buf.n_rows());
+        // A selection ending at the start of a row does not include this row
+        let (y0, y1) = selection.map_or((y, y), |((_, y0), (x1, y1))| {
+            (y0, if x1 == 0 && y1 > y0 { y1 - 1 } else { y1 })
+        });
+        self.anchor = anchor;
+        let up = matches!(arrow, AKey::Up);
+        if !(up && y0 > 0 && y1 < n_rows || matches!(arrow, AKey::Down) && y1 + 1 < n_rows) {
+            return;
+        }
+        let checkpoint = self.buf.history.checkpoint();
+        if up {
+            self.move_row(y0 - 1, y1);
+        } else {
+            self.move_row(y1 + 1, y0);
+        }
+        self.buf.history.merge_since(checkpoint);
+        let shift = |y: usize| if up { y - 1 } else { y + 1 };
+        self.cursor.y = shift(y);
+        self.anchor = anchor.map(|(x, y)| (x, shift(y)));
+    }
+
+    /// Swap the current row with the previous one, and move the cursor to the
+    /// next row.
+    fn transpose_lines(&mut self) {
+        let y = self.cursor.y;
+        if y == 0 || y >= self.buf.n_rows() {
+            return;
+        }
+        let checkpoint = self.buf.history.checkpoint();
+        self.move_row(y - 1, y);
+        self.buf.history.merge_since(checkpoint);
+        self.cursor.y = (y + 1).min(self.buf.n_rows() - 1);
+        self.update_cursor_x_position();
+    }
+
+    /// Join the next row onto the current row. The whitespace between them is
+    /// replaced with a single space, or removed if one of them is blank.
+    fn join_lines(&mut self) {
+        let y = self.cursor.y;
+        if y + 1 >= self.buf.n_rows() {
+            return;
+        }
+        let (line, next) = (self.buf.line(y), self.buf.line(y + 1).into_owned());
+        let end = line.len() - line.iter().rev().take_while(|b| is_blank(**b)).count();
+        let start = next.iter().take_while(|b| is_blank(**b)).count();
+        let checkpoint = self.buf.history.checkpoint();
+        self.delete_text((end, y), (start, y + 1));
+        if end > 0 && start < next.len() {
+            self.insert_text((end, y), b" ", false);
+        }
+        self.buf.history.merge_since(checkpoint);
+        (self.cursor.x, self.cursor.y) = (end, y);
+    }
+
+    /// Reflow the rows of the selection, or the paragraph of the current row
+    /// (the rows around it that are not blank and have the same comment
+    /// start), so that they fit in `text_width` columns. The cursor is moved
+    /// to the end of the reflowed text. The change is undone at once.
+    fn reflow(&mut self, selection: Option<((usize, usize), (usize, usize))>) {
+        let (y, n_rows) = (self.cursor.y, self.buf.n_rows());
+        let comment_starts = &self.buf.syntax.sl_comment_start;
+        let kind = |y| reflow::paragraph_kind(&self.buf.line(y), comment_starts);
+        let (y0, y1) = match selection {
+            // A selection ending at the start of a row does not include this row
+            Some(((_, y0), (0, y1))) if y1 > y0 => (y0, y1 - 1),
+            Some(((_, y0), (_, y1))) => (y0, y1),
+            None if y < n_rows
+                && let Some(k) = kind(y) =>
+            {
+                let same = |y: &usize| kind(*y).as_ref() == Some(&k);
+                let y0 = (0..y).rev().take_while(same).last().unwrap_or(y);
+                (y0, (y + 1..n_rows).take_while(same).last().unwrap_or(y))
+            }
+            None => return,
+        };
+        let rows: Vec<_> = (y0..=y1).map(|y| self.buf.line(y).into_owned()).collect();
+        let (width, tab) = (self.config.text_width, self.tab_stop_of(&self.buf));
+        let text = reflow::reflow(&rows, comment_starts, width, tab);
+        let checkpoint = self.buf.history.checkpoint();
+        if text != rows.join(&b'\n') {
+            self.delete_text((0, y0), (rows[rows.len() - 1].len(), y1));
+            self.insert_text((0, y0), &text, false);
+        }
+        self.buf.history.merge_since(checkpoint);
+        (self.cursor.x, self.cursor.y) = end_pos((0, y0), &text);
+    }
+
+    /// Swap the character before the cursor with the character under the
+    /// cursor, and move the cursor after them. At the end of a row, the last
+    /// two characters are swapped.
+    fn transpose_chars(&mut self) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        let Some(row) = self.current_row().filter(|row| x > 0 && row.chars.len() > 1) else {
+            return;
+        };
+        let prev_start = |cx: usize| cx - row.get_char_size(row.cx2rx[cx] - 1);
+        let (mid, end) = if x < row.chars.len() {
+            (x, x + row.get_char_size(row.cx2rx[x]))
+        } else if prev_start(x) > 0 {
+            (prev_start(x), x)
+        } else {
+            return;
+        };
+        let start = prev_start(mid);
+        let text = [&row.chars[mid..end], &row.chars[start..mid]].concat();
+        let checkpoint = self.buf.history.checkpoint();
+        self.delete_text((start, y), (end, y));
+        self.insert_text((start, y), &text, false);
+        self.buf.history.merge_since(checkpoint);
+        self.cursor.x = end;
+    }
+
+    /// Apply the transformation named `name` to the selected text, or to the
+    /// current row if nothing is selected. Transformations that act on lines
+    /// apply to all the rows of the selection, which is kept around the
+    /// result. The change is undone at once.
+    fn transform(&mut self, name: &str) {
+        let Some((_, lines, f)) = TRANSFORMS.iter().find(|(n, ..)| *n == name.trim()) else {
+            let names: Vec<_> = TRANSFORMS.iter().map(|(n, ..)| *n).collect();
+            set_status!(self, "Unknown transformation: {name} (expected {})", names.join(", "));
+            return;
+        };
+        let (y, selection) = (self.cursor.y, self.selection());
+        let (start, end) = match selection {
+            // A selection ending at the start of a row does not include this row
+            Some(((_, y0), (x1, y1))) if *lines => {
+                let y1 = if x1 == 0 && y1 > y0 { y1 - 1 } else { y1 };
+                ((0, y0), (self.buf.line(y1).len(), y1))
+            }
+            Some(selection) => selection,
+            None if y < self.buf.n_rows() => ((0, y), (self.buf.line(y).len(), y)),
+            None => return,
+        };
+        let text = self.buf.text_between(start, end);
+        let new_text = match f(&text) {
+            Ok(new_text) => new_text,
+            Err(err) => return set_status!(self, "{name}: {err}"),
+        };
+        let checkpoint = self.buf.history.checkpoint();
+        let new_end = if new_text == text {
+            end
+        } else {
+            self.delete_text(start, end);
+            if new_text.is_empty() { start } else { self.insert_text(start, &new_text, false) }
+        };
+        self.buf.history.merge_since(checkpoint);
+        if selection.is_some() {
+            self.anchor = Some(start);
+            (self.cursor.x, self.cursor.y) = new_end;
+        } else {
+            self.update_cursor_x_position();
+        }
+    }
+
+    /// Convert the line endings of the buffer, given their name. The buffer is
+    /// modified, but the text itself does not change.
+    fn set_line_ending(&mut self, name: &str) {
+        match LineEnding::from_name(name) {
+            Some(line_ending) => {
+                self.buf.line_ending = line_ending;
+                self.update_dirty();
+            }
+            None => set_status!(self, "Unknown line ending: {name}"),
+        }
+    }
+
+    /// Execute an external command and insert its output at the cursor. If it
+    /// fails, show its error output instead.
+    fn execute(&mut self, command: &str) {
+        let mut args = command.split_whitespace();
+        match Command::new(args.next().unwrap_or_default()).args(args).output() {
+            Ok(out) if !out.status.success() =>
+                set_status!(self, "{}", String::from_utf8_lossy(&out.stderr).trim_end()),
+            Ok(out) => self.insert_at_cursor(&out.stdout, false),
+            Err(e) => set_status!(self, "{e}"),
+        }
+    }
+
+    /// Change the encoding used to save the buffer, given its name.
+    fn set_encoding(&mut self, name: &str) {
+        match Encoding::from_name(name) {
+            Some(encoding) => {
+                self.buf.encoding = encoding;
+                self.update_dirty();
+            }
+            None => set_status!(self, "Unknown encoding: {name}"),
+        }
+    }
+
+    /// Toggle comment on the current line using the appropriate comment symbol
+    /// from the syntax configuration. If the line is already commented,
+    /// uncomment it. If not, add a comment symbol at the beginning.
+    fn toggle_comment(&mut self) {
+        // Get the first single-line comment start symbol from syntax config
+        let Some(sym) = self.buf.syntax.sl_comment_start.first().cloned() else { return };
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if y >= self.buf.n_rows() {
+            return;
+        }
+        let chars = self.buf.line(y);
+        // Find the first non-whitespace character position
+        let pos = chars.iter().position(|&c| !(c as char).is_whitespace()).unwrap_or(0);
+
+        // Check if the line is already commented
+        let n_update = if chars.get(pos..pos + sym.len()) == Some(sym.as_bytes()) {
+            let to_remove = sym.len() + usize::from(chars.get(pos + sym.len()) == Some(&b' '));
+            // Remove the comment and return the removed size as a negative integer
+            self.delete_text((pos, y), (pos + to_remove, y));
+            0isize.saturating_sub_unsigned(to_remove)
+        } else {
+            // Insert comment at the first non-whitespace position
+            self.insert_text((pos, y), &[sym.as_bytes(), b" "].concat(), false);
+            1isize.saturating_add_unsigned(sym.len())
+        };
+        if x >= pos {
+            self.cursor.x = x.saturating_add_signed(n_update);
+        }
+        // Update cursor position to ensure it's valid after row update
+        self.update_cursor_x_position();
+    }
+
+    /// Try to load a file. If found, load its text; rows will be rendered when
+    /// they are shown. If not found, do not return an error.
+    fn load(&mut self, path: &Path) -> Result<(), io::Error> {
+        let file = match File::open(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                self.buf.text = Rope::new(&[]);
+                return Ok(());
+            }
+            r => r,
+        }?;
+        let ft = file.metadata()?.file_type();
+        if !(ft.is_file() || ft.is_symlink()) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid input file type"));
+        }
+        let bytes = fs::read(path)?;
+        let encoding = Encoding::detect(&bytes);
+        let (text, invalid) = encoding.decode(&bytes);
+        let (text, line_ending, mixed) = LineEnding::normalize(&text);
+        if invalid {
+            set_status!(self, "Warning: invalid {}, replaced with U+FFFD", encoding.name());
+        } else if encoding == Encoding::Windows1252 {
+            set_status!(self, "Warning: the file is not valid UTF-8, it is read as Windows-1252");
+        } else if mixed {
+            let name = line_ending.name();
+            set_status!(self, "Warning: mixed line endings, they will be saved as {name}");
+        }
+        (self.buf.line_ending, self.buf.saved_line_ending) = (line_ending, line_ending);
+        (self.buf.encoding, self.buf.saved_encoding) = (encoding, encoding);
+        self.buf.indent = Indent::detect(&text);
+        self.buf.text = Rope::new(&text);
+        self.buf.invalidate_rows(0);
+        // The number of rows has changed. The left padding may need to be updated.
+        self.update_screen_cols();
+        Ok(())
+    }
+
+    /// Apply the properties of the `.editorconfig` files that match the file
+    /// of the current buffer. They take precedence over the indentation
+    /// guessed from the file, and the line endings they set are used when the
+    /// file is saved. The encoding they set is only used for a new file: if
+    /// `detected` is true, the encoding detected from the file is kept, with a
+    /// warning if it is different.
+    fn apply_editorconfig(&mut self, path: &Path, detected: bool) {
+        let props = editorconfig::Properties::find(path);
+        self.set_indent(props.expand_tab, props.indent_width);
+        match props.encoding {
+            Some(encoding) if detected && encoding != self.buf.encoding => {
+                let (name, charset) = (self.buf.encoding.name(), encoding.name());
+                set_status!(
+                    self,
+                    "Warning: the file is in {name}, not in {charset} as in .editorconfig"
+                );
+            }
+            Some(encoding) => (self.buf.encoding, self.buf.saved_encoding) = (encoding, encoding),
+            None => (),
+        }
+        let buf = &mut self.buf;
+        buf.tab_stop = props.tab_stop;
+        if let Some(line_ending) = props.line_ending {
+            (buf.line_ending, buf.saved_line_ending) = (line_ending, line_ending);
+        }
+        buf.trim_trailing_whitespace = props.trim_trailing_whitespace;
+        buf.insert_final_newline = props.insert_final_newline;
+    }
+
+    /// Apply the settings of the modelines found in the first and last rows
+    /// of the current buffer: its tab size, its indentation and its language,
+    /// which is looked up by name.
+    fn apply_modelines(&mut self) {
+        let n_rows = self.buf.n_rows();
+        let last_rows = n_rows.saturating_sub(modeline::N_ROWS).max(modeline::N_ROWS)..n_rows;
+        let rows = (0..n_rows.min(modeline::N_ROWS)).chain(last_rows);
+        let modeline = Modeline::find(rows.map(|y| self.buf.line(y)));
+        if let Some(name) = modeline.syntax {
+            match SyntaxConf::find_by_name(&name, &sys::data_dirs()) {
+                Some(syntax) => self.buf.syntax = syntax,
+                None => set_status!(self, "Warning: unknown language {name} in modeline"),
+            }
+        }
+        self.buf.tab_stop = modeline.tab_stop.or(self.buf.tab_stop);
+        self.set_indent(modeline.expand_tab, modeline.indent_width);
+    }
+
+    /// Set the indentation of the current buffer to tabs or spaces, and set
+    /// the number of spaces per level. If either setting is `None`, the
+    /// current one is kept.
+    fn set_indent(&mut self, expand_tab: Option<bool>, width: Option<NonZeroUsize>) {
+        if expand_tab.is_none() && width.is_none() {
+            return;
+        }
+        let default_width = self.buf.syntax.indent_width.unwrap_or(self.config.indent_width);
+        self.buf.indent = Some(match (expand_tab, self.indent_of(&self.buf)) {
+            (Some(false), _) | (None, Indent::Tabs) => Indent::Tabs,
+            (_, Indent::Spaces(n)) => Indent::Spaces(width.unwrap_or(n)),
+            (Some(true), Indent::Tabs) => Indent::Spaces(width.unwrap_or(default_width)),
+        });
+    }
+
+    /// Return whether the file or the configuration asks for the trailing
+    /// whitespace to be trimmed, and for a final new line to be added.
+    fn clean_up_settings(&self) -> (bool, bool) {
+        let buf = &self.buf;
+        let trim = buf.trim_trailing_whitespace.unwrap_or(self.config.trim_trailing_whitespace);
+        (trim, buf.insert_final_newline.unwrap_or(self.config.insert_final_newline))
+    }
+
+    /// Return the text as it will be after `clean_up_text`, with `\n` line
+    /// endings, without changing the buffer.
+    fn cleaned_up_text(&self) -> Vec<u8> {
+        let (trim, final_newline) = self.clean_up_settings();
+        let mut text = Vec::with_capacity(self.buf.text.len() + 1);
+        for (y, line) in self.buf.text.lines(0).enumerate() {
+            let blanks =
+                if trim { line.iter().rev().take_while(|b| is_blank(**b)).count() } else { 0 };
+            text.extend_from_slice(if y == 0 { b"" } else { b"\n" });
+            text.extend_from_slice(&line[..line.len() - blanks]);
+        }
+        if final_newline && text.last().is_some_and(|b| *b != b'\n') {
+            text.push(b'\n');
+        }
+        text
+    }
+
+    /// Remove the trailing whitespace of the rows and add a new line at the
+    /// end of the text, if the file or the configuration asks for it. The
+    /// changes are undone at once, and the cursor is kept on the text. Return
+    /// what was cleaned up, as shown in the status bar.
+    fn clean_up_text(&mut self) -> Vec<String> {
+        let (checkpoint, mut cleaned_up) = (self.buf.history.checkpoint(), Vec::new());
+        let (trim, final_newline) = self.clean_up_settings();
+        if trim {
+            let mut n_trimmed = 0;
+            for y in 0..self.buf.n_rows() {
+                let line = self.buf.line(y);
+                let len = line.len() - line.iter().rev().take_while(|b| is_blank(**b)).count();
+                if len < line.len() {
+                    self.delete_text((len, y), (line.len(), y));
+                    n_trimmed += 1;
+                }
+            }
+            if n_trimmed > 0 {
+                let s = if n_trimmed == 1 { "" } else { "s" };
+                cleaned_up.push(format!("trailing whitespace trimmed on {n_trimmed} row{s}"));
+            }
+        }
+        let last = self.buf.n_rows().saturating_sub(1);
+        if final_newline && !self.buf.line(last).is_empty() {
+            self.insert_text((self.buf.line(last).len(), last), b"\n", false);
+            cleaned_up.push(String::from("final new line added"));
+        }
+        self.buf.history.merge_since(checkpoint);
+        self.update_cursor_x_position();
+        cleaned_up
+    }
+
+    /// Save the text to a file, given its name. The text is cleaned up if the
+    /// file or the configuration asks for it; the buffer is only cleaned up
+    /// once the file has been written. Return the number of bytes written and
+    /// what was cleaned up.
+    fn save(&mut self, file_name: &str) -> Result<(usize, Vec<String>), io::Error> {
+        let mut text = Vec::with_capacity(self.buf.text.len());
+        self.buf.line_ending.write(&mut text, once(self.cleaned_up_text()))?;
+        // The text is encoded before creating the file, so that the file is left
+        // untouched if the text can't be encoded
+        let bytes = self.buf.encoding.encode(text)?;
+        let mut file = File::create(file_name)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        Ok((bytes.len(), self.clean_up_text()))
+    }
+
+    /// Save the text to a file and handle all errors. Errors and success
+    /// messages will be printed to the status bar. Return whether the file
+    /// was successfully saved.
+    fn save_and_handle_io_errors(&mut self, file_name: &str) -> bool {
+        let saved = self.save(file_name);
+        // Print error or success message to the status bar
+        match saved.as_ref() {
+            Ok((w, cleaned_up)) => {
+                let size = format_size(*w as u64);
+                let cleaned_up = if cleaned_up.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", cleaned_up.join(", "))
+                };
+                set_status!(self, "{size} written to {file_name}{cleaned_up}");
+            }
+            Err(err) => set_status!(self, "Can't save! I/O error: {err}"),
+        }
+        // If save was successful, set dirty to false.
+        if saved.is_ok() {
+            self.buf.history.mark_saved();
+            self.buf.saved_line_ending = self.buf.line_ending;
+            self.buf.saved_encoding = self.buf.encoding;
+        }
+        self.buf.dirty &= saved.is_err();
+        saved.is_ok()
+    }
+
+    /// Save to a file after obtaining the file path from the prompt. If
+    /// successful, the `file_name` attribute of the editor will be set and
+    /// syntax highlighting will be updated.
+    fn save_as(&mut self, file_name: String) {
+        if self.buf.file_name.is_none() {
+            // The properties of the `.editorconfig` files apply to the new file
+            self.apply_editorconfig(&sys::path(&file_name), false);
+        }
+        if self.save_and_handle_io_errors(&file_name) {
+            // If save was successful
+            self.buf.syntax = SyntaxConf::find(&file_name, &sys::data_dirs());
+            self.buf.file_name = Some(file_name);
+            self.buf.invalidate_rows(0);
+        }
+    }
+
+    /// Load a file into the current buffer, which must be empty. If
+    /// `file_name` is `None`, start an empty document instead.
+    fn load_buffer(&mut self, file_name: Option<&str>) -> Result<(), io::Error> {
+        if let Some(path) = file_name.map(sys::path) {
+            self.buf.syntax = SyntaxConf::find(&path.to_string_lossy(), &sys::data_dirs());
+            let detected = path.exists();
+            self.load(path.as_path())?;
+            self.apply_editorconfig(path.as_path(), detected);
+            if self.config.modelines {
+                self.apply_modelines();
+            }
+            self.buf.file_name = Some(path.to_string_lossy().to_string());
+        } else {
+            self.buf.text = Rope::new(&[]);
+        }
+        Ok(())
+    }
+
+    /// Return all open buffers, in order.
+    fn buffers(&self) -> impl Iterator<Item=&Buffer> {
+        let (before, after) = self.inactive.split_at(self.buf_idx);
+        let before = before.iter().map(|(buf, _)| buf);
+        before.chain(once(&self.buf)).chain(after.iter().map(|(buf, _)| buf))
+    }
+
+    /// Return mutable references to all open buffers, in order.
+    fn buffers_mut(&mut self) -> impl Iterator<Item=&mut Buffer> {
+        let (before, after) = self.inactive.split_at_mut(self.buf_idx);
+        let before = before.iter_mut().map(|(buf, _)| buf);
+        before.chain(once(&mut self.buf)).chain(after.iter_mut().map(|(buf, _)| buf))
+    }
+
+    /// Return the list of open buffers, as shown in the buffer switcher prompt.
+    /// The current buffer is shown in brackets and modified buffers are marked
+    /// with `*`.
+    fn buffer_list(&self) -> String {
+        let names = self.buffers().enumerate().map(|(i, buf)| {
+            let (name, modified) = (buf.name(), if buf.dirty { "*" } else { "" });
+            if i == self.buf_idx {
+                format!("[{}:{name}{modified}]", i + 1)
+            } else {
+                format!("{}:{name}{modified}", i + 1)
+            }
+        });
+        names.collect::<Vec<_>>().join(" ")
+    }
+
+    /// Switch to the buffer with the given number, counted from 1, or else to
+    /// the first buffer whose name contains `name`.
+    fn switch_to_buffer_named(&mut self, name: &str) {
+        let by_number = name.trim().parse::<usize>().ok().and_then(|n| n.checked_sub(1));
+        let i = by_number.or_else(|| self.buffers().position(|buf| buf.name().contains(name)));
+        match i {
+            Some(i) if i <= self.inactive.len() => self.switch_buffer(i),
+            _ => set_status!(self, "No such buffer: {name}"),
+        }
+    }
+
+    /// Make the buffer at index `i` among all open buffers the current buffer.
+    /// The cursor state of each buffer is saved and restored.
+    fn switch_buffer(&mut self, i: usize) {
+        if i == self.buf_idx || i > self.inactive.len() {
+            return;
+        }
+        let (buf, cursor) = self.inactive.remove(if i < self.buf_idx { i } else { i - 1 });
+        let previous = (mem::replace(&mut self.buf, buf), mem::replace(&mut self.cursor, cursor));
+        self.inactive.insert(self.buf_idx - usize::from(i < self.buf_idx), previous);
+        (self.buf_idx, self.anchor) = (i, None);
+        // The number of rows may be different. The left padding may need to be updated.
+        self.update_screen_cols();
+    }
+
+    /// Switch to the next buffer (`offset` = 1) or the previous buffer
+    /// (`offset` = -1), wrapping around.
+    fn cycle_buffers(&mut self, offset: isize) {
+        let n = self.inactive.len() + 1;
+        self.switch_buffer((self.buf_idx + n).wrapping_add_signed(offset) % n);
+    }
+
+    /// Open a file in a new buffer, placed after the current buffer. If the
+    /// file is already open, switch to its buffer instead.
+    fn open(&mut self, file_name: &str) {
+        let path = sys::path(file_name).to_string_lossy().to_string();
+        let open_buffer = self.buffers().position(|buf| buf.file_name.as_ref() == Some(&path));
+        if let Some(i) = open_buffer {
+            return self.switch_buffer(i);
+        }
+        let previous = (mem::take(&mut self.buf), mem::take(&mut self.cursor));
+        self.inactive.insert(self.buf_idx, previous);
+        (self.buf_idx, self.anchor) = (self.buf_idx + 1, None);
+        // Panes showing the following buffers now show buffers at a different index
+        for pane in self.layout.panes_mut().into_iter().filter(|p| p.buf_idx >= self.buf_idx) {
+            pane.buf_idx += 1;
+        }
+        if let Err(err) = self.load_buffer(Some(file_name)) {
+            self.close_buffer();
+            set_status!(self, "Can't open {path}: {err}");
+        }
+    }
+
+    /// Close the current buffer and switch to the previous one. If it is the
+    /// only buffer, replace it with an empty document.
+    fn close_buffer(&mut self) {
+        if self.inactive.is_empty() {
+            (self.buf, self.cursor) = (Buffer::default(), CursorState::default());
+            self.buf.text = Rope::new(&[]);
+        } else {
+            let closed = self.buf_idx;
+            self.buf_idx = self.buf_idx.saturating_sub(1);
+            (self.buf, self.cursor) = self.inactive.remove(self.buf_idx);
+            // Panes showing the closed buffer now show the new current buffer
+            for pane in self.layout.panes_mut().into_iter().filter(|p| p.buf_idx >= closed) {
+                pane.buf_idx = if pane.buf_idx == closed { self.buf_idx } else { pane.buf_idx - 1 };
+            }
+        }
+        self.anchor = None;
+        self.update_screen_cols();
+    }
+
+    /// Split the current pane in two panes showing the current buffer, side by
+    /// side if `vertical` is true, one above the other otherwise.
+    fn split_pane(&mut self, vertical: bool) {
+        let pane = Pane { buf_idx: self.buf_idx, cursor: self.cursor.clone(), ..Pane::default() };
+        self.layout.split(self.pane_idx, vertical, pane);
+        self.update_layout();
+    }
+
+    /// Make the pane at index `i` the current pane.
+    fn switch_pane(&mut self, i: usize) {
+        let pane = self.layout.pane_mut(self.pane_idx);
+        (pane.buf_idx, pane.cursor) = (self.buf_idx, self.cursor.clone());
+        self.pane_idx = i;
+        self.enter_pane();
+    }
+
+    /// Close the current pane, unless it is the only one, and switch to the
+    /// next pane.
+    fn close_pane(&mut self) {
+        if !self.layout.close(self.pane_idx) {
+            return set_status!(self, "Cannot close the only pane");
+        }
+        self.pane_idx = self.pane_idx.min(self.layout.panes().len() - 1);
+        self.enter_pane();
+    }
+
+    /// Show the buffer of the current pane and restore the cursor state of the
+    /// pane. The buffer may have been edited in another pane, so the cursor
+    /// position is clamped.
+    fn enter_pane(&mut self) {
+        let pane = self.layout.pane_mut(self.pane_idx).clone();
+        self.switch_buffer(pane.buf_idx);
+        (self.cursor, self.anchor) = (pane.cursor, None);
+        self.cursor.y = self.cursor.y.min(self.buf.n_rows());
+        self.update_cursor_x_position();
+        self.update_layout();
+    }
+
+    /// Draw the left part of a pane: line numbers and vertical bar.
+    fn draw_left_padding<T: Display>(&self, ln_pad: usize, buffer: &mut String, val: T) {
+        if ln_pad >= 2 {
+            // \u{2502}: pipe "│"
+            let s = format!("{:>1$} \u{2502}", val, ln_pad - 2);
+            // \x1b[38;5;240m: Dark grey color
+            push_colored(buffer, "\x1b[38;5;240m", &s, self.use_color);
+        }
+    }
+
+    /// Draw rows of text and empty rows of a pane on the terminal, by adding
+    /// characters to the buffer. The selection is only shown in the current
+    /// pane.
+    fn draw_rows(
+        &self, pane: &Pane, buf: &Buffer, current: bool, buffer: &mut String,
+    ) -> Result<(), Error> {
+        let (top, left, height, width) = pane.rect;
+        let ln_pad = self.line_number_padding(buf.n_rows(), width);
+        let (n_rows, n_cols) = (height.saturating_sub(1), width.saturating_sub(ln_pad));
+        let (mut line, mut i) = (0, pane.cursor.roff);
+        while line < n_rows {
+            if let Some(row) = buf.rendered_row(i) {
+                // Draw a row of text. The match segment (background set to cyan) takes
+                // precedence over the brackets (underlined) and the selection
+                // (inverted colors).
+                let matched = buf.match_segment.iter().filter(|(y, _)| *y == i);
+                let mut overlays: Vec<_> =
+                    matched.map(|(_, m)| (m.clone(), HlType::Match)).collect();
+                if current {
+                    let brackets = self.matched_brackets.iter().filter(|(y, _)| *y == i);
+                    overlays.extend(brackets.map(|(_, r)| (r.clone(), HlType::Bracket)));
+                    let selected = self.selected_rx_ranges(i).into_iter();
+                    overlays.extend(selected.map(|r| (r, HlType::Selection)));
+                }
+                // With soft wrapping, the row is drawn on several lines, and its line number is
+                // only shown on the first one.
+                let starts = if self.config.soft_wrap {
+                    row.wrap(n_cols)
+                } else {
+                    vec![(pane.cursor.coff, 0)]
+                };
+                for (k, (start, _)) in starts.iter().enumerate().take(n_rows - line) {
+                    start_pane_line(buffer, (top + line, left), width)?;
+                    if k == 0 {
+                        self.draw_left_padding(ln_pad, buffer, i + 1);
+                    } else {
+                        self.draw_left_padding(ln_pad, buffer, "");
+                    }
+                    let len = starts.get(k + 1).map_or(n_cols, |(next, _)| next - start);
+                    row.draw(*start, len, &overlays, buffer, self.use_color);
+                    line += 1;
+                }
+            } else {
+                // Draw an empty row
+                start_pane_line(buffer, (top + line, left), width)?;
+                self.draw_left_padding(ln_pad, buffer, '~');
+                if buf.is_empty() && i == n_rows / 3 {
+                    write!(buffer, "{WELCOME_MESSAGE:^n_cols$.n_cols$}")?;
+                }
+                line += 1;
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// Draw the status bar of a pane on the terminal, by adding characters to
+    /// the buffer.
+    fn draw_status_bar(&self, pane: &Pane, buf: &Buffer, buffer: &mut String) -> Result<(), Error> {
+        let (top, left, height, width) = pane.rect;
+        if height == 0 {
+            return Ok(());
+        }
+        // Left part of the status bar
+        let modified = if buf.dirty { " (modified)" } else { "" };
+        let n_buffers = self.inactive.len() + 1;
+        let position = if n_buffers > 1 {
+            format!(" [{}/{n_buffers}]", pane.buf_idx + 1)
+        } else {
+            String::new()
+        };
+        let recording = if self.recording.is_some() { " (recording)" } else { "" };
+        let mode = match self.config.keymap {
+            Keymap::Vi => format!("[{}] ", self.vi.mode.name()),
+            Keymap::Default => String::new(),
+        };
+        let left_part = format!("{mode}{:.30}{position}{modified}{recording}", buf.name());
+        let left_part = truncate_to_width(&left_part, width);
+
+        // Right part of the status bar
+        let size = format_size(buf.file_size());
+        let (x, y) = (pane.cursor.x, pane.cursor.y);
+        let row = buf.row(y, self.tab_stop_of(buf));
+        let rx = row.and_then(|row| row.cx2rx.get(x).copied()).unwrap_or(0);
+        let (syntax, indent) = (&buf.syntax.name, self.indent_of(buf).name());
+        let (encoding, line_ending) = (buf.encoding.name(), buf.line_ending.name());
+        let right_part = format!(
+            "{syntax} | {indent} | {encoding} | {line_ending} | {size} | {}:{}",
+            y + 1,
+            rx + 1
+        );
+
+        // Draw
+        start_pane_line(buffer, (top + height - 1, left), width)?;
+        let rw = width.saturating_sub(left_part.width());
+        push_colored(buffer, WBG, &format!("{left_part}{right_part:>rw$.rw$}"), self.use_color);
+        Ok(())
+    }
+
+    /// Draw the message bar on the terminal, by adding characters to the
+    /// buffer.
+    fn draw_message_bar(&self, buffer: &mut String) {
+        buffer.push_str(CLEAR_LINE_RIGHT_OF_CURSOR);
+        let msg_duration = self.config.message_dur;
+        if let Some(sm) = self.status_msg.as_ref().filter(|sm| sm.time.elapsed() < msg_duration) {
+            buffer.push_str(truncate_to_width(&sm.msg, self.term_size.1));
+        }
+    }
+
+    /// Find the bracket under the cursor and the matching bracket among the
+    /// rows shown in the current pane, so that they can be highlighted.
+    fn find_shown_brackets(&mut self) {
+        let (pos, rows) =
+            ((self.cursor.x, self.cursor.y), self.cursor.roff..self.cursor.roff + self.screen_rows);
+        let brackets = self.buf.matching_bracket(pos, rows, self.tab_stop_of(&self.buf));
+        self.matched_brackets = (brackets.into_iter().flatten())
+            .filter_map(|((x, y), len)| {
+                let row = self.buf.row(y, self.tab_stop_of(&self.buf))?;
+                Some((y, row.cx2rx[x]..row.cx2rx[x + len]))
+            })
+            .collect();
+    }
+
+    /// Move the cursor to the bracket matching the bracket under the cursor.
+    fn jump_to_matching_bracket(&mut self) {
+        let (pos, rows) = ((self.cursor.x, self.cursor.y), 0..self.buf.n_rows());
+        match self.buf.matching_bracket(pos, rows, self.tab_stop_of(&self.buf)) {
+            Some([_, (pos, _)]) => self.jump_to(pos),
+            None => set_status!(self, "No matching bracket"),
+        }
+    }
+
+    /// Move the cursor to the position given as `<line number>[:<column
+    /// number>]`, recording its current position in the jump list.
+    fn go_to(&mut self, position: &str) {
+        let mut split = position.splitn(2, ':')
+            // saturating_sub: Lines and cols are 1-indexed
+            .map(|u| u.trim().parse().map(|s: usize| s.saturating_sub(1)));
+        match (split.next().transpose(), split.next().transpose()) {
+            (Ok(Some(y)), Ok(x)) => {
+                self.buf.push_jump((self.cursor.x, self.cursor.y));
+                self.cursor.y = y.min(self.buf.n_rows());
+                if let Some(rx) = x {
+                    self.cursor.x = self.current_row().map_or(0, |r| r.rx2cx[rx]);
+                } else {
+                    self.update_cursor_x_position();
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => set_status!(self, "Parsing error: {e}"),
+            (Ok(None), _) => (),
+        }
+    }
 
-        if let Some(path) = file_name.map(sys::path) {
-            self.syntax = SyntaxConf::find(&path.to_string_lossy(), &sys::data_dirs());
-            self.load(path.as_path())?;
-            self.file_name = Some(path.to_string_lossy().to_string());
+    /// Set the mark named by `key`, from `a` to `z`, at the cursor position.
+    fn set_mark(&mut self, key: &Key) {
+        if let Key::Char(c @ b'a'..=b'z') = key {
+            self.buf.marks.insert(*c, (self.cursor.x, self.cursor.y));
+            set_status!(self, "Mark {} set", *c as char);
+        }
+    }
+
+    /// Jump to the mark named by `key`, if it is set.
+    fn jump_to_mark(&mut self, key: &Key) {
+        if let Key::Char(c @ b'a'..=b'z') = key {
+            match self.buf.marks.get(c) {
+                Some(&pos) => self.jump_to(pos),
+                None => set_status!(self, "Mark {} is not set", *c as char),
+            }
+        }
+    }
+
+    /// Move the cursor one screen up or down, recording its current position in
+    /// the jump list.
+    fn move_page(&mut self, up: bool) {
+        self.buf.push_jump((self.cursor.x, self.cursor.y));
+        self.cursor.y = if up {
+            self.cursor.roff.saturating_sub(self.screen_rows)
+        } else {
+            (self.cursor.roff + 2 * self.screen_rows - 1).min(self.buf.n_rows())
+        };
+        self.update_cursor_x_position();
+    }
+
+    /// Move the cursor to position `(x, y)`, recording its current position in
+    /// the jump list.
+    fn jump_to(&mut self, (x, y): (usize, usize)) {
+        self.buf.push_jump((self.cursor.x, self.cursor.y));
+        self.cursor.y = y.min(self.buf.n_rows());
+        self.cursor.x = x.min(self.buf.line(self.cursor.y).len());
+    }
+
+    /// Move the cursor back to the previous position of the jump list.
+    fn jump_back(&mut self) {
+        match self.buf.jump_back((self.cursor.x, self.cursor.y)) {
+            Some((x, y)) => (self.cursor.x, self.cursor.y) = (x, y),
+            None => set_status!(self, "No previous position in the jump list"),
+        }
+    }
+
+    /// Move the cursor forward to the next position of the jump list, after
+    /// jumping back.
+    fn jump_forward(&mut self) {
+        match self.buf.jump_forward() {
+            Some((x, y)) => (self.cursor.x, self.cursor.y) = (x, y),
+            None => set_status!(self, "No next position in the jump list"),
+        }
+    }
+
+    /// Return the marks set in the current buffer, as shown in the status bar,
+    /// with their line numbers.
+    fn mark_list(&self) -> String {
+        let marks = self.buf.marks.iter().map(|(c, (_, y))| format!("{}:{}", *c as char, y + 1));
+        marks.collect::<Vec<_>>().join(" ")
+    }
+
+    /// Return the position `(x, y)` of the cursor on the screen, relative to
+    /// the first line and column where the text of the current pane is
+    /// drawn.
+    fn cursor_screen_pos(&self) -> (usize, usize) {
+        let (rx, tab, width) = (self.rx(), self.tab_stop_of(&self.buf), self.screen_cols);
+        if !self.config.soft_wrap {
+            return (rx - self.cursor.coff, self.cursor.y - self.cursor.roff);
+        }
+        let n_lines = |y| self.buf.row(y, tab).map_or(1, |row| row.wrap(width).len());
+        let above: usize = (self.cursor.roff..self.cursor.y).map(n_lines).sum();
+        let starts =
+            self.buf.row(self.cursor.y, tab).map_or_else(|| vec![(0, 0)], |row| row.wrap(width));
+        let k = visual_line(&starts, rx);
+        (rx - starts[k].1, above + k)
+    }
+
+    /// Scroll the current pane vertically when long rows are wrapped, so that
+    /// the cursor can be shown. The first shown line is always the first line
+    /// of a row.
+    fn scroll_wrapped(&mut self) {
+        let (y, screen_rows) = (self.cursor.y, self.screen_rows);
+        self.cursor.coff = 0;
+        self.cursor.roff =
+            self.cursor.roff.clamp(y.saturating_sub(screen_rows.saturating_sub(1)), y);
+        while self.cursor.roff < y && self.cursor_screen_pos().1 >= screen_rows {
+            self.cursor.roff += 1;
+        }
+    }
+
+    /// Update the scroll offsets of the current pane so that the cursor is
+    /// shown.
+    fn scroll(&mut self) {
+        if self.config.soft_wrap {
+            self.scroll_wrapped();
+        } else {
+            self.cursor.scroll(self.rx(), self.screen_rows, self.screen_cols);
+        }
+    }
+
+    /// Render the rows that are shown in each pane, so that they can be drawn.
+    fn render_shown_rows(&mut self) {
+        let shown: Vec<_> = self
+            .layout
+            .panes()
+            .into_iter()
+            .enumerate()
+            .map(|(i, pane)| {
+                if i == self.pane_idx {
+                    (self.buf_idx, self.cursor.roff, self.screen_rows)
+                } else {
+                    (pane.buf_idx, pane.cursor.roff, pane.rect.2.saturating_sub(1))
+                }
+            })
+            .collect();
+        let tab = self.config.tab_stop;
+        let mut buffers: Vec<_> = self.buffers_mut().collect();
+        for (buf_idx, roff, n_rows) in shown {
+            let buf = &mut *buffers[buf_idx];
+            buf.render_rows(roff..roff + n_rows, buf.tab_stop.unwrap_or(tab));
+        }
+    }
+
+    /// Refresh the screen: update the offsets, draw the rows, the status bar,
+    /// the message bar, and move the cursor to the correct position.
+    fn refresh_screen(&mut self) -> Result<(), Error> {
+        self.scroll();
+        // Finding brackets may discard rendered rows, so it is done first
+        self.find_shown_brackets();
+        self.render_shown_rows();
+        let mut buffer = String::from(HIDE_CURSOR);
+        let (buffers, panes) = (self.buffers().collect::<Vec<_>>(), self.layout.panes());
+        let rect @ (top, left, ..) = panes[self.pane_idx].rect;
+        // The cursor state of the current pane is only saved when leaving the pane
+        let current = Pane { buf_idx: self.buf_idx, cursor: self.cursor.clone(), rect };
+        for (i, pane) in panes.into_iter().enumerate() {
+            let pane = if i == self.pane_idx { &current } else { pane };
+            self.draw_rows(pane, buffers[pane.buf_idx], i == self.pane_idx, &mut buffer)?;
+            self.draw_status_bar(pane, buffers[pane.buf_idx], &mut buffer)?;
+        }
+        write!(buffer, "\x1b[{};1H", self.term_size.0)?;
+        self.draw_message_bar(&mut buffer);
+        let (cursor_x, cursor_y) = if self.prompt_mode.is_none() {
+            // If not in prompt mode, position the cursor according to the `cursor`
+            // attributes and the position of the current pane.
+            let (x, y) = self.cursor_screen_pos();
+            (left + self.ln_pad + x + 1, top + y + 1)
+        } else {
+            // If in prompt mode, position the cursor on the prompt line at the end of the
+            // line.
+            (self.status_msg.as_ref().map_or(0, |sm| sm.msg.width() + 1), self.term_size.0)
+        };
+        // Finally, print `buffer` and move the cursor
+        print!("{buffer}\x1b[{cursor_y};{cursor_x}H{SHOW_CURSOR}");
+        io::stdout().flush().map_err(Error::from)
+    }
+
+    /// Return whether the program can exit (`key` is `EXIT`) or the current
+    /// buffer can be closed (`key` is `CLOSE_BUFFER`): either no modified
+    /// buffer would be discarded, or the key has been pressed enough times.
+    /// Otherwise, show how many more times the key must be pressed.
+    fn confirm_discard(&mut self, key: u8) -> bool {
+        let (n_dirty, times, key, action) = if key == EXIT {
+            (self.buffers().filter(|buf| buf.dirty).count(), self.quit_times, 'Q', "quit")
+        } else {
+            (usize::from(self.buf.dirty), self.close_times, 'W', "close")
+        };
+        if n_dirty == 0 || times + 1 >= self.config.quit_times {
+            return true;
+        }
+        let r = self.config.quit_times - times - 1;
+        let msg = format!("Press Ctrl+{key} {r} more time{0:.1$} to {action}.", "s", r - 1);
+        set_status!(self, "{n_dirty} modified buffer{0:.1$}. {msg}", "s", n_dirty - 1);
+        false
+    }
+
+    /// Process a key that has been pressed, when not in prompt mode. Returns
+    /// whether the program should exit, and optionally the prompt mode to
+    /// switch to.
+    fn process_keypress(&mut self, key: &Key) -> (bool, Option<PromptMode>) {
+        // This won't be mutated, unless key is Key::Character(EXIT)
+        let mut reset_quit_times = true;
+        let mut prompt_mode = None;
+        // Only consecutive typed characters are undone at once
+        if !matches!(key, Key::Char(c) if *c == b'\t' || !c.is_ascii_control()) {
+            self.buf.history.break_merge();
+        }
+        if self.process_keypress_at_cursors(key) || self.process_keypress_in_block(key) {
+            (self.quit_times, self.close_times) = (0, 0);
+            return (false, None);
+        }
+        // The selection is cleared, unless the key extends it or copies it
+        let (selection, anchor) = (self.selection(), self.anchor.take());
+        let (last_paste, cutting) = (self.last_paste.take(), mem::take(&mut self.cutting));
+
+        match key {
+            Key::ShiftArrow(_)
+            | Key::ShiftPageUp
+            | Key::ShiftPageDown
+            | Key::ShiftHome
+            | Key::ShiftEnd => {
+                let (position, block) = ((self.cursor.x, self.cursor.y), self.block);
+                if let Some(key) = key.unshifted() {
+                    self.process_keypress(&key);
+                }
+                (self.anchor, self.block) = (anchor.or(Some(position)), block);
+            }
+            Key::Arrow(arrow) => self.move_cursor(arrow, false),
+            Key::CtrlArrow(arrow) => self.move_cursor(arrow, true),
+            Key::PageUp => self.move_page(true),
+            Key::PageDown => self.move_page(false),
+            Key::Home => self.cursor.x = 0,
+            Key::End => self.cursor.x = self.current_row().map_or(0, |row| row.chars.len()),
+            Key::Char(b'\r' | b'\n') => {
+                // Enter
+                self.delete_selection(selection);
+                self.insert_new_line();
+            }
+            // Backspace or Ctrl + H
+            Key::Char(BACKSPACE | DELETE_BIS) | Key::Delete if self.delete_selection(selection) =>
+                (),
+            Key::Char(BACKSPACE | DELETE_BIS) => self.delete_char(true),
+            Key::Char(REMOVE_LINE) => self.delete_current_row(),
+            Key::Delete => self.delete_next_char(),
+            Key::Alt(SPLIT_HORIZONTALLY) => self.split_pane(false),
+            Key::Alt(SPLIT_VERTICALLY) => self.split_pane(true),
+            Key::Alt(NEXT_PANE) =>
+                self.switch_pane((self.pane_idx + 1) % self.layout.panes().len()),
+            Key::Alt(CLOSE_PANE) => self.close_pane(),
+            Key::AltArrow(arrow @ (AKey::Up | AKey::Down)) => self.add_cursor_vertically(arrow),
+            Key::AltArrow(AKey::Left) => self.jump_back(),
+            Key::AltArrow(AKey::Right) => self.jump_forward(),
+            Key::CtrlShiftArrow(arrow) => self.move_lines(arrow, selection, anchor),
+            Key::Alt(TRANSPOSE_LINES) => self.transpose_lines(),
+            Key::Alt(JOIN_LINES) => self.join_lines(),
+            Key::Alt(REFLOW) => self.reflow(selection),
+            Key::Alt(BLOCK_SELECTION) => self.toggle_block_selection(anchor),
+            Key::Alt(TRANSFORM) =>
+                (prompt_mode, self.anchor) = (Some(PromptMode::Transform(String::new())), anchor),
+            Key::Alt(c) if let Some(mode) = PromptMode::for_alt_key(*c) => prompt_mode = Some(mode),
+            Key::Alt(ADD_CURSOR_AT_NEXT_OCCURRENCE) => self.add_cursor_at_next_occurrence(),
+            Key::Alt(PASTE_PREVIOUS) => self.paste_previous(last_paste.as_ref()),
+            Key::Escape | Key::Char(REFRESH_SCREEN) | Key::Alt(_) => (),
+            Key::Char(EXIT) if self.confirm_discard(EXIT) => return (true, None),
+            Key::Char(CLOSE_BUFFER) if self.confirm_discard(CLOSE_BUFFER) => self.close_buffer(),
+            Key::Char(EXIT | CLOSE_BUFFER) => reset_quit_times = false,
+            Key::Char(OPEN) => prompt_mode = Some(PromptMode::Open(String::new())),
+            Key::Char(NEXT_BUFFER) => self.cycle_buffers(1),
+            Key::Char(PREVIOUS_BUFFER) => self.cycle_buffers(-1),
+            Key::Char(SWITCH_BUFFER) => prompt_mode = Some(PromptMode::SwitchBuffer(String::new())),
+            Key::Char(SAVE) if let Some(file_name) = self.buf.file_name.take() => {
+                self.save_and_handle_io_errors(&file_name);
+                self.buf.file_name = Some(file_name);
+            }
+            Key::Char(SAVE) => prompt_mode = Some(PromptMode::Save(String::new())),
+            Key::Char(FIND) =>
+                prompt_mode = Some(PromptMode::Find(String::new(), self.cursor.clone(), None)),
+            Key::Char(GOTO) => prompt_mode = Some(PromptMode::GoTo(String::new())),
+            Key::Char(DUPLICATE) => self.duplicate_current_row(),
+            Key::Char(CUT) => self.cut(selection, cutting),
+            Key::Char(COPY) if selection.is_some() => {
+                self.copy(selection, false);
+                self.anchor = anchor;
+            }
+            Key::Char(COPY) => self.copy(None, false),
+            Key::Char(PASTE) => {
+                self.delete_selection(selection);
+                self.paste();
+            }
+            Key::Char(TOGGLE_COMMENT) => self.toggle_comment(),
+            Key::Char(TRANSPOSE_CHARS) => self.transpose_chars(),
+            Key::Char(MATCHING_BRACKET) => self.jump_to_matching_bracket(),
+            Key::Char(UNDO) => self.undo(),
+            Key::Char(REDO) => self.redo(),
+            Key::Char(EXECUTE) => prompt_mode = Some(PromptMode::Execute(String::new())),
+            Key::Char(c) => {
+                self.delete_selection(selection);
+                self.insert_byte(*c);
+            }
+        }
+        let pressed = |k| !reset_quit_times && matches!(key, Key::Char(c) if *c == k);
+        self.quit_times = if pressed(EXIT) { self.quit_times + 1 } else { 0 };
+        self.close_times = if pressed(CLOSE_BUFFER) { self.close_times + 1 } else { 0 };
+
+        self.block &= self.anchor.is_some();
+        (false, prompt_mode)
+    }
+
+    /// Process a key with the `vi` keymap. In the insert mode, keys are
+    /// processed as with the default keymap until Escape is pressed. In the
+    /// normal and visual modes, characters, arrows, Enter, Backspace, Delete,
+    /// Home and End are read as Vi commands, control characters that would be
+    /// inserted as text (e.g. Tab) are ignored, and the other keys (e.g.
+    /// Ctrl+S) are processed as with the default keymap.
+    fn process_vi_keypress(&mut self, key: &Key) -> (bool, Option<PromptMode>) {
+        self.vi_keys.push(key.clone());
+        if self.vi.mode == vi::Mode::Insert {
+            if !matches!(key, Key::Escape) {
+                return self.process_keypress(key);
+            }
+            self.vi.mode = vi::Mode::Normal;
+            self.last_change = mem::take(&mut self.vi_keys);
+            // As in Vi, the cursor goes back onto the last inserted character
+            if self.cursor.x > 0 {
+                self.move_cursor(&AKey::Left, false);
+            }
+            return (false, None);
+        }
+        #[expect(clippy::wildcard_enum_match_arm)]
+        let c = match key {
+            Key::Char(c) if !c.is_ascii_control() || *c == REMOVE_LINE => *c,
+            Key::Char(b'\r' | b'\n') | Key::Arrow(AKey::Down) => b'j',
+            Key::Char(BACKSPACE | DELETE_BIS) | Key::Arrow(AKey::Left) => b'h',
+            Key::Delete => b'x',
+            Key::Arrow(AKey::Up) => b'k',
+            Key::Arrow(AKey::Right) => b'l',
+            Key::Home => b'0',
+            Key::End => b'$',
+            Key::Char(c) if !is_command(*c) => {
+                self.vi.cancel();
+                self.vi_keys.clear();
+                return (false, None);
+            }
+            _ => {
+                self.vi.cancel();
+                self.vi_keys.clear();
+                if matches!(key, Key::Escape) {
+                    self.anchor = None;
+                }
+                let result = self.process_keypress(key);
+                if self.anchor.is_none() {
+                    self.vi.mode = vi::Mode::Normal;
+                }
+                return result;
+            }
+        };
+        let Some(command) = self.vi.parse(c) else {
+            if !self.vi.is_pending() && self.vi.mode == vi::Mode::Normal {
+                self.vi_keys.clear();
+            }
+            return (false, None);
+        };
+        let result = self.run_vi_command(command);
+        if self.vi.mode == vi::Mode::Visual && self.anchor.is_none() {
+            self.vi.mode = vi::Mode::Normal;
+        }
+        match self.vi.mode {
+            vi::Mode::Normal if command.is_change() =>
+                self.last_change = mem::take(&mut self.vi_keys),
+            vi::Mode::Normal => self.vi_keys.clear(),
+            vi::Mode::Insert | vi::Mode::Visual => (),
+        }
+        result
+    }
+
+    /// Run a command of the normal or the visual mode of the `vi` keymap.
+    fn run_vi_command(&mut self, command: vi::Command) -> (bool, Option<PromptMode>) {
+        match command {
+            vi::Command::Move(motion, n) => (0..n).for_each(|_| self.vi_move(motion)),
+            vi::Command::Operate(op, motion, n) => self.vi_operate(op, motion, n),
+            vi::Command::OperateRows(op, n) =>
+                self.vi_operate_rows(op, self.cursor.y, self.cursor.y.saturating_add(n - 1)),
+            vi::Command::OperateSelection(op) => {
+                let ((x, y), end) = self.vi_selection();
+                (self.cursor.x, self.cursor.y, self.anchor) = (x, y, None);
+                self.vi_operate_to(op, end);
+            }
+            vi::Command::Paste(n) => {
+                let checkpoint = self.buf.history.checkpoint();
+                self.read_clipboard();
+                // Text that is not made of whole rows is pasted after the cursor
+                if self.kill_ring.get(0).is_some_and(|clip| !clip.rows && !clip.block) {
+                    self.vi_move(Motion::Right);
+                }
+                (0..n).for_each(|_| self.paste_clip(0));
+                self.buf.history.merge_since(checkpoint);
+            }
+            vi::Command::Insert(insertion) => self.vi_insert(insertion),
+            vi::Command::Visual if self.vi.mode == vi::Mode::Visual => self.anchor = None,
+            vi::Command::Visual =>
+                (self.vi.mode, self.anchor) =
+                    (vi::Mode::Visual, Some((self.cursor.x, self.cursor.y))),
+            vi::Command::Undo(n) => (0..n).for_each(|_| self.undo()),
+            vi::Command::Redo(n) => (0..n).for_each(|_| self.redo()),
+            vi::Command::Repeat(n) => {
+                self.vi_keys.clear();
+                let keys = self.last_change.clone();
+                for key in keys.iter().cycle().take(keys.len().saturating_mul(n)) {
+                    self.process_vi_keypress(key);
+                }
+            }
+            vi::Command::Prompt => return (false, Some(PromptMode::ViCommand(String::new()))),
+            vi::Command::Find => return self.process_keypress(&Key::Char(FIND)),
+        }
+        (false, None)
+    }
+
+    /// Move the cursor once for a motion of the `vi` keymap. Unlike with the
+    /// arrow keys, the cursor stays on its row when moving left or right.
+    fn vi_move(&mut self, motion: Motion) {
+        let (x, y, n_rows) = (self.cursor.x, self.cursor.y, self.buf.n_rows());
+        let row_len = self.current_row().map_or(0, |row| row.chars.len());
+        match motion {
+            Motion::Left if x > 0 => self.move_cursor(&AKey::Left, false),
+            Motion::Right if x < row_len => self.move_cursor(&AKey::Right, false),
+            Motion::Up => self.move_cursor(&AKey::Up, false),
+            Motion::Down if y + 1 < n_rows => self.move_cursor(&AKey::Down, false),
+            Motion::Left | Motion::Right | Motion::Down => (),
+            Motion::StartOfRow => self.cursor.x = 0,
+            Motion::EndOfRow => self.cursor.x = row_len,
+            Motion::NextWord => (self.cursor.x, self.cursor.y) = self.next_word_start(),
+            Motion::PreviousWord => (self.cursor.x, self.cursor.y) = self.previous_word_start(),
+            Motion::EndOfWord => (self.cursor.x, self.cursor.y) = self.word_end(),
+            Motion::Row(row) => {
+                self.buf.push_jump((x, y));
+                let row = row.unwrap_or(n_rows).clamp(1, n_rows.max(1));
+                self.cursor.y = row - 1;
+                self.cursor.x = self.buf.line(row - 1).iter().take_while(|b| is_blank(**b)).count();
+            }
+        }
+    }
+
+    /// Return the position of the start of the next word (`w`), which may be
+    /// on a next row. Empty rows are words.
+    fn next_word_start(&self) -> (usize, usize) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if let Some(x) = vi::next_word_start(&self.buf.line(y), x) {
+            return (x, y);
+        }
+        let mut rows = (y + 1..).zip(self.buf.text.lines(y + 1));
+        let start = rows.find_map(|(y, row)| {
+            let x = row.iter().position(|b| !is_blank(*b));
+            x.or_else(|| row.is_empty().then_some(0)).map(|x| (x, y))
+        });
+        start.unwrap_or_else(|| self.end_of_text())
+    }
+
+    /// Return the position of the start of the previous word (`b`), which may
+    /// be on a previous row. Empty rows are words.
+    fn previous_word_start(&self) -> (usize, usize) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if let Some(x) = vi::previous_word_start(&self.buf.line(y), x) {
+            return (x, y);
+        }
+        let start = (0..y.min(self.buf.n_rows())).rev().find_map(|y| {
+            let row = self.buf.line(y);
+            let x = vi::previous_word_start(&row, row.len());
+            x.or_else(|| row.is_empty().then_some(0)).map(|x| (x, y))
+        });
+        start.unwrap_or((0, 0))
+    }
+
+    /// Return the position of the last character of the next word (`e`),
+    /// which may be on a next row.
+    fn word_end(&self) -> (usize, usize) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        if let Some(x) = vi::word_end(&self.buf.line(y), x + 1) {
+            return (x, y);
+        }
+        let mut rows = (y + 1..).zip(self.buf.text.lines(y + 1));
+        let end = rows.find_map(|(y, row)| vi::word_end(&row, 0).map(|x| (x, y)));
+        end.unwrap_or_else(|| self.end_of_text())
+    }
+
+    /// Return the position of the end of the last row.
+    fn end_of_text(&self) -> (usize, usize) {
+        let y = self.buf.n_rows().saturating_sub(1);
+        (self.buf.line(y).len(), y)
+    }
+
+    /// Apply an operator of the `vi` keymap to the text covered by moving the
+    /// cursor `n` times. With a motion to another row (e.g. `j` or `G`), the
+    /// operator applies to whole rows.
+    fn vi_operate(&mut self, op: Operator, motion: Motion, n: usize) {
+        let (x, y) = (self.cursor.x, self.cursor.y);
+        // As in Vi, `cw` changes the text up to the end of the word
+        let motion = match (op, motion) {
+            (Operator::Change, Motion::NextWord) => Motion::EndOfWord,
+            (_, motion) => motion,
+        };
+        (0..n).for_each(|_| self.vi_move(motion));
+        let mut end = (self.cursor.x, self.cursor.y);
+        if motion.is_linewise() {
+            (self.cursor.x, self.cursor.y) = (x, y);
+            return self.vi_operate_rows(op, y.min(end.1), y.max(end.1));
+        }
+        #[expect(clippy::wildcard_enum_match_arm)]
+        match motion {
+            // The last character of the word is included
+            Motion::EndOfWord if end != (x, y) => {
+                self.vi_move(Motion::Right);
+                end = (self.cursor.x, self.cursor.y);
+            }
+            // `dw` on the last word of a row stops at the end of the row
+            Motion::NextWord if end.1 > y => end = (self.buf.line(end.1 - 1).len(), end.1 - 1),
+            _ => (),
+        }
+        if (end.1, end.0) < (y, x) {
+            (self.cursor.x, self.cursor.y) = end;
+            end = (x, y);
+        } else {
+            (self.cursor.x, self.cursor.y) = (x, y);
+        }
+        self.vi_operate_to(op, end);
+    }
+
+    /// Apply an operator of the `vi` keymap to the text between the cursor and
+    /// `end`: the text is copied, and deleted unless the operator is `y`. If
+    /// there is no such text, nothing is copied or deleted.
+    fn vi_operate_to(&mut self, op: Operator, end: (usize, usize)) {
+        let start = (self.cursor.x, self.cursor.y);
+        if start != end {
+            self.copy(Some((start, end)), false);
+            if op != Operator::Yank {
+                self.delete_selection(Some((start, end)));
+            }
+        }
+        if op == Operator::Change {
+            self.vi.mode = vi::Mode::Insert;
+        }
+    }
+
+    /// Apply an operator of the `vi` keymap to the rows from `y0` to `y1`:
+    /// the rows are copied, and deleted with `d`. With `c`, their text is
+    /// replaced with the indentation of the first row.
+    fn vi_operate_rows(&mut self, op: Operator, y0: usize, y1: usize) {
+        let n_rows = self.buf.n_rows();
+        if y0 >= n_rows {
+            return;
+        }
+        let y1 = y1.min(n_rows - 1);
+        let text = self.buf.text_between((0, y0), (self.buf.line(y1).len(), y1));
+        self.push_clip(Clip { text, rows: true, block: false }, false);
+        self.cursor.y = y0;
+        match op {
+            Operator::Yank => (),
+            Operator::Delete => {
+                let checkpoint = self.buf.history.checkpoint();
+                (y0..=y1).for_each(|_| self.delete_current_row());
+                self.buf.history.merge_since(checkpoint);
+            }
+            Operator::Change => {
+                let indent = self.buf.line(y0).iter().take_while(|b| is_blank(**b)).count();
+                self.delete_text((indent, y0), (self.buf.line(y1).len(), y1));
+                (self.cursor.x, self.vi.mode) = (indent, vi::Mode::Insert);
+            }
+        }
+    }
+
+    /// Return the start and the end of the selection of the visual mode of the
+    /// `vi` keymap. Unlike other selections, it includes the character under
+    /// the cursor or under the anchor, whichever comes last.
+    fn vi_selection(&self) -> ((usize, usize), (usize, usize)) {
+        let (cursor, anchor) = ((self.cursor.x, self.cursor.y), self.anchor.unwrap_or_default());
+        let (start, (x, y)) = if (anchor.1, anchor.0) < (cursor.1, cursor.0) {
+            (anchor, cursor)
         } else {
-            self.rows.push(Row::new(Vec::new()));
-            self.file_name = None;
+            (cursor, anchor)
+        };
+        let row = self.buf.row(y, self.tab_stop_of(&self.buf));
+        let end = match row.as_deref() {
+            Some(row) if x < row.chars.len() => (x + row.get_char_size(row.cx2rx[x]), y),
+            Some(_) if y + 1 < self.buf.n_rows() => (0, y + 1),
+            _ => (x, y),
+        };
+        (start, end)
+    }
+
+    /// Switch to the insert mode of the `vi` keymap, moving the cursor or
+    /// adding a row first, depending on `insertion`.
+    fn vi_insert(&mut self, insertion: Insertion) {
+        match insertion {
+            Insertion::Before => (),
+            Insertion::After => self.vi_move(Motion::Right),
+            Insertion::RowStart =>
+                self.cursor.x =
+                    self.buf.line(self.cursor.y).iter().take_while(|b| is_blank(**b)).count(),
+            Insertion::RowEnd => self.vi_move(Motion::EndOfRow),
+            Insertion::RowBelow => {
+                self.vi_move(Motion::EndOfRow);
+                self.insert_new_line();
+            }
+            Insertion::RowAbove if self.cursor.y < self.buf.n_rows() => {
+                let line = self.buf.line(self.cursor.y).into_owned();
+                let indent = &line[..line.iter().take_while(|b| is_blank(**b)).count()];
+                self.insert_text((0, self.cursor.y), &[indent, b"\n"].concat(), false);
+                self.cursor.x = indent.len();
+            }
+            Insertion::RowAbove => self.insert_new_line(),
         }
+        self.vi.mode = vi::Mode::Insert;
+    }
+
+    /// Run a command entered after `:` with the `vi` keymap: `w [file]`, `q`,
+    /// `q!`, `wq` or `x`, `e <file>`, a position `<line>[:<column>]` or
+    /// `/<text>`, using the corresponding prompts when needed. Return the
+    /// prompt mode to switch to, if any.
+    fn run_vi_command_line(&mut self, command: &str) -> Option<PromptMode> {
+        if let Some(query) = command.strip_prefix('/') {
+            let (x, y) = (self.cursor.x, self.cursor.y);
+            if self.find(query, None, true).is_some() {
+                self.buf.push_jump((x, y));
+            } else {
+                set_status!(self, "Not found: {query}");
+            }
+            self.buf.match_segment = None;
+            return None;
+        }
+        let (name, arg) = command.split_once(' ').map_or((command, ""), |(n, a)| (n, a.trim()));
+        match (name, arg) {
+            ("", _) => (),
+            ("w", "") => return self.process_keypress(&Key::Char(SAVE)).1,
+            ("w", file_name) => self.save_as(String::from(file_name)),
+            ("q", "") => self.macro_keys.push_front(Key::Char(EXIT)),
+            ("q!", "") => {
+                // Quit without asking for confirmation
+                self.quit_times = self.config.quit_times;
+                self.macro_keys.push_front(Key::Char(EXIT));
+            }
+            ("wq" | "x", "") if self.buf.file_name.is_none() =>
+                return Some(PromptMode::Save(String::new())),
+            ("wq" | "x", "") => {
+                self.process_keypress(&Key::Char(SAVE));
+                if !self.buf.dirty {
+                    self.macro_keys.push_front(Key::Char(EXIT));
+                }
+            }
+            ("e", file_name) if !file_name.is_empty() => self.open(file_name),
+            (position, "") if position.starts_with(|c: char| c.is_ascii_digit()) =>
+                self.go_to(position),
+            _ => set_status!(self, "Not an editor command: {command}"),
+        }
+        None
+    }
+
+    /// Try to find a query, this is called after pressing Ctrl-F and for each
+    /// key that is pressed. `last_match` is the last row that was matched,
+    /// `forward` indicates whether to search forward or backward. Returns
+    /// the row of a new match, or `None` if the search was unsuccessful.
+    fn find(&mut self, query: &str, last_match: Option<usize>, forward: bool) -> Option<usize> {
+        // Number of rows to search
+        let num_rows = if query.is_empty() { 0 } else { self.buf.n_rows() };
+        let lines: Vec<_> = self.buf.text.lines(0).take(num_rows).collect();
+        let mut current = last_match.unwrap_or_else(|| num_rows.saturating_sub(1));
+        // TODO: Handle multiple matches per line
+        for _ in 0..num_rows {
+            current = (current + if forward { 1 } else { num_rows - 1 }) % num_rows;
+            let chars = &lines[current];
+            if let Some(cx) = chars.windows(query.len()).position(|w| w == query.as_bytes()) {
+                // self.cursor.coff: Try to reset the column offset; if the match is after the
+                // offset, this will be updated in self.cursor.scroll() so that
+                // the result is visible
+                (self.cursor.x, self.cursor.y, self.cursor.coff) = (cx, current, 0);
+                let rx = self
+                    .buf
+                    .row(current, self.tab_stop_of(&self.buf))
+                    .map_or(0, |row| row.cx2rx[cx]);
+                self.buf.match_segment = Some((current, rx..rx + query.len()));
+                return Some(current);
+            }
+        }
+        None
+    }
+
+    /// If `file_name` is not None, load the file. Then run the text editor.
+    ///
+    /// # Errors
+    ///
+    /// Will Return `Err` if any error occur.
+    pub fn run<I: BufRead>(&mut self, file_name: Option<&str>, input: &mut I) -> Result<(), Error> {
+        self.update_window_size()?;
+        set_status!(self, "{HELP_MESSAGE}");
+
+        self.load_buffer(file_name)?;
         loop {
-            if let Some(mode) = &self.prompt_mode {
-                set_status!(self, "{}", mode.status_msg());
-            }
-            self.refresh_screen()?;
-            let key = self.loop_until_keypress(input)?;
-            // TODO: Can we avoid using take()?
-            self.prompt_mode = match self.prompt_mode.take() {
-                // process_keypress returns (should_quit, prompt_mode)
-                None => match self.process_keypress(&key) {
-                    (true, _) => return Ok(()),
-                    (false, prompt_mode) => prompt_mode,
-                },
-                Some(prompt_mode) => prompt_mode.process_keypress(self, &key),
+            let key = if let Some(key) = self.next_macro_key() {
+                key
+            } else {
+                if let Some(mode) = &self.prompt_mode {
+                    set_status!(self, "{}", mode.status_msg(self));
+                }
+                self.refresh_screen()?;
+                self.loop_until_keypress(input)?
+            };
+            if self.process_key(&key) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Return the next key of the macro being played, if any. The screen is
+    /// scrolled first, as it is before reading a key from the terminal, so that
+    /// the key is processed as if it was typed.
+    fn next_macro_key(&mut self) -> Option<Key> {
+        let key = self.macro_keys.pop_front()?;
+        self.scroll();
+        Some(key)
+    }
+
+    /// Process a key in the current prompt mode, or in regular edition mode if
+    /// there is no prompt, and record it if a macro is being recorded. Return
+    /// whether the editor should quit.
+    fn process_key(&mut self, key: &Key) -> bool {
+        let prompt = self.prompt_mode.is_some();
+        match (&mut self.recording, key) {
+            (Some(_), Key::Alt(RECORD_MACRO)) if !prompt => {
+                self.last_macro = self.recording.take().unwrap_or_default();
+                set_status!(self, "Macro recorded ({} keys)", self.last_macro.len());
+                return false;
+            }
+            (None, Key::Alt(RECORD_MACRO)) if !prompt => {
+                self.recording = Some(Vec::new());
+                set_status!(self, "Recording macro... (M-r to stop)");
+                return false;
+            }
+            (Some(_), Key::Alt(PLAY_MACRO)) if !prompt => {
+                set_status!(self, "A macro cannot be played while it is recorded");
+                return false;
+            }
+            (Some(keys), _) => keys.push(key.clone()),
+            (None, _) => (),
+        }
+        // TODO: Can we avoid using take()?
+        self.prompt_mode = match self.prompt_mode.take() {
+            // process_keypress returns (should_quit, prompt_mode)
+            None if self.config.keymap == Keymap::Vi => match self.process_vi_keypress(key) {
+                (true, _) => return true,
+                (false, prompt_mode) => prompt_mode,
+            },
+            None => match self.process_keypress(key) {
+                (true, _) => return true,
+                (false, prompt_mode) => prompt_mode,
+            },
+            Some(prompt_mode) => prompt_mode.process_keypress(self, key),
+        };
+        false
+    }
+
+    /// Play a macro, given as `[name] [count]`: the macro saved with this name,
+    /// or the last recorded macro if there is no name, is played `count` times
+    /// (once by default). Its keys are processed before the next keys read.
+    fn play_macro(&mut self, args: &str) {
+        let mut args = args.split_whitespace().peekable();
+        let name = args.next_if(|arg| arg.parse::<usize>().is_err());
+        let keys = match name {
+            None => Ok(self.last_macro.clone()),
+            Some(name) => macros::load(&sys::data_dirs()).get(name).map_or_else(
+                || Err(format!("No such macro: {name}")),
+                |keys| Key::parse_keys(keys),
+            ),
+        };
+        let count = args.next().map_or(Ok(1), str::parse::<usize>).map_err(|e| e.to_string());
+        match (keys, count) {
+            (Ok(keys), Ok(count)) => {
+                // Recording and playing macros in a macro could loop forever
+                let keys =
+                    keys.iter().filter(|k| !matches!(k, Key::Alt(RECORD_MACRO | PLAY_MACRO)));
+                for _ in 0..count {
+                    self.macro_keys.extend(keys.clone().cloned());
+                }
             }
+            (Err(e), _) | (_, Err(e)) => set_status!(self, "{e}"),
+        }
+    }
+
+    /// Save the last recorded macro with the given name in the data directory.
+    fn save_macro(&mut self, name: &str) {
+        let keys: String = self.last_macro.iter().map(ToString::to_string).collect();
+        if name.is_empty() || name.contains(['=', ' ']) || name.parse::<usize>().is_ok() {
+            set_status!(self, "Invalid macro name: {name}");
+        } else if let Err(e) = macros::save(&sys::data_dirs(), name, &keys) {
+            set_status!(self, "Could not save macro: {e}");
+        } else {
+            set_status!(self, "Macro saved as {name}");
         }
     }
 }
@@ -774,18 +2999,74 @@ enum PromptMode {
     GoTo(String),
     /// Execute(prompt buffer)
     Execute(String),
+    /// Open(prompt buffer)
+    Open(String),
+    /// SwitchBuffer(prompt buffer)
+    SwitchBuffer(String),
+    /// Set a mark; waiting for the key naming it
+    SetMark,
+    /// Jump to a mark; waiting for the key naming it
+    JumpToMark,
+    /// KillRing(prompt buffer)
+    KillRing(String),
+    /// Transform(prompt buffer)
+    Transform(String),
+    /// LineEnding(prompt buffer)
+    LineEnding(String),
+    /// Encoding(prompt buffer)
+    Encoding(String),
+    /// Indent(prompt buffer)
+    Indent(String),
+    /// PlayMacro(prompt buffer)
+    PlayMacro(String),
+    /// SaveMacro(prompt buffer)
+    SaveMacro(String),
+    /// ViCommand(prompt buffer), a command entered after `:` with the `vi`
+    /// keymap
+    ViCommand(String),
 }
 
 // TODO: Use trait with mode_status_msg and process_keypress, implement the
 // trait for separate  structs for Save and Find?
 impl PromptMode {
+    /// Return the prompt mode to switch to when Alt and `c` are pressed, if
+    /// any.
+    const fn for_alt_key(c: u8) -> Option<Self> {
+        match c {
+            SET_MARK => Some(Self::SetMark),
+            JUMP_TO_MARK => Some(Self::JumpToMark),
+            KILL_RING => Some(Self::KillRing(String::new())),
+            LINE_ENDING => Some(Self::LineEnding(String::new())),
+            ENCODING => Some(Self::Encoding(String::new())),
+            CONVERT_INDENT => Some(Self::Indent(String::new())),
+            PLAY_MACRO => Some(Self::PlayMacro(String::new())),
+            SAVE_MACRO => Some(Self::SaveMacro(String::new())),
+            _ => None,
+        }
+    }
+
     /// Return the status message to print for the selected `PromptMode`.
-    fn status_msg(&self) -> String {
+    fn status_msg(&self, ed: &Editor) -> String {
         match self {
             Self::Save(buffer) => format!("Save as: {buffer}"),
             Self::Find(buffer, ..) => format!("Search (Use ESC/Arrows/Enter): {buffer}"),
             Self::GoTo(buffer) => format!("Enter line number[:column number]: {buffer}"),
             Self::Execute(buffer) => format!("Command to execute: {buffer}"),
+            Self::Open(buffer) => format!("Open file: {buffer}"),
+            Self::SwitchBuffer(buffer) =>
+                format!("{} | Switch to buffer (number or name): {buffer}", ed.buffer_list()),
+            Self::SetMark => format!("{} | Set mark (a-z): ", ed.mark_list()),
+            Self::JumpToMark => format!("{} | Jump to mark (a-z): ", ed.mark_list()),
+            Self::KillRing(buffer) => format!("{} | Paste (number): {buffer}", ed.kill_ring_list()),
+            Self::Transform(buffer) => format!("Transformation: {buffer}"),
+            Self::LineEnding(buffer) => format!("Convert line endings to (LF/CRLF/CR): {buffer}"),
+            Self::Encoding(buffer) =>
+                format!("Save with encoding (e.g. UTF-8, UTF-16LE BOM, Latin-1): {buffer}"),
+            Self::Indent(buffer) =>
+                format!("Convert indentation to (tabs/spaces [width]): {buffer}"),
+            Self::PlayMacro(buffer) => format!("Play macro ([name] [count]): {buffer}"),
+            Self::SaveMacro(buffer) => format!("Save macro as: {buffer}"),
+            Self::ViCommand(buffer) => format!(":{buffer}"),
         }
     }
 
@@ -798,10 +3079,10 @@ impl PromptMode {
                 PromptState::Cancelled => set_status!(ed, "Save aborted"),
                 PromptState::Completed(file_name) => ed.save_as(file_name),
             },
+            Self::Find(query, ..) if matches!(key, Key::Alt(ADD_CURSORS_AT_MATCHES)) =>
+                ed.add_cursors_at_matches(query.as_bytes()),
             Self::Find(b, saved_cursor, last_match) => {
-                if let Some(row_idx) = last_match {
-                    ed.rows[row_idx].match_segment = None;
-                }
+                ed.buf.match_segment = None;
                 match process_prompt_keypress(b, key) {
                     PromptState::Active(query) => {
                         #[expect(clippy::wildcard_enum_match_arm)]
@@ -816,46 +3097,76 @@ impl PromptMode {
                     }
                     // The prompt was cancelled. Restore the previous position.
                     PromptState::Cancelled => ed.cursor = saved_cursor,
-                    // Cursor has already been moved, do nothing
-                    PromptState::Completed(_) => (),
+                    // The cursor has already been moved. Record where it was
+                    // in the jump list.
+                    PromptState::Completed(_) => ed.buf.push_jump((saved_cursor.x, saved_cursor.y)),
                 }
             }
             Self::GoTo(b) => match process_prompt_keypress(b, key) {
                 PromptState::Active(b) => return Some(Self::GoTo(b)),
                 PromptState::Cancelled => (),
-                PromptState::Completed(b) => {
-                    let mut split = b.splitn(2, ':')
-                        // saturating_sub: Lines and cols are 1-indexed
-                        .map(|u| u.trim().parse().map(|s: usize| s.saturating_sub(1)));
-                    match (split.next().transpose(), split.next().transpose()) {
-                        (Ok(Some(y)), Ok(x)) => {
-                            ed.cursor.y = y.min(ed.rows.len());
-                            if let Some(rx) = x {
-                                ed.cursor.x = ed.current_row().map_or(0, |r| r.rx2cx[rx]);
-                            } else {
-                                ed.update_cursor_x_position();
-                            }
-                        }
-                        (Err(e), _) | (_, Err(e)) => set_status!(ed, "Parsing error: {e}"),
-                        (Ok(None), _) => (),
-                    }
-                }
+                PromptState::Completed(b) => ed.go_to(&b),
+            },
+            Self::ViCommand(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::ViCommand(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => return ed.run_vi_command_line(b.trim()),
+            },
+            Self::Execute(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::Execute(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.execute(&b),
+            },
+            Self::Open(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::Open(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) if b.is_empty() => (),
+                PromptState::Completed(file_name) => ed.open(&file_name),
+            },
+            Self::SwitchBuffer(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::SwitchBuffer(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.switch_to_buffer_named(&b),
+            },
+            Self::SetMark => ed.set_mark(key),
+            Self::JumpToMark => ed.jump_to_mark(key),
+            Self::KillRing(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::KillRing(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => match b.trim().parse::<usize>() {
+                    Ok(i @ 1..) if i <= ed.kill_ring.len() => ed.paste_clip(i - 1),
+                    _ => set_status!(ed, "No such clip: {b}"),
+                },
+            },
+            Self::Transform(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::Transform(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.transform(&b),
+            },
+            Self::LineEnding(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::LineEnding(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.set_line_ending(&b),
+            },
+            Self::Encoding(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::Encoding(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.set_encoding(&b),
+            },
+            Self::Indent(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::Indent(b)),
+                PromptState::Cancelled => (),
+                PromptState::Completed(b) => ed.convert_indent(&b),
             },
-            Self::Execute(b) => match process_prompt_keypress(b, key) {
-                PromptState::Active(b) => return Some(Self::Execute(b)),
+            Self::PlayMacro(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::PlayMacro(b)),
                 PromptState::Cancelled => (),
-                PromptState::Completed(b) => {
-                    let mut args = b.split_whitespace();
-                    match Command::new(args.next().unwrap_or_default()).args(args).output() {
-                        Ok(out) if !out.status.success() =>
-                            set_status!(ed, "{}", String::from_utf8_lossy(&out.stderr).trim_end()),
-                        Ok(out) => out.stdout.into_iter().for_each(|c| match c {
-                            b'\n' => ed.insert_new_line(),
-                            c => ed.insert_byte(c),
-                        }),
-                        Err(e) => set_status!(ed, "{e}"),
-                    }
-                }
+                PromptState::Completed(b) => ed.play_macro(&b),
+            },
+            Self::SaveMacro(b) => match process_prompt_keypress(b, key) {
+                PromptState::Active(b) => return Some(Self::SaveMacro(b)),
+                PromptState::Cancelled => set_status!(ed, "Save aborted"),
+                PromptState::Completed(b) => ed.save_macro(b.trim()),
             },
         }
         None
@@ -891,40 +3202,42 @@ mod tests {
     use std::io::Cursor;
 
     use rstest::rstest;
+    use tempfile::TempDir;
 
     use super::*;
-    use crate::syntax::HlType;
 
     fn assert_row_chars_equal(editor: &Editor, expected: &[&[u8]]) {
         assert_eq!(
-            editor.rows.len(),
+            editor.buf.n_rows(),
             expected.len(),
             "editor has {} rows, expected {}",
-            editor.rows.len(),
+            editor.buf.n_rows(),
             expected.len()
         );
-        for (i, (row, expected)) in editor.rows.iter().zip(expected).enumerate() {
+        for (i, (chars, expected)) in editor.buf.text.lines(0).zip(expected).enumerate() {
             assert_eq!(
-                row.chars,
+                chars,
                 *expected,
                 "comparing characters for row {}\n  left: {}\n  right: {}",
                 i,
-                String::from_utf8_lossy(&row.chars),
+                String::from_utf8_lossy(&chars),
                 String::from_utf8_lossy(expected)
             );
         }
     }
 
-    fn assert_row_synthax_highlighting_types_equal(editor: &Editor, expected: &[&[HlType]]) {
+    fn assert_row_synthax_highlighting_types_equal(editor: &mut Editor, expected: &[&[HlType]]) {
         assert_eq!(
-            editor.rows.len(),
+            editor.buf.n_rows(),
             expected.len(),
             "editor has {} rows, expected {}",
-            editor.rows.len(),
+            editor.buf.n_rows(),
             expected.len()
         );
-        for (i, (row, expected)) in editor.rows.iter().zip(expected).enumerate() {
-            assert_eq!(row.hl, *expected, "comparing HlTypes for row {i}");
+        editor.buf.render_rows(0..expected.len(), editor.config.tab_stop);
+        for (i, expected) in expected.iter().enumerate() {
+            let hl = editor.buf.rendered_row(i).map(|row| &row.hl[..]);
+            assert_eq!(hl, Some(*expected), "comparing HlTypes for row {i}");
         }
     }
 
@@ -951,6 +3264,16 @@ mod tests {
         assert_eq!(format_size(input), expected_output);
     }
 
+    #[rstest]
+    #[case("abc", 5, "abc")]
+    #[case("abcdef", 3, "abc")]
+    #[case("a↵b…c", 4, "a↵b…")]
+    #[case("日本語", 5, "日本")]
+    #[case("日本語", 0, "")]
+    fn truncate_to_width_output(#[case] s: &str, #[case] width: usize, #[case] expected: &str) {
+        assert_eq!(truncate_to_width(s, width), expected);
+    }
+
     #[test]
     fn editor_insert_byte() {
         let mut editor = Editor::default();
@@ -961,9 +3284,9 @@ mod tests {
         editor.insert_byte(b'Z');
 
         assert_eq!(editor.cursor.x, editor_cursor_x_before + 3);
-        assert_eq!(editor.rows.len(), 1);
-        assert_eq!(editor.n_bytes, 3);
-        assert_eq!(editor.rows[0].chars, [b'X', b'Y', b'Z']);
+        assert_eq!(editor.buf.n_rows(), 1);
+        assert_eq!(editor.buf.n_bytes(), 3);
+        assert_eq!(editor.buf.line(0).to_vec(), [b'X', b'Y', b'Z']);
     }
 
     #[test]
@@ -976,12 +3299,115 @@ mod tests {
         }
 
         assert_eq!(editor.cursor.y, editor_cursor_y_before + 3);
-        assert_eq!(editor.rows.len(), 3);
-        assert_eq!(editor.n_bytes, 0);
+        assert_eq!(editor.buf.n_rows(), 3);
+        assert_eq!(editor.buf.n_bytes(), 0);
+
+        for chars in editor.buf.text.lines(0) {
+            assert_eq!(chars, []);
+        }
+    }
+
+    #[test]
+    fn editor_auto_indent() {
+        let syntax =
+            SyntaxConf { indent_after: vec!['{', ':'], dedent_on: vec!['}'], ..Default::default() };
+        let (buf, config) = (Buffer { syntax, ..Default::default() }, Config {
+            auto_close: false,
+            ..Default::default()
+        });
+        let mut editor = Editor { buf, config, ..Default::default() };
+        for &b in b"fn f() {\nif x {\n}\n}\n\ta:\nb {}" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        editor.process_keypress(&Key::Arrow(AKey::Left));
+        editor.process_keypress(&Key::Char(b'\r'));
+        assert_row_chars_equal(&editor, &[
+            b"fn f() {",
+            b"\tif x {",
+            b"\t}",
+            b"}",
+            b"\ta:",
+            b"\t\tb {",
+            b"\t\t\t",
+            b"\t\t}",
+        ]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (3, 6));
+    }
+
+    #[test]
+    fn editor_tabs_and_spaces() {
+        let syntax =
+            SyntaxConf { indent_after: vec!['{'], expand_tab: Some(true), ..Default::default() };
+        let config = Config { expand_tab: false, auto_close: false, ..Default::default() };
+        let buf = Buffer { syntax, ..Default::default() };
+        let mut editor = Editor { buf, config, ..Default::default() };
+        let four = NonZeroUsize::new(4).expect("4 is not 0");
+        assert_eq!(editor.indent_of(&editor.buf), Indent::Spaces(four));
+        for &b in b"a {\n\tb\tc" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        assert_row_chars_equal(&editor, &[b"a {", b"        b   c"]);
+        editor.process_keypress(&Key::Home);
+        editor.process_keypress(&Key::Char(b'\t'));
+        editor.process_keypress(&Key::Char(b'\t'));
+        editor.process_keypress(&Key::Char(BACKSPACE));
+        assert_row_chars_equal(&editor, &[b"a {", b"            b   c"]);
+        assert_eq!(editor.cursor.x, 4);
+        // Delete in leading spaces only deletes the space under the cursor
+        editor.cursor.x = 2;
+        editor.process_keypress(&Key::Delete);
+        assert_row_chars_equal(&editor, &[b"a {", b"           b   c"]);
+        assert_eq!(editor.cursor.x, 2);
+        editor.process_keypress(&Key::Char(UNDO));
+        editor.cursor.x = 4;
+
+        let (_, prompt_mode) = editor.process_keypress(&Key::Alt(CONVERT_INDENT));
+        assert_eq!(prompt_mode, Some(PromptMode::Indent(String::new())));
+        editor.convert_indent("tabs");
+        assert_row_chars_equal(&editor, &[b"a {", b"\t\t\tb   c"]);
+        assert_eq!((editor.cursor.x, editor.buf.indent), (0, Some(Indent::Tabs)));
+        editor.process_keypress(&Key::Char(b'\t'));
+        assert_row_chars_equal(&editor, &[b"a {", b"\t\t\t\tb   c"]);
+        editor.convert_indent("spaces 2");
+        assert_row_chars_equal(&editor, &[b"a {", b"        b   c"]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"a {", b"\t\t\t\tb   c"]);
+        editor.convert_indent("tabs 2");
+        assert!(editor.status_msg.take().is_some_and(|msg| msg.msg.starts_with("Expected tabs")));
+
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("file.txt");
+        fs::write(&path, "a {\n  b {\n    c\n  }\n}\n").expect("Could not write file");
+        editor.load(&path).expect("Could not load file");
+        assert_eq!(editor.buf.indent.map(Indent::name), Some(String::from("Spaces: 2")));
+    }
 
-        for row in &editor.rows {
-            assert_eq!(row.chars, []);
+    #[test]
+    fn editor_auto_close() {
+        let syntax = SyntaxConf { sl_string_quotes: vec!['"', '\''], ..Default::default() };
+        let mut editor =
+            Editor { buf: Buffer { syntax, ..Default::default() }, ..Default::default() };
+        for &b in b"f(a[\"b" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        assert_row_chars_equal(&editor, &[b"f(a[\"b\"])"]);
+        for &b in b"\"]x) don't" {
+            editor.process_keypress(&Key::Char(b));
         }
+        assert_row_chars_equal(&editor, &[b"f(a[\"b\"]x) don't"]);
+        assert_eq!(editor.cursor.x, 16);
+
+        editor.process_keypress(&Key::Char(b'{'));
+        editor.process_keypress(&Key::Char(BACKSPACE));
+        editor.process_keypress(&Key::Char(BACKSPACE));
+        assert_row_chars_equal(&editor, &[b"f(a[\"b\"]x) don'"]);
+
+        // Delete before an auto-closed pair only deletes the opening character
+        editor.process_keypress(&Key::Char(b'('));
+        editor.process_keypress(&Key::Arrow(AKey::Left));
+        editor.process_keypress(&Key::Delete);
+        assert_row_chars_equal(&editor, &[b"f(a[\"b\"]x) don')"]);
+        assert_eq!(editor.cursor.x, 15);
     }
 
     #[test]
@@ -990,12 +3416,12 @@ mod tests {
         for b in b"Hello world!" {
             editor.insert_byte(*b);
         }
-        editor.delete_char();
+        editor.delete_char(true);
         assert_row_chars_equal(&editor, &[b"Hello world"]);
         editor.move_cursor(&AKey::Left, true);
         editor.move_cursor(&AKey::Left, false);
         editor.move_cursor(&AKey::Left, false);
-        editor.delete_char();
+        editor.delete_char(true);
         assert_row_chars_equal(&editor, &[b"Helo world"]);
     }
 
@@ -1266,7 +3692,7 @@ mod tests {
         let (should_quit, prompt_mode_again) = editor.process_keypress(&Key::PageDown);
         assert!(!should_quit);
         assert!(prompt_mode_again.is_none());
-        assert_eq!(editor.cursor.y, editor.rows.len());
+        assert_eq!(editor.cursor.y, editor.buf.n_rows());
         assert_eq!(editor.cursor.x, 0);
     }
 
@@ -1331,19 +3757,18 @@ mod tests {
 
     #[test]
     fn delete_current_row_updates_syntax_highlighting() {
-        let mut editor = Editor {
-            syntax: SyntaxConf {
-                ml_comment_delims: Some(("/*".to_owned(), "*/".to_owned())),
-                ..Default::default()
-            },
+        let syntax = SyntaxConf {
+            ml_comment_delims: Some(("/*".to_owned(), "*/".to_owned())),
             ..Default::default()
         };
+        let mut editor =
+            Editor { buf: Buffer { syntax, ..Default::default() }, ..Default::default() };
         for &b in b"A\nb/*c\nd\ne\nf*/g\nh" {
             editor.process_keypress(&Key::Char(b));
         }
 
         assert_row_chars_equal(&editor, &[b"A", b"b/*c", b"d", b"e", b"f*/g", b"h"]);
-        assert_row_synthax_highlighting_types_equal(&editor, &[
+        assert_row_synthax_highlighting_types_equal(&mut editor, &[
             &[HlType::Normal],
             &[HlType::Normal, HlType::MlComment, HlType::MlComment, HlType::MlComment],
             &[HlType::MlComment],
@@ -1356,7 +3781,7 @@ mod tests {
         editor.delete_current_row();
 
         assert_row_chars_equal(&editor, &[b"A", b"b/*c", b"d", b"e", b"h"]);
-        assert_row_synthax_highlighting_types_equal(&editor, &[
+        assert_row_synthax_highlighting_types_equal(&mut editor, &[
             &[HlType::Normal],
             &[HlType::Normal, HlType::MlComment, HlType::MlComment, HlType::MlComment],
             &[HlType::MlComment],
@@ -1368,19 +3793,784 @@ mod tests {
         editor.delete_current_row();
 
         assert_row_chars_equal(&editor, &[b"A", b"d", b"e", b"h"]);
-        assert_row_synthax_highlighting_types_equal(&editor, &[
+        assert_row_synthax_highlighting_types_equal(&mut editor, &[
+            &[HlType::Normal],
+            &[HlType::Normal],
+            &[HlType::Normal],
+            &[HlType::Normal],
+        ]);
+    }
+
+    #[test]
+    fn editor_move_lines() {
+        let syntax = SyntaxConf {
+            ml_comment_delims: Some(("/*".to_owned(), "*/".to_owned())),
+            ..Default::default()
+        };
+        let mut editor =
+            Editor { buf: Buffer { syntax, ..Default::default() }, ..Default::default() };
+        editor.insert_text((0, 0), b"a\n/*\nb\n*/\nc", false);
+        (editor.cursor.x, editor.cursor.y) = (1, 3);
+        editor.process_keypress(&Key::CtrlShiftArrow(AKey::Up));
+        assert_row_chars_equal(&editor, &[b"a", b"/*", b"*/", b"b", b"c"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (1, 2));
+        assert_row_synthax_highlighting_types_equal(&mut editor, &[
             &[HlType::Normal],
+            &[HlType::MlComment, HlType::MlComment],
+            &[HlType::MlComment, HlType::MlComment],
             &[HlType::Normal],
             &[HlType::Normal],
+        ]);
+
+        // The selected rows are moved, and stay selected
+        (editor.cursor.x, editor.cursor.y) = (0, 0);
+        editor.process_keypress(&Key::ShiftArrow(AKey::Down));
+        editor.process_keypress(&Key::ShiftEnd);
+        editor.process_keypress(&Key::CtrlShiftArrow(AKey::Up));
+        editor.process_keypress(&Key::CtrlShiftArrow(AKey::Down));
+        assert_row_chars_equal(&editor, &[b"*/", b"a", b"/*", b"b", b"c"]);
+        assert_eq!(editor.selection(), Some(((0, 1), (2, 2))));
+        assert_row_synthax_highlighting_types_equal(&mut editor, &[
+            &[HlType::Normal, HlType::Normal],
             &[HlType::Normal],
+            &[HlType::MlComment, HlType::MlComment],
+            &[HlType::MlComment],
+            &[HlType::MlComment],
+        ]);
+
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"a", b"/*", b"*/", b"b", b"c"]);
+    }
+
+    #[test]
+    fn editor_join_and_transpose() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), "  foo \t\n    bar\nx\naé".as_bytes(), false);
+        (editor.cursor.x, editor.cursor.y) = (1, 0);
+        editor.process_keypress(&Key::Alt(JOIN_LINES));
+        assert_row_chars_equal(&editor, &[b"  foo bar", b"x", "aé".as_bytes()]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (5, 0));
+
+        editor.process_keypress(&Key::Char(TRANSPOSE_CHARS));
+        assert_eq!((editor.cursor.x, editor.buf.line(0).to_vec()), (6, b"  fo obar".to_vec()));
+        editor.process_keypress(&Key::End);
+        editor.process_keypress(&Key::Char(TRANSPOSE_CHARS));
+        assert_row_chars_equal(&editor, &[b"  fo obra", b"x", "aé".as_bytes()]);
+
+        editor.cursor.y = 1;
+        editor.process_keypress(&Key::Alt(TRANSPOSE_LINES));
+        assert_row_chars_equal(&editor, &[b"x", b"  fo obra", "aé".as_bytes()]);
+        assert_eq!(editor.cursor.y, 2);
+        editor.process_keypress(&Key::End);
+        editor.process_keypress(&Key::Char(TRANSPOSE_CHARS));
+        assert_row_chars_equal(&editor, &[b"x", b"  fo obra", "éa".as_bytes()]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"x", b"  fo obra", "aé".as_bytes()]);
+    }
+
+    #[test]
+    fn editor_reflow() {
+        let mut editor = Editor::default();
+        editor.buf.syntax.sl_comment_start = vec![String::from("//")];
+        editor.config.text_width = 12;
+        editor.insert_text(
+            (0, 0),
+            b"x\n\n  // a bb ccc\n  // dddd\n  // ee\n\nf g h i j k l",
+            false,
+        );
+        (editor.cursor.x, editor.cursor.y) = (0, 3);
+        editor.process_keypress(&Key::Alt(REFLOW));
+        assert_row_chars_equal(&editor, &[
+            b"x",
+            b"",
+            b"  // a bb",
+            b"  // ccc",
+            b"  // dddd ee",
+            b"",
+            b"f g h i j k l",
+        ]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (12, 4));
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[
+            b"x",
+            b"",
+            b"  // a bb ccc",
+            b"  // dddd",
+            b"  // ee",
+            b"",
+            b"f g h i j k l",
+        ]);
+
+        // The selection may span several paragraphs
+        (editor.cursor.x, editor.cursor.y, editor.anchor) = (0, 7, Some((0, 1)));
+        editor.process_keypress(&Key::Alt(REFLOW));
+        assert_row_chars_equal(&editor, &[
+            b"x",
+            b"",
+            b"  // a bb",
+            b"  // ccc",
+            b"  // dddd ee",
+            b"",
+            b"f g h i j k",
+            b"l",
+        ]);
+        assert_eq!((editor.cursor.x, editor.cursor.y, editor.anchor), (1, 7, None));
+
+        // Blank rows are left alone
+        editor.cursor.y = 1;
+        editor.process_keypress(&Key::Alt(REFLOW));
+        assert_eq!(editor.buf.n_rows(), 8);
+    }
+
+    #[test]
+    fn editor_block_selection() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), "a\tb\n日本c\nxyz".as_bytes(), false);
+        (editor.cursor.x, editor.cursor.y) = (0, 0);
+        for key in [Key::Alt(BLOCK_SELECTION), Key::ShiftArrow(AKey::Down)] {
+            editor.process_keypress(&key);
+        }
+        editor.process_keypress(&Key::ShiftArrow(AKey::Right));
+        // The tab is partly in the first two columns, so it is selected
+        assert_eq!(editor.block_ranges(), vec![(0, 0..2), (1, 0..3)]);
+        assert_eq!(editor.selected_rx_ranges(0), vec![0..4]);
+        assert_eq!(editor.selected_rx_ranges(1), vec![0..2]);
+        assert_eq!(editor.selection(), None);
+
+        editor.process_keypress(&Key::Char(COPY));
+        let clip = Clip { text: "a\t\n日".as_bytes().to_vec(), rows: false, block: true };
+        assert_eq!(editor.kill_ring.get(0), Some(&clip));
+        assert!(editor.block);
+        editor.process_keypress(&Key::Char(CUT));
+        assert_row_chars_equal(&editor, &[b"b", "本c".as_bytes(), b"xyz"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y, editor.anchor), (0, 0, None));
+        assert!(!editor.block);
+
+        // The rows of the block are pasted at the same column, padded if needed
+        (editor.cursor.x, editor.cursor.y) = (1, 2);
+        editor.process_keypress(&Key::Char(PASTE));
+        assert_row_chars_equal(&editor, &[b"b", "本c".as_bytes(), b"xa\tyz", " 日".as_bytes()]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"b", "本c".as_bytes(), b"xyz"]);
+
+        // Typing in an empty block inserts text in each of its rows
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), b"| a |\n| bb |\n| c |", false);
+        (editor.cursor.x, editor.cursor.y) = (2, 0);
+        editor.process_keypress(&Key::Alt(BLOCK_SELECTION));
+        editor.process_keypress(&Key::ShiftArrow(AKey::Down));
+        editor.process_keypress(&Key::ShiftArrow(AKey::Down));
+        assert_eq!(editor.selected_rx_ranges(1), vec![2..3]);
+        for c in b"->" {
+            editor.process_keypress(&Key::Char(*c));
+        }
+        assert_row_chars_equal(&editor, &[b"| ->a |", b"| ->bb |", b"| ->c |"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y, editor.anchor), (4, 2, None));
+
+        // Typing in a block replaces its text
+        (editor.cursor.x, editor.cursor.y) = (2, 0);
+        editor.process_keypress(&Key::Alt(BLOCK_SELECTION));
+        editor.process_keypress(&Key::ShiftArrow(AKey::Down));
+        editor.process_keypress(&Key::ShiftArrow(AKey::Right));
+        editor.process_keypress(&Key::ShiftArrow(AKey::Right));
+        editor.process_keypress(&Key::Char(b'+'));
+        assert_row_chars_equal(&editor, &[b"| +a |", b"| +bb |", b"| ->c |"]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"| ->a |", b"| ->bb |", b"| ->c |"]);
+
+        // Moving without Shift ends the block selection
+        editor.process_keypress(&Key::Alt(BLOCK_SELECTION));
+        editor.process_keypress(&Key::Arrow(AKey::Left));
+        assert!(!editor.block);
+    }
+
+    #[test]
+    fn editor_transform() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), b"b \xff\nc\na\nb \xff\nz", false);
+        (editor.cursor.x, editor.cursor.y, editor.anchor) = (0, 4, Some((1, 0)));
+        let (_, prompt_mode) = editor.process_keypress(&Key::Alt(TRANSFORM));
+        assert_eq!(prompt_mode, Some(PromptMode::Transform(String::new())));
+        editor.transform("unique");
+        editor.transform("sort");
+        assert_row_chars_equal(&editor, &[b"a", b"b \xff", b"c", b"z"]);
+        assert_eq!((editor.anchor, editor.selection()), (Some((0, 0)), Some(((0, 0), (1, 2)))));
+
+        editor.transform("upper");
+        assert_row_chars_equal(&editor, &[b"A", b"B \xff", b"C", b"z"]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"a", b"b \xff", b"c", b"z"]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"b \xff", b"c", b"a", b"z"]);
+
+        (editor.anchor, editor.cursor.y) = (None, 3);
+        editor.transform("base64-encode");
+        assert_row_chars_equal(&editor, &[b"b \xff", b"c", b"a", b"eg=="]);
+        editor.transform("hex-decode");
+        assert_row_chars_equal(&editor, &[b"b \xff", b"c", b"a", b"eg=="]);
+        assert_eq!(
+            editor.status_msg.take().map(|msg| msg.msg),
+            Some(String::from("hex-decode: Invalid hexadecimal"))
+        );
+        editor.transform("rot13");
+        assert!(editor.status_msg.is_some_and(|msg| msg.msg.starts_with("Unknown transformation")));
+    }
+
+    #[test]
+    fn editor_undo_redo() {
+        let mut editor = Editor::default();
+        for &b in b"abc\nde" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        assert_row_chars_equal(&editor, &[b"abc", b"de"]);
+
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"abc", b""]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (0, 1));
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"abc"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (3, 0));
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b""]);
+        assert_eq!(editor.buf.n_bytes(), 0);
+        assert!(!editor.buf.dirty);
+
+        editor.process_keypress(&Key::Char(REDO));
+        editor.process_keypress(&Key::Char(REDO));
+        assert_row_chars_equal(&editor, &[b"abc", b""]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (0, 1));
+        assert!(editor.buf.dirty);
+    }
+
+    #[test]
+    fn editor_undo_after_moving_back() {
+        let mut editor = Editor::default();
+        for key in [Key::Char(b'a'), Key::Arrow(AKey::Left), Key::Arrow(AKey::Right)] {
+            editor.process_keypress(&key);
+        }
+        editor.process_keypress(&Key::Char(b'b'));
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"a"]);
+        assert_eq!(editor.cursor.x, 1);
+    }
+
+    #[test]
+    fn editor_undo_remove_line() {
+        let mut editor = Editor::default();
+        for &b in b"A\nb/*c\nd" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        (editor.cursor.x, editor.cursor.y) = (2, 1);
+        editor.process_keypress(&Key::Char(REMOVE_LINE));
+        assert_row_chars_equal(&editor, &[b"A", b"d"]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"A", b"b/*c", b"d"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (2, 1));
+        assert_eq!(editor.buf.n_bytes(), 6);
+    }
+
+    #[test]
+    fn editor_dirty_after_undo_to_saved_state() {
+        let buf = Buffer { file_name: Some(String::from("file.txt")), ..Default::default() };
+        let mut editor = Editor { buf, ..Default::default() };
+        editor.insert_new_line();
+        editor.buf.history.mark_saved();
+        editor.update_dirty();
+        assert!(!editor.buf.dirty);
+        editor.insert_byte(b'a');
+        editor.delete_char(true);
+        assert!(editor.buf.dirty);
+        editor.undo();
+        assert!(editor.buf.dirty);
+        editor.undo();
+        assert!(!editor.buf.dirty);
+        editor.undo();
+        assert!(editor.buf.dirty);
+    }
+
+    #[test]
+    fn editor_cut_and_paste_selection() {
+        let mut editor = Editor::default();
+        for &b in b"Hello\nWorld" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        (editor.cursor.x, editor.cursor.y) = (2, 0);
+        editor.process_keypress(&Key::ShiftArrow(AKey::Down));
+        assert_eq!(editor.selection(), Some(((2, 0), (2, 1))));
+        assert_eq!(editor.selected_rx_ranges(0), vec![2..5]);
+        assert_eq!(editor.selected_rx_ranges(1), vec![0..2]);
+
+        editor.process_keypress(&Key::Char(CUT));
+        assert_row_chars_equal(&editor, &[b"Herld"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y, editor.buf.n_bytes()), (2, 0, 5));
+        assert_eq!(editor.selection(), None);
+
+        editor.process_keypress(&Key::End);
+        editor.process_keypress(&Key::Char(PASTE));
+        assert_row_chars_equal(&editor, &[b"Herldllo", b"Wo"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y, editor.buf.n_bytes()), (2, 1, 10));
+    }
+
+    #[test]
+    fn editor_kill_ring() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), b"a\nb\nc\nd", false);
+        (editor.cursor.x, editor.cursor.y) = (0, 0);
+        // Consecutive cuts are pasted back together
+        editor.process_keypress(&Key::Char(CUT));
+        editor.process_keypress(&Key::Char(CUT));
+        editor.process_keypress(&Key::Arrow(AKey::Down));
+        editor.process_keypress(&Key::Char(COPY));
+        assert_eq!(editor.kill_ring_list(), "1:d 2:a↵b");
+
+        editor.process_keypress(&Key::Char(PASTE));
+        assert_row_chars_equal(&editor, &[b"c", b"d", b"d"]);
+        editor.process_keypress(&Key::Alt(PASTE_PREVIOUS));
+        assert_row_chars_equal(&editor, &[b"c", b"d", b"a", b"b"]);
+        assert_eq!(editor.cursor.y, 3);
+        editor.process_keypress(&Key::Alt(PASTE_PREVIOUS));
+        assert_row_chars_equal(&editor, &[b"c", b"d", b"d"]);
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"c", b"d", b"a", b"b"]);
+
+        // The previous key was not a paste
+        editor.process_keypress(&Key::Alt(PASTE_PREVIOUS));
+        assert_row_chars_equal(&editor, &[b"c", b"d", b"a", b"b"]);
+
+        (editor.cursor.x, editor.cursor.y) = (1, 0);
+        let (_, prompt_mode) = editor.process_keypress(&Key::Alt(KILL_RING));
+        assert_eq!(prompt_mode, Some(PromptMode::KillRing(String::new())));
+        let prompt_mode = PromptMode::KillRing(String::from("2"));
+        assert_eq!(prompt_mode.process_keypress(&mut editor, &Key::Char(b'\r')), None);
+        assert_row_chars_equal(&editor, &[b"c", b"a", b"b", b"d", b"a", b"b"]);
+    }
+
+    #[test]
+    fn editor_marks_and_jump_list() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), b"a\nb\nc\nd\ne", false);
+        (editor.cursor.x, editor.cursor.y) = (1, 2);
+        assert_eq!(PromptMode::SetMark.process_keypress(&mut editor, &Key::Char(b'm')), None);
+        // The mark follows its row
+        editor.insert_text((0, 0), b"new\n", false);
+        editor.delete_text((0, 1), (0, 2));
+        editor.undo();
+        assert_eq!(editor.mark_list(), "m:4");
+
+        (editor.cursor.x, editor.cursor.y) = (0, 0);
+        PromptMode::JumpToMark.process_keypress(&mut editor, &Key::Char(b'z'));
+        assert_eq!((editor.cursor.x, editor.cursor.y), (0, 0));
+        PromptMode::JumpToMark.process_keypress(&mut editor, &Key::Char(b'm'));
+        assert_eq!((editor.cursor.x, editor.cursor.y), (1, 3));
+        editor.go_to("6");
+        assert_eq!((editor.cursor.x, editor.cursor.y), (1, 5));
+
+        for (key, expected) in [
+            (AKey::Left, (1, 3)),
+            (AKey::Left, (0, 0)),
+            (AKey::Left, (0, 0)),
+            (AKey::Right, (1, 3)),
+            (AKey::Right, (1, 5)),
+            (AKey::Right, (1, 5)),
+        ] {
+            editor.process_keypress(&Key::AltArrow(key));
+            assert_eq!((editor.cursor.x, editor.cursor.y), expected);
+        }
+    }
+
+    #[test]
+    fn editor_type_over_selection() {
+        let mut editor = Editor::default();
+        for &b in b"abc\ndef\nghi" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        editor.process_keypress(&Key::ShiftArrow(AKey::Left));
+        editor.process_keypress(&Key::ShiftArrow(AKey::Up));
+        editor.process_keypress(&Key::ShiftHome);
+        editor.process_keypress(&Key::Char(COPY));
+        assert_eq!(
+            editor.kill_ring.get(0),
+            Some(&Clip { text: b"def\nghi".to_vec(), rows: false, block: false })
+        );
+        assert_eq!(editor.selection(), Some(((0, 1), (3, 2))));
+
+        editor.process_keypress(&Key::Char(b'X'));
+        assert_row_chars_equal(&editor, &[b"abc", b"X"]);
+        assert_eq!(editor.buf.n_bytes(), 4);
+
+        editor.process_keypress(&Key::Home);
+        editor.process_keypress(&Key::ShiftEnd);
+        editor.process_keypress(&Key::Char(BACKSPACE));
+        assert_row_chars_equal(&editor, &[b"abc", b""]);
+        editor.process_keypress(&Key::ShiftArrow(AKey::Left));
+        editor.process_keypress(&Key::Arrow(AKey::Left));
+        assert_eq!(editor.selection(), None);
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case::clipboard_changed("printf Hello", &[&b"aHello"[..]])]
+    #[case::clipboard_unchanged("printf a\\n", &[&b"a"[..], &b"a"[..]])]
+    fn editor_paste_from_clipboard_command(#[case] cmd: &str, #[case] expected: &[&[u8]]) {
+        let config = Config {
+            osc52_clipboard: false,
+            clipboard_paste_cmd: Some(String::from(cmd)),
+            ..Config::default()
+        };
+        let mut editor = Editor { config, ..Default::default() };
+        editor.process_keypress(&Key::Char(b'a'));
+        editor.process_keypress(&Key::Char(COPY));
+        // The row copied in the editor is pasted below the current row if the
+        // clipboard still contains it, otherwise the clipboard text is inserted.
+        editor.process_keypress(&Key::Char(PASTE));
+        assert_row_chars_equal(&editor, expected);
+    }
+
+    #[test]
+    fn editor_line_endings() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("file.txt");
+        fs::write(&path, "Hello\r\nWorld\r\n").expect("Could not write file");
+        let file_name = path.to_string_lossy().to_string();
+
+        let mut editor = Editor::default();
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        assert_row_chars_equal(&editor, &[b"Hello", b"World", b""]);
+        assert_eq!((editor.buf.line_ending, editor.buf.file_size()), (LineEnding::CrLf, 14));
+        editor.insert_text((5, 0), b"!", false);
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some(b"Hello!\r\nWorld\r\n".to_vec()));
+
+        let (_, prompt_mode) = editor.process_keypress(&Key::Alt(LINE_ENDING));
+        assert_eq!(prompt_mode, Some(PromptMode::LineEnding(String::new())));
+        editor.set_line_ending("lf");
+        assert!(editor.buf.dirty);
+        editor.set_line_ending("CRLF");
+        assert!(!editor.buf.dirty);
+        editor.set_line_ending("LF");
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some(b"Hello!\nWorld\n".to_vec()));
+
+        fs::write(&path, "a\rb\r\nc\rd").expect("Could not write file");
+        let mut editor = Editor::default();
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        assert_row_chars_equal(&editor, &[b"a", b"b", b"c", b"d"]);
+        assert_eq!(editor.buf.line_ending, LineEnding::Cr);
+        assert!(editor.status_msg.is_some_and(|msg| msg.msg.contains("mixed line endings")));
+    }
+
+    #[test]
+    fn editor_encodings() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("file.txt");
+        fs::write(&path, b"\xff\xfeH\x00\xe9\x00\r\x00\n\x00").expect("Could not write file");
+        let file_name = path.to_string_lossy().to_string();
+
+        let mut editor = Editor::default();
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        assert_row_chars_equal(&editor, &["Hé".as_bytes(), b""]);
+        assert_eq!(editor.buf.encoding, Encoding::Utf16Le { bom: true });
+        editor.insert_text((3, 0), b"!", false);
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        let expected = b"\xff\xfeH\x00\xe9\x00!\x00\r\x00\n\x00";
+        assert_eq!(fs::read(&path).ok().as_deref(), Some(&expected[..]));
+
+        let (_, prompt_mode) = editor.process_keypress(&Key::Alt(ENCODING));
+        assert_eq!(prompt_mode, Some(PromptMode::Encoding(String::new())));
+        editor.set_encoding("latin-1");
+        assert!(editor.buf.dirty);
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some(b"H\xe9!\r\n".to_vec()));
+
+        // Text that can't be encoded is not saved
+        editor.insert_text((0, 0), "✓".as_bytes(), false);
+        assert!(!editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some(b"H\xe9!\r\n".to_vec()));
+        editor.set_encoding("utf-8 bom");
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some("\u{feff}✓Hé!\r\n".as_bytes().to_vec()));
+
+        // A file that is not valid UTF-8 is read as Windows-1252, with a warning
+        fs::write(&path, b"H\xe9\n").expect("Could not write file");
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        assert_eq!(editor.buf.encoding, Encoding::Windows1252);
+        let msg = editor.status_msg.take().map(|msg| msg.msg);
+        assert!(msg.is_some_and(|msg| msg.contains("read as Windows-1252")));
+    }
+
+    #[test]
+    fn editor_editorconfig() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let config = "root = true\n[*.py]\nindent_style = space\nindent_size = 2\ntab_width = \
+                      8\nend_of_line = crlf\ntrim_trailing_whitespace = \
+                      true\ninsert_final_newline = true\n[*.md]\ncharset = latin1\n";
+        fs::write(tmp_dir.path().join(".editorconfig"), config).expect("Could not write file");
+        let path = tmp_dir.path().join("file.py");
+        fs::write(&path, "if x:\n\ty = 1  \n\tz = 2").expect("Could not write file");
+        let file_name = path.to_string_lossy().to_string();
+
+        let mut editor = Editor::default();
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        let (two, eight) = (NonZeroUsize::new(2), NonZeroUsize::new(8));
+        assert_eq!(editor.buf.indent, two.map(Indent::Spaces));
+        assert_eq!((editor.buf.tab_stop, editor.buf.line_ending), (eight, LineEnding::CrLf));
+        assert!(!editor.buf.dirty);
+        editor.cursor.y = 1;
+        editor.cursor.x = 8;
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some(b"if x:\r\n\ty = 1\r\n\tz = 2\r\n".to_vec()));
+        assert_eq!((editor.cursor.x, editor.cursor.y), (6, 1));
+        assert!(!editor.buf.dirty);
+        // The clean-up is undone at once
+        editor.undo();
+        assert_row_chars_equal(&editor, &[b"if x:", b"\ty = 1  ", b"\tz = 2"]);
+
+        // The properties only apply to the matching files
+        let path = tmp_dir.path().join("file.txt");
+        fs::write(&path, "a  ").expect("Could not write file");
+        let mut editor = Editor::default();
+        editor.load_buffer(Some(&path.to_string_lossy())).expect("Could not load file");
+        assert_eq!((editor.buf.indent, editor.buf.tab_stop), (None, None));
+        assert!(editor.save_and_handle_io_errors(&path.to_string_lossy()));
+        assert_eq!(fs::read(&path).ok(), Some(b"a  ".to_vec()));
+
+        // The encoding detected from a file is kept, with a warning
+        let path = tmp_dir.path().join("file.md");
+        fs::write(&path, "é").expect("Could not write file");
+        let mut editor = Editor::default();
+        editor.load_buffer(Some(&path.to_string_lossy())).expect("Could not load file");
+        assert_eq!(editor.buf.encoding, Encoding::Utf8 { bom: false });
+        assert!(editor.status_msg.is_some_and(|sm| sm.msg.contains("not in Windows-1252")));
+
+        // The properties apply to a new file when it is saved
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), "é".as_bytes(), false);
+        editor.save_as(tmp_dir.path().join("new.md").to_string_lossy().to_string());
+        assert_eq!(fs::read(tmp_dir.path().join("new.md")).ok(), Some(b"\xe9".to_vec()));
+        assert!(!editor.buf.dirty);
+    }
+
+    #[test]
+    fn editor_clean_up_on_save() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("file.txt");
+        fs::write(&path, "a  \n\tb\t\nc").expect("Could not write file");
+        let file_name = path.to_string_lossy().to_string();
+
+        let mut editor = Editor::default();
+        (editor.config.trim_trailing_whitespace, editor.config.insert_final_newline) = (true, true);
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        (editor.cursor.x, editor.cursor.y) = (3, 0);
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some(b"a\n\tb\nc\n".to_vec()));
+        assert_eq!((editor.cursor.x, editor.cursor.y, editor.buf.n_bytes()), (1, 0, 4));
+        let msg = editor.status_msg.take().map(|msg| msg.msg);
+        assert!(msg.is_some_and(|msg| {
+            msg.ends_with("file.txt (trailing whitespace trimmed on 2 rows, final new line added)")
+        }));
+        assert!(!editor.buf.dirty);
+
+        // Nothing left to clean up
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert!(editor.status_msg.take().is_some_and(|msg| msg.msg.ends_with("file.txt")));
+
+        // The settings of the file take precedence over the configuration
+        editor.buf.trim_trailing_whitespace = Some(false);
+        editor.insert_text((1, 0), b" ", false);
+        assert!(editor.save_and_handle_io_errors(&file_name));
+        assert_eq!(fs::read(&path).ok(), Some(b"a \n\tb\nc\n".to_vec()));
+
+        // The text is not cleaned up if the file can't be written
+        editor.buf.trim_trailing_whitespace = Some(true);
+        let missing_dir = tmp_dir.path().join("missing").join("file.txt");
+        assert!(!editor.save_and_handle_io_errors(&missing_dir.to_string_lossy()));
+        assert_row_chars_equal(&editor, &[b"a ", b"\tb", b"c", b""]);
+    }
+
+    #[test]
+    fn editor_modelines() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("file.txt");
+        let text = "a\n\tb\n\n\n\n\n\n# vim: set ts=2 sw=3 et ft=unknown:\n";
+        fs::write(&path, text).expect("Could not write file");
+        let file_name = path.to_string_lossy().to_string();
+
+        // Modelines are ignored unless they are enabled
+        let mut editor = Editor::default();
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        assert_eq!((editor.buf.indent, editor.buf.tab_stop), (Some(Indent::Tabs), None));
+
+        let mut editor = Editor::default();
+        editor.config.modelines = true;
+        editor.load_buffer(Some(&file_name)).expect("Could not load file");
+        let (two, three) = (NonZeroUsize::new(2), NonZeroUsize::new(3));
+        assert_eq!((editor.buf.indent, editor.buf.tab_stop), (three.map(Indent::Spaces), two));
+        assert!(editor.status_msg.is_some_and(|msg| msg.msg.contains("unknown language unknown")));
+    }
+
+    #[test]
+    fn editor_open_switch_and_close_buffers() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let path = tmp_dir.path().join("file.txt");
+        fs::write(&path, "Hello\nWorld\n").expect("Could not write file");
+        let file_name = path.to_string_lossy().to_string();
+
+        let mut editor = Editor::default();
+        editor.load_buffer(None).expect("Could not create empty buffer");
+        editor.insert_byte(b'a');
+        editor.open(&file_name);
+        assert_row_chars_equal(&editor, &[b"Hello", b"World", b""]);
+        assert_eq!(editor.buffer_list(), format!("1:[No Name]* [2:{file_name}]"));
+
+        // Quitting warns about the modified buffer, even though it is not the current
+        // one
+        assert_eq!(editor.process_keypress(&Key::Char(EXIT)), (false, None));
+
+        editor.process_keypress(&Key::Char(NEXT_BUFFER));
+        assert_row_chars_equal(&editor, &[b"a"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (1, 0));
+
+        // Opening a file that is already open switches to its buffer
+        editor.open(&file_name);
+        assert_eq!((editor.buf_idx, editor.inactive.len()), (1, 1));
+        editor.process_keypress(&Key::Char(CLOSE_BUFFER));
+        assert_row_chars_equal(&editor, &[b"a"]);
+        assert!(editor.inactive.is_empty());
+
+        // Closing a modified buffer needs to be confirmed, even after Ctrl+Q
+        editor.process_keypress(&Key::Char(EXIT));
+        editor.process_keypress(&Key::Char(CLOSE_BUFFER));
+        assert_row_chars_equal(&editor, &[b"a"]);
+        editor.process_keypress(&Key::Char(CLOSE_BUFFER));
+        assert_row_chars_equal(&editor, &[b""]);
+        assert!(!editor.buf.dirty);
+    }
+
+    #[test]
+    fn editor_switch_buffer_prompt() {
+        let mut editor = Editor::default();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let previous = mem::replace(&mut editor.buf, Buffer {
+                file_name: Some(name.to_owned()),
+                ..Default::default()
+            });
+            editor.inactive.push((previous, CursorState::default()));
+        }
+        editor.buf_idx = 3;
+        editor.process_keypress(&Key::Char(PREVIOUS_BUFFER));
+        assert_eq!(editor.buffer_list(), "1:[No Name] 2:a.txt [3:b.txt] 4:c.txt");
+
+        for (input, expected) in [("1", 0), ("c.t", 3), ("9", 3), ("a.", 1)] {
+            let mut prompt_mode = Some(PromptMode::SwitchBuffer(input.to_owned()));
+            prompt_mode =
+                prompt_mode.and_then(|mode| mode.process_keypress(&mut editor, &Key::Char(b'\r')));
+            assert_eq!(prompt_mode, None);
+            assert_eq!(editor.buf_idx, expected);
+        }
+        assert_eq!(editor.buffers().map(Buffer::name).collect::<Vec<_>>(), [
+            "[No Name]",
+            "a.txt",
+            "b.txt",
+            "c.txt"
         ]);
     }
 
+    #[test]
+    fn editor_split_switch_and_close_panes() {
+        let mut editor = Editor { term_size: (25, 80), ..Default::default() };
+        editor.update_layout();
+        for &b in b"abc\ndef" {
+            editor.process_keypress(&Key::Char(b));
+        }
+        editor.process_keypress(&Key::Alt(SPLIT_VERTICALLY));
+        editor.process_keypress(&Key::Alt(SPLIT_HORIZONTALLY));
+        let rects: Vec<_> = editor.layout.panes().iter().map(|pane| pane.rect).collect();
+        assert_eq!(rects, [(0, 0, 12, 39), (12, 0, 12, 39), (0, 40, 24, 40)]);
+        assert_eq!((editor.screen_rows, editor.window_width), (11, 39));
+
+        // Each pane has its own cursor, and panes show the same buffer
+        editor.process_keypress(&Key::Arrow(AKey::Up));
+        editor.process_keypress(&Key::Alt(NEXT_PANE));
+        assert_eq!((editor.pane_idx, editor.cursor.x, editor.cursor.y), (1, 3, 1));
+        editor.process_keypress(&Key::Char(REMOVE_LINE));
+        assert_row_chars_equal(&editor, &[b"abc"]);
+        editor.process_keypress(&Key::Alt(NEXT_PANE));
+        editor.process_keypress(&Key::Alt(NEXT_PANE));
+        assert_eq!((editor.pane_idx, editor.cursor.x, editor.cursor.y), (0, 3, 0));
+
+        editor.process_keypress(&Key::Alt(CLOSE_PANE));
+        editor.process_keypress(&Key::Alt(CLOSE_PANE));
+        assert_eq!(editor.layout.panes().len(), 1);
+        editor.process_keypress(&Key::Alt(CLOSE_PANE));
+        assert_eq!((editor.screen_rows, editor.window_width), (23, 80));
+        // The cursor of the remaining pane is clamped to the edited buffer
+        assert_eq!((editor.cursor.x, editor.cursor.y), (0, 1));
+    }
+
+    #[test]
+    fn editor_edit_at_multiple_cursors() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), b"abc\nde\nfghi", false);
+        (editor.cursor.x, editor.cursor.y) = (2, 0);
+        editor.process_keypress(&Key::AltArrow(AKey::Down));
+        editor.process_keypress(&Key::AltArrow(AKey::Down));
+        assert_eq!(
+            (editor.cursor.x, editor.cursor.y, &editor.cursors[..]),
+            (2, 2, &[(2, 0), (2, 1)][..])
+        );
+
+        for key in [Key::Char(b'X'), Key::Char(b'\r'), Key::Char(BACKSPACE), Key::End] {
+            editor.process_keypress(&key);
+        }
+        assert_row_chars_equal(&editor, &[b"abXc", b"deX", b"fgXhi"]);
+        assert_eq!(
+            (editor.cursor.x, editor.cursor.y, &editor.cursors[..]),
+            (5, 2, &[(4, 0), (3, 1)][..])
+        );
+        editor.process_keypress(&Key::Char(b'!'));
+        assert_row_chars_equal(&editor, &[b"abXc!", b"deX!", b"fgXhi!"]);
+        assert_eq!(editor.selected_rx_ranges(1), vec![4..5]);
+
+        // Edits at all the cursors are undone at once
+        editor.process_keypress(&Key::Char(UNDO));
+        assert_row_chars_equal(&editor, &[b"abXc", b"deX", b"fgXhi"]);
+        // Another key removes the extra cursors
+        assert!(editor.cursors.is_empty());
+    }
+
+    #[test]
+    fn editor_add_cursors_at_occurrences() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), b"foo bar\nfoobar foo\nfoo", false);
+        (editor.cursor.x, editor.cursor.y) = (1, 2);
+        editor.process_keypress(&Key::Alt(ADD_CURSOR_AT_NEXT_OCCURRENCE));
+        assert_eq!((editor.cursor.x, editor.cursor.y, &editor.cursors[..]), (1, 0, &[(1, 2)][..]));
+        editor.process_keypress(&Key::Alt(ADD_CURSOR_AT_NEXT_OCCURRENCE));
+        assert_eq!(
+            (editor.cursor.x, editor.cursor.y, &editor.cursors[..]),
+            (8, 1, &[(1, 0), (1, 2)][..])
+        );
+        editor.process_keypress(&Key::Alt(ADD_CURSOR_AT_NEXT_OCCURRENCE));
+        assert_eq!(editor.cursors.len(), 2);
+        editor.process_keypress(&Key::Char(DELETE_BIS));
+        assert_row_chars_equal(&editor, &[b"oo bar", b"foobar oo", b"oo"]);
+
+        editor.process_keypress(&Key::Escape);
+        (editor.cursor.x, editor.cursor.y) = (1, 1);
+        editor.add_cursors_at_matches(b"oo");
+        assert_eq!(editor.cursors, [(0, 0), (7, 1), (0, 2)]);
+        for key in [Key::Delete, Key::Char(b'0')] {
+            editor.process_keypress(&key);
+        }
+        assert_row_chars_equal(&editor, &[b"0o bar", b"f0obar 0o", b"0o"]);
+    }
+
     #[test]
     fn loop_until_keypress() -> Result<(), Error> {
         let mut editor = Editor::default();
         let mut fake_stdin = Cursor::new(
-            b"abc\x1b[A\x1b[B\x1b[C\x1b[D\x1b[H\x1bOH\x1b[F\x1bOF\x1b[1;5C\x1b[5C\x1b[99",
+            b"abc\x1b[A\x1b[B\x1b[C\x1b[D\x1b[H\x1bOH\x1b[F\x1bOF\x1b[1;5C\x1b[5C\x1b[1;2D\x1b[1;2H\
+              \x1b[1;3B\x1b[1;6A\x1b[1;2F\x1b[5;2~\x1b[6;2~\x1bs\x1b[99",
         );
         for expected_key in [
             Key::Char(b'a'),
@@ -1396,6 +4586,14 @@ mod tests {
             Key::End,
             Key::CtrlArrow(AKey::Right),
             Key::CtrlArrow(AKey::Right),
+            Key::ShiftArrow(AKey::Left),
+            Key::ShiftHome,
+            Key::AltArrow(AKey::Down),
+            Key::CtrlShiftArrow(AKey::Up),
+            Key::ShiftEnd,
+            Key::ShiftPageUp,
+            Key::ShiftPageDown,
+            Key::Alt(b's'),
             Key::Escape,
         ] {
             assert_eq!(editor.loop_until_keypress(&mut fake_stdin)?, expected_key);
@@ -1466,7 +4664,7 @@ mod tests {
         editor.update_screen_cols();
 
         let mut buffer = String::new();
-        editor.draw_left_padding(&mut buffer, value);
+        editor.draw_left_padding(editor.ln_pad, &mut buffer, value);
         assert_eq!(buffer, expected);
     }
 
@@ -1475,7 +4673,7 @@ mod tests {
         let mut editor = Editor::default();
 
         // Set up Python syntax configuration for testing
-        editor.syntax.sl_comment_start = vec!["#".to_owned()];
+        editor.buf.syntax.sl_comment_start = vec!["#".to_owned()];
 
         for b in b"def hello():\n    print(\"Hello\")\n    return True" {
             if *b == b'\n' {
@@ -1489,34 +4687,246 @@ mod tests {
         editor.cursor.y = 0; // First line
         editor.cursor.x = 0;
         editor.process_keypress(&Key::Char(TOGGLE_COMMENT));
-        assert_eq!(editor.rows[0].chars, b"# def hello():");
+        assert_eq!(editor.buf.line(0).to_vec(), b"# def hello():");
 
         // Test uncommenting the same line
         editor.process_keypress(&Key::Char(TOGGLE_COMMENT));
-        assert_eq!(editor.rows[0].chars, b"def hello():");
+        assert_eq!(editor.buf.line(0).to_vec(), b"def hello():");
 
         // Test commenting an indented line
         editor.cursor.y = 1; // Second line (indented)
         editor.cursor.x = 0;
         editor.process_keypress(&Key::Char(TOGGLE_COMMENT));
-        assert_eq!(editor.rows[1].chars, b"    # print(\"Hello\")");
+        assert_eq!(editor.buf.line(1).to_vec(), b"    # print(\"Hello\")");
 
         // Test uncommenting the indented line
         editor.process_keypress(&Key::Char(TOGGLE_COMMENT));
-        assert_eq!(editor.rows[1].chars, b"    print(\"Hello\")");
+        assert_eq!(editor.buf.line(1).to_vec(), b"    print(\"Hello\")");
 
         // Test the bug case: cursor at end of line during toggle
         editor.cursor.y = 0; // First line
-        editor.cursor.x = editor.rows[0].chars.len(); // Position at end
+        editor.cursor.x = editor.buf.line(0).len(); // Position at end
         editor.process_keypress(&Key::Char(TOGGLE_COMMENT)); // Comment
-        assert_eq!(editor.rows[0].chars, b"# def hello():");
+        assert_eq!(editor.buf.line(0).to_vec(), b"# def hello():");
 
         // Now uncomment with cursor still at end - this should not panic
-        editor.cursor.x = editor.rows[0].chars.len(); // Position at end again
+        editor.cursor.x = editor.buf.line(0).len(); // Position at end again
         editor.process_keypress(&Key::Char(TOGGLE_COMMENT)); // Uncomment
-        assert_eq!(editor.rows[0].chars, b"def hello():");
+        assert_eq!(editor.buf.line(0).to_vec(), b"def hello():");
 
         // Verify cursor position is valid
-        assert!(editor.cursor.x <= editor.rows[0].chars.len());
+        assert!(editor.cursor.x <= editor.buf.line(0).len());
+    }
+
+    #[test]
+    fn editor_matching_bracket() {
+        let syntax = SyntaxConf {
+            sl_string_quotes: vec!['"'],
+            bracket_pairs: vec![("(".into(), ")".into()), ("begin".into(), "end".into())],
+            ..Default::default()
+        };
+        let buf = Buffer { syntax, ..Default::default() };
+        let mut editor = Editor { buf, screen_rows: 10, ..Default::default() };
+        editor.insert_text((0, 0), b"f(a, \")\",\n  (b)) begin\nbeginning end", false);
+        editor.process_keypress(&Key::Char(MATCHING_BRACKET));
+        assert_eq!((editor.cursor.x, editor.cursor.y), (0, 0));
+
+        editor.cursor.x = 1;
+        editor.find_shown_brackets();
+        assert_eq!(editor.matched_brackets, [(0, 1..2), (1, 5..6)]);
+        editor.process_keypress(&Key::Char(MATCHING_BRACKET));
+        assert_eq!((editor.cursor.x, editor.cursor.y), (5, 1));
+        editor.process_keypress(&Key::Char(MATCHING_BRACKET));
+        assert_eq!((editor.cursor.x, editor.cursor.y), (1, 0));
+
+        (editor.cursor.x, editor.cursor.y) = (7, 1);
+        editor.process_keypress(&Key::Char(MATCHING_BRACKET));
+        assert_eq!((editor.cursor.x, editor.cursor.y), (10, 2));
+    }
+
+    #[test]
+    fn editor_soft_wrap() {
+        let config = Config { soft_wrap: true, ..Default::default() };
+        let mut editor = Editor { config, screen_rows: 3, window_width: 10, ..Default::default() };
+        editor.insert_text((0, 0), "0123456789abcdef\nxy\nabcdefghi界j".as_bytes(), false);
+        (editor.cursor.x, editor.cursor.y) = (13, 0);
+        assert_eq!(editor.cursor_screen_pos(), (3, 1));
+        for (key, expected) in [
+            (AKey::Up, (3, 0)),
+            (AKey::Down, (13, 0)),
+            (AKey::Down, (2, 1)),
+            (AKey::Down, (2, 2)),
+            // The wide character does not fit on the first line
+            (AKey::Down, (12, 2)),
+            (AKey::Up, (2, 2)),
+            (AKey::Down, (12, 2)),
+        ] {
+            editor.process_keypress(&Key::Arrow(key));
+            assert_eq!((editor.cursor.x, editor.cursor.y), expected);
+        }
+        editor.scroll_wrapped();
+        assert_eq!((editor.cursor.roff, editor.cursor_screen_pos()), (1, (2, 2)));
+    }
+
+    #[test]
+    fn editor_renders_only_shown_rows() {
+        let mut editor = Editor { screen_rows: 4, ..Default::default() };
+        editor.buf.syntax.ml_comment_delims = Some(("/*".to_owned(), "*/".to_owned()));
+        editor.insert_text((0, 0), &b"row\n".repeat(100), false);
+        editor.cursor.roff = 50;
+        editor.render_shown_rows();
+        assert!(editor.buf.rendered_rows.keys().eq(&[50, 51, 52, 53]));
+        assert_eq!(editor.buf.hl_states.len(), 54);
+
+        // Only the edited rows are discarded, the following rows are shifted
+        editor.insert_text((0, 52), b"new ", false);
+        assert!(editor.buf.rendered_rows.keys().eq(&[50, 51, 53]));
+        assert_eq!(editor.buf.hl_states.len(), 54);
+        editor.insert_text((0, 51), b"\n", false);
+        assert!(editor.buf.rendered_rows.keys().eq(&[50, 54]));
+        assert_eq!(editor.buf.hl_states.len(), 55);
+        editor.render_shown_rows();
+        assert_eq!(editor.buf.rendered_row(53).map(|row| &row.chars[..]), Some(&b"new row"[..]));
+
+        // The following rows are discarded if the highlight state changes
+        editor.insert_text((0, 51), b"/*", false);
+        assert!(editor.buf.rendered_rows.keys().eq(&[50]));
+        assert_eq!(editor.buf.hl_states.len(), 52);
+    }
+
+    #[rstest]
+    #[case("ab", &[Key::Char(b'a'), Key::Char(b'b')])]
+    #[case("é", &[Key::Char(0xc3), Key::Char(0xa9)])]
+    #[case("<lt><Space><Tab><CR><BS><Esc><Del>", &[Key::Char(b'<'), Key::Char(b' '), Key::Char(b'\t'), Key::Char(b'\r'), Key::Char(BACKSPACE), Key::Escape, Key::Delete])]
+    #[case("<Up><C-Left><S-Right><M-Down><C-S-Up>", &[Key::Arrow(AKey::Up), Key::CtrlArrow(AKey::Left), Key::ShiftArrow(AKey::Right), Key::AltArrow(AKey::Down), Key::CtrlShiftArrow(AKey::Up)])]
+    #[case("<S-PageUp><PageDown><Home><S-End>", &[Key::ShiftPageUp, Key::PageDown, Key::Home, Key::ShiftEnd])]
+    #[case("<C-s><C-_><M-d><M-0x3e><C-@><0x80>", &[Key::Char(SAVE), Key::Char(TOGGLE_COMMENT), Key::Alt(b'd'), Key::Alt(b'>'), Key::Char(0), Key::Char(0x80)])]
+    fn key_notation(#[case] notation: &str, #[case] keys: &[Key]) {
+        assert_eq!(Key::parse_keys(notation).as_deref(), Ok(keys));
+        // Non-ASCII characters are written as bytes
+        let expected = notation.replace('é', "<0xc3><0xa9>");
+        assert_eq!(keys.iter().map(ToString::to_string).collect::<String>(), expected);
+    }
+
+    #[rstest]
+    #[case("<Up", "Missing '>'")]
+    #[case("<Foo>", "Invalid key: <Foo>")]
+    #[case("<S-a>", "Invalid key: <S-a>")]
+    fn invalid_key_notation(#[case] notation: &str, #[case] expected: &str) {
+        assert_eq!(Key::parse_keys(notation), Err(String::from(expected)));
+    }
+
+    #[test]
+    fn editor_record_and_play_macro() {
+        let mut editor = Editor::default();
+        editor.insert_text((0, 0), b"a\nb\nc\nd", false);
+        (editor.cursor.x, editor.cursor.y) = (0, 0);
+        let keys = [Key::Alt(RECORD_MACRO), Key::Home, Key::Char(b'#'), Key::Arrow(AKey::Down)];
+        for key in keys.into_iter().chain([Key::Alt(PLAY_MACRO), Key::Alt(RECORD_MACRO)]) {
+            editor.process_key(&key);
+        }
+        assert_row_chars_equal(&editor, &[b"#a", b"b", b"c", b"d"]);
+        assert_eq!(editor.last_macro, [Key::Home, Key::Char(b'#'), Key::Arrow(AKey::Down)]);
+        assert!(editor.recording.is_none());
+
+        for key in [Key::Alt(PLAY_MACRO), Key::Char(b'2'), Key::Char(b'\r')] {
+            editor.process_key(&key);
+        }
+        assert_eq!(editor.macro_keys.len(), 6);
+        while let Some(key) = editor.next_macro_key() {
+            editor.process_key(&key);
+        }
+        assert_row_chars_equal(&editor, &[b"#a", b"#b", b"#c", b"d"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (1, 3));
+
+        editor.play_macro("no_such_macro");
+        assert_eq!(
+            editor.status_msg.map(|msg| msg.msg).as_deref(),
+            Some("No such macro: no_such_macro")
+        );
+        assert!(editor.macro_keys.is_empty());
+    }
+
+    #[test]
+    fn editor_play_macro_past_the_screen() {
+        let mut editor = Editor { screen_rows: 2, ..Default::default() };
+        editor.insert_text((0, 0), b"0\n1\n2\n3\n4\n5\n6\n7\n8\n9", false);
+        (editor.cursor.x, editor.cursor.y) = (0, 0);
+        editor.last_macro = vec![Key::Arrow(AKey::Down); 3];
+        editor.last_macro.push(Key::PageDown);
+        editor.play_macro("");
+        while let Some(key) = editor.next_macro_key() {
+            editor.process_key(&key);
+        }
+        // After moving down, the screen scrolled to rows 2-3 before Page Down
+        assert_eq!((editor.cursor.roff, editor.cursor.y), (2, 5));
+    }
+
+    fn type_vi_keys(editor: &mut Editor, keys: &str) {
+        for key in Key::parse_keys(keys).expect("The keys are valid") {
+            assert!(!editor.process_key(&key));
+        }
+    }
+
+    #[test]
+    fn editor_vi_keymap() {
+        let mut editor = Editor::default();
+        editor.config.keymap = Keymap::Vi;
+        editor.insert_text((0, 0), b"one two three\n  four five\nsix", false);
+        (editor.cursor.x, editor.cursor.y) = (0, 0);
+        for (keys, position) in [("w", (4, 0)), ("e", (6, 0)), ("b", (4, 0)), ("$", (13, 0))] {
+            type_vi_keys(&mut editor, keys);
+            assert_eq!((editor.cursor.x, editor.cursor.y), position, "after {keys}");
+        }
+        for (keys, position) in
+            [("0j", (0, 1)), ("www", (0, 2)), ("gg", (0, 0)), ("G", (0, 2)), ("1G", (0, 0))]
+        {
+            type_vi_keys(&mut editor, keys);
+            assert_eq!((editor.cursor.x, editor.cursor.y), position, "after {keys}");
+        }
+
+        // Operators with counts, and repeating the last change with `.`
+        type_vi_keys(&mut editor, "2dw");
+        assert_row_chars_equal(&editor, &[b"three", b"  four five", b"six"]);
+        type_vi_keys(&mut editor, "j0wcwFOUR<Esc>w.");
+        assert_row_chars_equal(&editor, &[b"three", b"  FOUR FOUR", b"six"]);
+        assert_eq!((editor.cursor.x, editor.cursor.y), (10, 1));
+        type_vi_keys(&mut editor, "yyjp");
+        assert_row_chars_equal(&editor, &[b"three", b"  FOUR FOUR", b"six", b"  FOUR FOUR"]);
+        type_vi_keys(&mut editor, "gg2dd");
+        assert_row_chars_equal(&editor, &[b"six", b"  FOUR FOUR"]);
+        type_vi_keys(&mut editor, "u");
+        assert_row_chars_equal(&editor, &[b"three", b"  FOUR FOUR", b"six", b"  FOUR FOUR"]);
+
+        // Operators on empty text don't copy the current row, and Tab is ignored
+        type_vi_keys(&mut editor, "G$dl0X<Tab>");
+        assert_row_chars_equal(&editor, &[b"three", b"  FOUR FOUR", b"six", b"  FOUR FOUR"]);
+        assert_eq!(
+            editor.kill_ring.get(0).map(|clip| &clip.text[..]),
+            Some(&b"three\n  FOUR FOUR"[..])
+        );
+
+        // The visual mode, shown in the status bar
+        (editor.cursor.x, editor.cursor.y) = (0, 2);
+        type_vi_keys(&mut editor, "vl<Tab>");
+        assert_eq!(editor.vi.mode, vi::Mode::Visual);
+        assert_eq!(editor.selected_rx_ranges(2), vec![0..2]);
+        type_vi_keys(&mut editor, "d");
+        assert_row_chars_equal(&editor, &[b"three", b"  FOUR FOUR", b"x", b"  FOUR FOUR"]);
+        assert_eq!(editor.vi.mode, vi::Mode::Normal);
+        type_vi_keys(&mut editor, "Oab<Esc>");
+        assert_row_chars_equal(&editor, &[b"three", b"  FOUR FOUR", b"ab", b"x", b"  FOUR FOUR"]);
+
+        // Commands of the prompt
+        type_vi_keys(&mut editor, ":5:3<CR>");
+        assert_eq!((editor.cursor.x, editor.cursor.y), (2, 4));
+        type_vi_keys(&mut editor, ":/OU<CR>");
+        assert_eq!((editor.cursor.x, editor.cursor.y), (3, 1));
+        type_vi_keys(&mut editor, ":q<CR>");
+        let quit = editor.next_macro_key().map(|key| editor.process_key(&key));
+        assert_eq!(quit, Some(false));
+        type_vi_keys(&mut editor, ":q!<CR>");
+        let quit = editor.next_macro_key().map(|key| editor.process_key(&key));
+        assert_eq!(quit, Some(true));
     }
 }
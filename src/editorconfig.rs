@@ -0,0 +1,300 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # `.editorconfig` files
+//!
+//! Projects can describe their coding style in `.editorconfig` files (see
+//! <https://editorconfig.org>). These files are looked up from the directory
+//! of the opened file up to the root of the file system, stopping at a file
+//! that sets `root = true`. The properties of the sections whose glob matches
+//! the opened file are applied on top of the configuration, the files closest
+//! to the opened file taking precedence.
+
+use std::collections::BTreeMap;
+use std::{num::NonZeroUsize, path::Path};
+
+use crate::config::process_ini_sections;
+use crate::{encoding::Encoding, line_ending::LineEnding};
+
+/// The properties of the `.editorconfig` files that apply to a file. A
+/// property is `None` if it is not set.
+#[derive(Default)]
+#[cfg_attr(test, derive(Debug, PartialEq, Eq))]
+pub struct Properties {
+    /// Whether to indent with spaces (`indent_style`)
+    pub expand_tab: Option<bool>,
+    /// The number of columns per indentation level (`indent_size`)
+    pub indent_width: Option<NonZeroUsize>,
+    /// The size of a tab (`tab_width`, or else `indent_size`)
+    pub tab_stop: Option<NonZeroUsize>,
+    /// The line endings (`end_of_line`)
+    pub line_ending: Option<LineEnding>,
+    /// The encoding (`charset`)
+    pub encoding: Option<Encoding>,
+    /// Whether to trim the trailing whitespace of the rows when saving
+    /// (`trim_trailing_whitespace`)
+    pub trim_trailing_whitespace: Option<bool>,
+    /// Whether to end the file with a new line when saving
+    /// (`insert_final_newline`)
+    pub insert_final_newline: Option<bool>,
+}
+
+impl Properties {
+    /// Find the `.editorconfig` files that apply to the file at `path`, and
+    /// return the properties they set for it. Invalid values are ignored, as
+    /// well as the properties set to `unset`.
+    pub fn find(path: &Path) -> Self {
+        let Ok(path) = std::path::absolute(path) else { return Self::default() };
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let (file, mut root, mut pairs) = (dir.join(".editorconfig"), false, Vec::new());
+            if !file.is_file() {
+                continue;
+            }
+            // The path of the file relative to the directory, with `/` as separator
+            let rel_path = path.strip_prefix(dir).unwrap_or(&path).iter();
+            let rel_path: Vec<_> = rel_path.map(|part| part.to_string_lossy()).collect();
+            let rel_path = format!("/{}", rel_path.join("/"));
+            process_ini_sections(&file, &mut |section, key, value| {
+                let (key, value) = (key.to_ascii_lowercase(), value.to_ascii_lowercase());
+                match section {
+                    None => root |= key == "root" && value == "true",
+                    Some(glob) if section_matches(glob, &rel_path) => pairs.push((key, value)),
+                    Some(_) => (),
+                }
+                Ok(())
+            });
+            files.push(pairs);
+            if root {
+                break;
+            }
+        }
+        let mut values = BTreeMap::new();
+        for (key, value) in files.into_iter().rev().flatten() {
+            if value == "unset" {
+                values.remove(&key);
+            } else {
+                values.insert(key, value);
+            }
+        }
+        Self::from_values(&values)
+    }
+
+    /// Return the properties given their values, by name.
+    fn from_values(values: &BTreeMap<String, String>) -> Self {
+        let get = |key: &str| values.get(key).map(String::as_str);
+        let size = |key: &str| get(key)?.parse::<NonZeroUsize>().ok();
+        let tab_stop = size("tab_width").or_else(|| size("indent_size"));
+        let indent_width =
+            if get("indent_size") == Some("tab") { tab_stop } else { size("indent_size") };
+        let encoding = get("charset").and_then(|charset| match charset {
+            "utf-8-bom" => Some(Encoding::Utf8 { bom: true }),
+            "utf-16le" => Some(Encoding::Utf16Le { bom: true }),
+            "utf-16be" => Some(Encoding::Utf16Be { bom: true }),
+            charset => Encoding::from_name(charset),
+        });
+        Self {
+            expand_tab: get("indent_style").and_then(|style| match style {
+                "space" => Some(true),
+                "tab" => Some(false),
+                _ => None,
+            }),
+            indent_width,
+            tab_stop,
+            line_ending: get("end_of_line").and_then(LineEnding::from_name),
+            encoding,
+            trim_trailing_whitespace: get("trim_trailing_whitespace").and_then(|v| v.parse().ok()),
+            insert_final_newline: get("insert_final_newline").and_then(|v| v.parse().ok()),
+        }
+    }
+}
+
+/// Return whether the glob of a section matches a path, which starts with `/`
+/// and is relative to the directory of the `.editorconfig` file. A glob
+/// without `/` matches the file name in any directory.
+fn section_matches(glob: &str, path: &str) -> bool {
+    let glob = match glob.strip_prefix('/') {
+        Some(glob) => format!("/{glob}"),
+        None if glob.contains('/') => format!("/{glob}"),
+        None => format!("**/{glob}"),
+    };
+    let (glob, path): (Vec<_>, Vec<_>) = (glob.chars().collect(), path.chars().collect());
+    glob_matches(&glob, &path)
+}
+
+/// Return whether a glob matches a path. `*` matches any characters except
+/// `/`, `**` matches any characters, `?` matches one character except `/`,
+/// `[abc]`, `[a-z]` and `[!abc]` match one character from a set (or not from
+/// a set), `{s1,s2}` matches one of the comma-separated patterns and
+/// `{n1..n2}` matches an integer between `n1` and `n2`.
+fn glob_matches(glob: &[char], path: &[char]) -> bool {
+    match glob {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| glob_matches(rest, &path[i..])),
+        ['*', rest @ ..] => {
+            let n = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+            (0..=n).any(|i| glob_matches(rest, &path[i..]))
+        }
+        ['?', rest @ ..] =>
+            path.first().is_some_and(|c| *c != '/') && glob_matches(rest, &path[1..]),
+        ['[', rest @ ..] if let Some(end) = rest.iter().position(|c| *c == ']') => {
+            let (set, negated) = match &rest[..end] {
+                ['!', set @ ..] => (set, true),
+                set => (set, false),
+            };
+            path.first().is_some_and(|c| *c != '/' && in_set(set, *c) != negated)
+                && glob_matches(&rest[end + 1..], &path[1..])
+        }
+        ['{', rest @ ..] if let Some(end) = closing_brace(rest) => {
+            let (inner, rest) = (&rest[..end], &rest[end + 1..]);
+            if let Some(range) = int_range(inner) {
+                // Try the longest integer first
+                let n = path.iter().take_while(|c| c.is_ascii_digit() || **c == '-').count();
+                return (1..=n).rev().any(|i| {
+                    let int: String = path[..i].iter().collect();
+                    int.parse().is_ok_and(|int| range.contains(&int))
+                        && glob_matches(rest, &path[i..])
+                });
+            }
+            let alternatives = split_alternatives(inner);
+            if alternatives.len() < 2 {
+                // A single pattern in braces is matched literally
+                return path.first() == Some(&'{') && glob_matches(&glob[1..], &path[1..]);
+            }
+            alternatives.into_iter().any(|alt| glob_matches(&[alt, rest].concat(), path))
+        }
+        ['\\', c, rest @ ..] | [c, rest @ ..] =>
+            path.first() == Some(c) && glob_matches(rest, &path[1..]),
+    }
+}
+
+/// Return whether a character belongs to a set such as `abc` or `a-z0-9`.
+fn in_set(set: &[char], c: char) -> bool {
+    match set {
+        [] => false,
+        [lo, '-', hi, rest @ ..] => (*lo..=*hi).contains(&c) || in_set(rest, c),
+        [x, rest @ ..] => *x == c || in_set(rest, c),
+    }
+}
+
+/// Return the index of the brace that closes a brace, given the characters
+/// that follow it.
+fn closing_brace(glob: &[char]) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in glob.iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Split the inside of braces at the commas that are not in nested braces.
+fn split_alternatives(inner: &[char]) -> Vec<&[char]> {
+    let (mut alternatives, mut depth, mut start) = (Vec::new(), 0usize, 0);
+    for (i, c) in inner.iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                alternatives.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    alternatives.push(&inner[start..]);
+    alternatives
+}
+
+/// Parse the inside of braces as a range of integers, such as `1..10`.
+fn int_range(inner: &[char]) -> Option<std::ops::RangeInclusive<i64>> {
+    let inner: String = inner.iter().collect();
+    let (start, end) = inner.split_once("..")?;
+    Some(start.parse().ok()?..=end.parse().ok()?)
+}
+
+#[cfg(test)]
+#[cfg(not(target_family = "wasm"))] // No filesystem on wasm
+mod tests {
+    use std::fs;
+
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[rstest]
+    #[case("*", "/a.rs", true)]
+    #[case("*.rs", "/src/a.rs", true)]
+    #[case("*.rs", "/src/a.rsx", false)]
+    #[case("src/*.rs", "/src/a.rs", true)]
+    #[case("src/*.rs", "/lib/src/a.rs", false)]
+    #[case("/src/*.rs", "/src/a/b.rs", false)]
+    #[case("src/**.rs", "/src/a/b.rs", true)]
+    #[case("a?c", "/abc", true)]
+    #[case("a?c", "/d/a/c", false)]
+    #[case("*.[ch]", "/a.h", true)]
+    #[case("*.[!ch]", "/a.h", false)]
+    #[case("[a-c]x", "/bx", true)]
+    #[case("*.{js,ts}", "/a.ts", true)]
+    #[case("*.{js,ts}", "/a.rs", false)]
+    #[case("{Makefile,*.{mk,mak}}", "/rules.mak", true)]
+    #[case("{single}", "/{single}", true)]
+    #[case("file{1..12}", "/file10", true)]
+    #[case("file{1..12}", "/file13", false)]
+    #[case("\\*.rs", "/a.rs", false)]
+    fn section_glob(#[case] glob: &str, #[case] path: &str, #[case] expected: bool) {
+        assert_eq!(section_matches(glob, path), expected);
+    }
+
+    #[test]
+    fn find_properties() {
+        let tmp_dir = TempDir::new().expect("Could not create temporary directory");
+        let dir = tmp_dir.path().join("project");
+        fs::create_dir_all(dir.join("src")).expect("Could not create directory");
+        let write = |path: &Path, s: &str| fs::write(path, s).expect("Could not write file");
+        // This file is ignored, since the next one is a root file
+        write(&tmp_dir.path().join(".editorconfig"), "[*]\nend_of_line = cr\n");
+        write(
+            &dir.join(".editorconfig"),
+            "root = true\n[*]\nindent_style = space\nindent_size = 4\nend_of_line = CRLF\ncharset \
+             = utf-8-bom\ninsert_final_newline = true\n[*.md]\ntrim_trailing_whitespace = \
+             false\n[Makefile]\nindent_style = tab\n",
+        );
+        write(
+            &dir.join("src/.editorconfig"),
+            "[*.rs]\nindent_size = tab\ntab_width = 8\ncharset = unset\n",
+        );
+
+        let n = |n| NonZeroUsize::new(n);
+        assert_eq!(Properties::find(&dir.join("src/main.rs")), Properties {
+            expand_tab: Some(true),
+            indent_width: n(8),
+            tab_stop: n(8),
+            line_ending: Some(LineEnding::CrLf),
+            encoding: None,
+            trim_trailing_whitespace: None,
+            insert_final_newline: Some(true),
+        });
+        assert_eq!(Properties::find(&dir.join("Makefile")), Properties {
+            expand_tab: Some(false),
+            indent_width: n(4),
+            tab_stop: n(4),
+            line_ending: Some(LineEnding::CrLf),
+            encoding: Some(Encoding::Utf8 { bom: true }),
+            trim_trailing_whitespace: None,
+            insert_final_newline: Some(true),
+        });
+        let readme = Properties::find(&dir.join("README.md"));
+        assert_eq!(readme.trim_trailing_whitespace, Some(false));
+        assert_eq!(Properties::find(&tmp_dir.path().join("a.txt")), Properties {
+            line_ending: Some(LineEnding::Cr),
+            ..Properties::default()
+        });
+    }
+}
@@ -0,0 +1,238 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Encodings
+//!
+//! The text of a buffer is edited as UTF-8. Files encoded in UTF-16 or in
+//! Windows-1252 (a superset of Latin-1) are detected when they are loaded,
+//! decoded to UTF-8, and encoded back when they are saved. A byte order mark
+//! (BOM) is kept if the file had one.
+
+use std::io::{self, ErrorKind};
+
+/// The characters mapped to bytes 0x80 to 0x9F in Windows-1252. The bytes that
+/// are not defined are mapped to the control characters with the same value,
+/// so that any byte can be decoded and encoded back.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// The names accepted for Windows-1252, in addition to its own.
+const LATIN_1_NAMES: [&str; 4] = ["latin-1", "latin1", "iso-8859-1", "cp1252"];
+
+/// The encoding of a file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Encoding {
+    /// UTF-8, with or without a byte order mark. Invalid UTF-8 is kept as is.
+    Utf8 { bom: bool },
+    /// UTF-16, little endian
+    Utf16Le { bom: bool },
+    /// UTF-16, big endian
+    Utf16Be { bom: bool },
+    /// Windows-1252, which is also used for Latin-1 files
+    Windows1252,
+}
+
+impl Default for Encoding {
+    fn default() -> Self { Self::Utf8 { bom: false } }
+}
+
+impl Encoding {
+    /// All the encodings.
+    pub const ALL: [Self; 7] = [
+        Self::Utf8 { bom: false },
+        Self::Utf8 { bom: true },
+        Self::Utf16Le { bom: true },
+        Self::Utf16Le { bom: false },
+        Self::Utf16Be { bom: true },
+        Self::Utf16Be { bom: false },
+        Self::Windows1252,
+    ];
+
+    /// Return the name of the encoding, as shown in the status bar.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Utf8 { bom: false } => "UTF-8",
+            Self::Utf8 { bom: true } => "UTF-8 BOM",
+            Self::Utf16Le { bom: true } => "UTF-16LE BOM",
+            Self::Utf16Le { bom: false } => "UTF-16LE",
+            Self::Utf16Be { bom: true } => "UTF-16BE BOM",
+            Self::Utf16Be { bom: false } => "UTF-16BE",
+            Self::Windows1252 => "Windows-1252",
+        }
+    }
+
+    /// Return the encoding with the given name, ignoring case. Latin-1 is
+    /// accepted as a name for Windows-1252.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        if LATIN_1_NAMES.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            return Some(Self::Windows1252);
+        }
+        Self::ALL.into_iter().find(|enc| enc.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Return the byte order mark written at the start of the file.
+    const fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8 { bom: true } => b"\xef\xbb\xbf",
+            Self::Utf16Le { bom: true } => b"\xff\xfe",
+            Self::Utf16Be { bom: true } => b"\xfe\xff",
+            Self::Utf8 { bom: false }
+            | Self::Utf16Le { bom: false }
+            | Self::Utf16Be { bom: false }
+            | Self::Windows1252 => b"",
+        }
+    }
+
+    /// Detect the encoding of `bytes`: from the byte order mark if there is
+    /// one, then UTF-16 if zero bytes suggest it, then UTF-8 unless the bytes
+    /// that are not valid UTF-8 outnumber the valid non-ASCII characters, in
+    /// which case Windows-1252 is used.
+    pub fn detect(bytes: &[u8]) -> Self {
+        let with_bom =
+            [Self::Utf8 { bom: true }, Self::Utf16Le { bom: true }, Self::Utf16Be { bom: true }];
+        if let Some(enc) = with_bom.into_iter().find(|enc| bytes.starts_with(enc.bom())) {
+            return enc;
+        }
+        // In UTF-16 text made mostly of ASCII characters, every other byte is 0
+        let zeros = |parity| bytes.iter().skip(parity).step_by(2).filter(|b| **b == 0).count();
+        let (n_pairs, even, odd) = (bytes.len() / 2, zeros(0), zeros(1));
+        if bytes.len().is_multiple_of(2) && n_pairs > 0 {
+            if even == 0 && odd * 2 >= n_pairs {
+                return Self::Utf16Le { bom: false };
+            } else if odd == 0 && even * 2 >= n_pairs {
+                return Self::Utf16Be { bom: false };
+            }
+        }
+        // A few stray bytes in UTF-8 text are kept as is
+        let (mut valid, mut invalid) = (0, 0);
+        for chunk in bytes.utf8_chunks() {
+            valid += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+            invalid += chunk.invalid().len();
+        }
+        if invalid > valid { Self::Windows1252 } else { Self::Utf8 { bom: false } }
+    }
+
+    /// Decode `bytes` to UTF-8, removing the byte order mark. Return the text,
+    /// and whether some bytes could not be decoded and were replaced with
+    /// U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> (Vec<u8>, bool) {
+        let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+        let to_u16: fn([u8; 2]) -> u16 = match self {
+            Self::Utf8 { .. } => return (bytes.to_vec(), false),
+            Self::Utf16Le { .. } => u16::from_le_bytes,
+            Self::Utf16Be { .. } => u16::from_be_bytes,
+            Self::Windows1252 => {
+                let text: String = bytes.iter().map(|b| decode_windows_1252(*b)).collect();
+                return (text.into_bytes(), false);
+            }
+        };
+        let (units, rest) = bytes.as_chunks::<2>();
+        let (mut text, mut invalid) = (String::with_capacity(bytes.len()), !rest.is_empty());
+        for c in char::decode_utf16(units.iter().map(|unit| to_u16(*unit))) {
+            invalid |= c.is_err();
+            text.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+        if !rest.is_empty() {
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+        (text.into_bytes(), invalid)
+    }
+
+    /// Encode UTF-8 `text`, with the byte order mark if there is one. Fail if
+    /// the text is not valid UTF-8 or cannot be represented in the encoding.
+    pub fn encode(self, text: Vec<u8>) -> Result<Vec<u8>, io::Error> {
+        let name = self.name();
+        let invalid = |msg: String| io::Error::new(ErrorKind::InvalidData, msg);
+        let mut bytes = self.bom().to_vec();
+        if let Self::Utf8 { .. } = self {
+            bytes.extend(text);
+            return Ok(bytes);
+        }
+        let s = str::from_utf8(&text)
+            .map_err(|_| invalid(format!("Invalid UTF-8 can't be encoded in {name}")))?;
+        match self {
+            Self::Utf16Le { .. } => bytes.extend(s.encode_utf16().flat_map(u16::to_le_bytes)),
+            Self::Utf16Be { .. } => bytes.extend(s.encode_utf16().flat_map(u16::to_be_bytes)),
+            Self::Utf8 { .. } | Self::Windows1252 =>
+                for c in s.chars() {
+                    let err = || invalid(format!("{c:?} can't be encoded in {name}"));
+                    bytes.push(encode_windows_1252(c).ok_or_else(err)?);
+                },
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decode a byte encoded in Windows-1252.
+fn decode_windows_1252(b: u8) -> char {
+    match b {
+        0x80..=0x9f => WINDOWS_1252_HIGH[usize::from(b - 0x80)],
+        _ => char::from(b),
+    }
+}
+
+/// Encode a character in Windows-1252, if it can be represented.
+fn encode_windows_1252(c: char) -> Option<u8> {
+    match u8::try_from(c) {
+        Ok(b @ (..0x80 | 0xa0..)) => Some(b),
+        _ => WINDOWS_1252_HIGH.iter().zip(0x80..).find(|(h, _)| **h == c).map(|(_, b)| b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(b"", Encoding::Utf8 { bom: false }, "")]
+    #[case("Kibi é\n".as_bytes(), Encoding::Utf8 { bom: false }, "Kibi é\n")]
+    #[case(b"\xef\xbb\xbfa", Encoding::Utf8 { bom: true }, "a")]
+    #[case(b"\xff\xfea\x00\xe9\x00=\xd8\x00\xde", Encoding::Utf16Le { bom: true }, "aé😀")]
+    #[case(b"\xfe\xff\x00a\x00\n", Encoding::Utf16Be { bom: true }, "a\n")]
+    #[case(b"K\x00i\x00b\x00i\x00", Encoding::Utf16Le { bom: false }, "Kibi")]
+    #[case(b"\x00K\x00i\x00b\x00i", Encoding::Utf16Be { bom: false }, "Kibi")]
+    #[case(b"caf\xe9 \x80\x81", Encoding::Windows1252, "café €\u{81}")]
+    fn detect_decode_and_encode(
+        #[case] bytes: &[u8], #[case] encoding: Encoding, #[case] text: &str,
+    ) {
+        assert_eq!(Encoding::detect(bytes), encoding);
+        let (decoded, invalid) = encoding.decode(bytes);
+        assert_eq!(
+            (String::from_utf8(decoded.clone()).ok().as_deref(), invalid),
+            (Some(text), false)
+        );
+        assert_eq!(encoding.encode(decoded).ok().as_deref(), Some(bytes));
+    }
+
+    #[test]
+    fn invalid_text() {
+        // Invalid UTF-8 is kept as is
+        let enc = Encoding::detect(b"\xef\xbb\xbfa\xff");
+        assert_eq!(enc.decode(b"\xef\xbb\xbfa\xff"), (b"a\xff".to_vec(), false));
+        // A stray byte in UTF-8 text does not make it Windows-1252
+        assert_eq!(Encoding::detect(b"\xc3\xa9 \xc3\xa8 \xff"), Encoding::Utf8 { bom: false });
+        assert_eq!(Encoding::detect(b"\xc3\xa9 \xe8 \xff"), Encoding::Windows1252);
+        let (text, invalid) = Encoding::Utf16Le { bom: true }.decode(b"\xff\xfe\x00\xd8a");
+        assert_eq!(
+            (String::from_utf8(text).ok(), invalid),
+            (Some(String::from("\u{fffd}\u{fffd}")), true)
+        );
+        let error_kind = |r: Result<_, io::Error>| r.err().map(|e| e.kind());
+        assert_eq!(
+            error_kind(Encoding::Windows1252.encode("😀".into())),
+            Some(ErrorKind::InvalidData)
+        );
+        let utf16 = Encoding::Utf16Be { bom: false };
+        assert_eq!(error_kind(utf16.encode(b"\xff".to_vec())), Some(ErrorKind::InvalidData));
+        assert_eq!(Encoding::from_name(" latin-1"), Some(Encoding::Windows1252));
+        assert_eq!(Encoding::from_name("utf-16le bom"), Some(Encoding::Utf16Le { bom: true }));
+        assert_eq!(Encoding::from_name("utf-32"), None);
+    }
+}
@@ -0,0 +1,240 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # History
+//!
+//! Undo / redo history. Edits are recorded as insertions or deletions of text
+//! at a given position, and are grouped into changes that are undone or redone
+//! at once.
+
+use crate::editor::CursorState;
+
+/// An edit operation. The position is given as `(x, y)`, where `x` is a byte
+/// index in row `y`. The text may contain new lines.
+#[derive(Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq, Eq))]
+pub enum Edit {
+    /// Text inserted at the given position.
+    Insert((usize, usize), Vec<u8>),
+    /// Text deleted, starting at the given position.
+    Delete((usize, usize), Vec<u8>),
+}
+
+impl Edit {
+    /// Return the edit that reverts this edit.
+    fn inverse(&self) -> Self {
+        match self {
+            Self::Insert(pos, text) => Self::Delete(*pos, text.clone()),
+            Self::Delete(pos, text) => Self::Insert(*pos, text.clone()),
+        }
+    }
+}
+
+/// Return the position right after `text`, if `text` starts at position
+/// `(x, y)`.
+pub fn end_pos((x, y): (usize, usize), text: &[u8]) -> (usize, usize) {
+    let mut lines = text.split(|b| *b == b'\n');
+    let first_line_len = lines.next().map_or(0, <[u8]>::len);
+    lines.fold((x + first_line_len, y), |(_, y), line| (line.len(), y + 1))
+}
+
+/// Return the position where `pos` is moved to after `edit` is applied. A
+/// position where text is inserted is moved after the inserted text, and a
+/// position inside deleted text is moved to the start of the deletion.
+pub fn shift_pos(pos: (usize, usize), edit: &Edit) -> (usize, usize) {
+    let before = |(x0, y0): (usize, usize), (x1, y1): (usize, usize)| (y0, x0) <= (y1, x1);
+    match edit {
+        Edit::Insert(start, _) if before(pos, *start) && pos != *start => pos,
+        Edit::Delete(start, _) if before(pos, *start) => pos,
+        Edit::Delete(start, text) if before(pos, end_pos(*start, text)) => *start,
+        Edit::Insert(start, text) => {
+            let end = end_pos(*start, text);
+            if pos.1 == start.1 {
+                (end.0 + pos.0 - start.0, end.1)
+            } else {
+                (pos.0, pos.1 + end.1 - start.1)
+            }
+        }
+        Edit::Delete(start, text) => {
+            let end = end_pos(*start, text);
+            if pos.1 == end.1 {
+                (start.0 + pos.0 - end.0, start.1)
+            } else {
+                (pos.0, pos.1 - (end.1 - start.1))
+            }
+        }
+    }
+}
+
+/// A group of edits that are undone or redone at once.
+struct Change {
+    /// The edits, in the order they were applied.
+    edits: Vec<Edit>,
+    /// The cursor state before the change, restored when it is undone.
+    cursor: CursorState,
+    /// Unique identifier, used to know whether the buffer is in its saved
+    /// state.
+    id: usize,
+}
+
+/// The undo / redo history of a document.
+#[derive(Default)]
+pub struct History {
+    /// Changes that can be undone, the most recent one last.
+    undo: Vec<Change>,
+    /// Changes that can be redone, the most recently undone one last.
+    redo: Vec<Change>,
+    /// Whether the next typed character can be merged into the last change.
+    mergeable: bool,
+    /// The number of changes created so far, used to generate identifiers.
+    n_changes: usize,
+    /// The identifier of the last change when the document was saved, 0 if the
+    /// document was saved before any change.
+    saved: usize,
+}
+
+impl History {
+    /// Record an edit, given the cursor state before the edit. If `typing` is
+    /// true and the last change is made of typed characters that end where
+    /// this edit starts, the edit is merged into the last change.
+    pub fn push(&mut self, edit: Edit, cursor: &CursorState, typing: bool) {
+        self.redo.clear();
+        if let (true, Some(change), Edit::Insert(pos, text)) =
+            (typing && self.mergeable, self.undo.last_mut(), &edit)
+            && let Some(Edit::Insert(last_pos, last_text)) = change.edits.last_mut()
+            && end_pos(*last_pos, last_text) == *pos
+        {
+            last_text.extend(text);
+            return;
+        }
+        self.n_changes += 1;
+        self.undo.push(Change { edits: vec![edit], cursor: cursor.clone(), id: self.n_changes });
+        self.mergeable = typing;
+    }
+
+    /// Stop merging typed characters into the last change, e.g. when the
+    /// cursor moves or another command is run between them.
+    pub const fn break_merge(&mut self) { self.mergeable = false; }
+
+    /// Return a checkpoint that can be given to `merge_since`.
+    pub const fn checkpoint(&self) -> usize { self.undo.len() }
+
+    /// Merge the changes recorded since `checkpoint` was returned into a single
+    /// change, for instance when the same edit is made at several cursors.
+    pub fn merge_since(&mut self, checkpoint: usize) {
+        let mut changes = self.undo.split_off(checkpoint.min(self.undo.len())).into_iter();
+        if let Some(mut change) = changes.next() {
+            for next in changes {
+                (change.edits, change.id) = ([change.edits, next.edits].concat(), next.id);
+            }
+            self.undo.push(change);
+        }
+    }
+
+    /// Undo the last change. Return the edits to apply, in order, and the
+    /// cursor state to restore.
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, CursorState)> {
+        let change = self.undo.pop()?;
+        let edits = change.edits.iter().rev().map(Edit::inverse).collect();
+        let cursor = change.cursor.clone();
+        self.redo.push(change);
+        self.mergeable = false;
+        Some((edits, cursor))
+    }
+
+    /// Redo the last undone change. Return the edits to apply, in order.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        let change = self.redo.pop()?;
+        let edits = change.edits.clone();
+        self.undo.push(change);
+        self.mergeable = false;
+        Some(edits)
+    }
+
+    /// Mark the current state as saved.
+    pub fn mark_saved(&mut self) {
+        (self.saved, self.mergeable) = (self.undo.last().map_or(0, |c| c.id), false);
+    }
+
+    /// Return whether the document is in the state it was last saved in.
+    pub fn is_saved(&self) -> bool { self.undo.last().map_or(0, |c| c.id) == self.saved }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case((3, 2), b"", (3, 2))]
+    #[case((3, 2), b"abc", (6, 2))]
+    #[case((3, 2), b"\n", (0, 3))]
+    #[case((3, 2), b"ab\ncd\nef", (2, 4))]
+    fn end_position(
+        #[case] pos: (usize, usize), #[case] text: &[u8], #[case] expected: (usize, usize),
+    ) {
+        assert_eq!(end_pos(pos, text), expected);
+    }
+
+    #[rstest]
+    #[case::before_insertion((1, 2), Edit::Insert((3, 2), b"a\nb".to_vec()), (1, 2))]
+    #[case::at_insertion((3, 2), Edit::Insert((3, 2), b"a\nb".to_vec()), (1, 3))]
+    #[case::after_insertion((5, 2), Edit::Insert((3, 2), b"a\nb".to_vec()), (3, 3))]
+    #[case::row_after_insertion((5, 4), Edit::Insert((3, 2), b"a\nb".to_vec()), (5, 5))]
+    #[case::before_deletion((3, 2), Edit::Delete((3, 2), b"a\nb".to_vec()), (3, 2))]
+    #[case::inside_deletion((0, 3), Edit::Delete((3, 2), b"a\nb".to_vec()), (3, 2))]
+    #[case::after_deletion((4, 3), Edit::Delete((3, 2), b"a\nb".to_vec()), (6, 2))]
+    #[case::row_after_deletion((4, 5), Edit::Delete((3, 2), b"a\nb".to_vec()), (4, 4))]
+    fn shifted_position(
+        #[case] pos: (usize, usize), #[case] edit: Edit, #[case] expected: (usize, usize),
+    ) {
+        assert_eq!(shift_pos(pos, &edit), expected);
+    }
+
+    #[test]
+    fn changes_are_merged_since_checkpoint() {
+        let (mut history, cursor) = (History::default(), CursorState::default());
+        history.push(Edit::Insert((0, 0), b"a".to_vec()), &cursor, false);
+        let checkpoint = history.checkpoint();
+        history.push(Edit::Insert((0, 1), b"b".to_vec()), &cursor, false);
+        history.push(Edit::Insert((0, 2), b"c".to_vec()), &cursor, false);
+        history.merge_since(checkpoint);
+        let edits = vec![Edit::Delete((0, 2), b"c".to_vec()), Edit::Delete((0, 1), b"b".to_vec())];
+        assert_eq!(history.undo(), Some((edits, cursor.clone())));
+        assert_eq!(history.undo(), Some((vec![Edit::Delete((0, 0), b"a".to_vec())], cursor)));
+    }
+
+    #[test]
+    fn typing_is_merged() {
+        let (mut history, cursor) = (History::default(), CursorState::default());
+        history.push(Edit::Insert((0, 0), b"a".to_vec()), &cursor, true);
+        history.push(Edit::Insert((1, 0), b"b".to_vec()), &cursor, true);
+        history.push(Edit::Insert((5, 0), b"c".to_vec()), &cursor, true);
+        assert_eq!(
+            history.undo(),
+            Some((vec![Edit::Delete((5, 0), b"c".to_vec())], cursor.clone()))
+        );
+        assert_eq!(history.undo(), Some((vec![Edit::Delete((0, 0), b"ab".to_vec())], cursor)));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), Some(vec![Edit::Insert((0, 0), b"ab".to_vec())]));
+    }
+
+    #[test]
+    fn saved_state() {
+        let (mut history, cursor) = (History::default(), CursorState::default());
+        assert!(history.is_saved());
+        history.push(Edit::Insert((0, 0), b"a".to_vec()), &cursor, true);
+        assert!(!history.is_saved());
+        history.mark_saved();
+        history.push(Edit::Insert((1, 0), b"b".to_vec()), &cursor, true);
+        assert!(!history.is_saved());
+        history.undo();
+        assert!(history.is_saved());
+        history.undo();
+        assert!(!history.is_saved());
+        history.redo();
+        assert!(history.is_saved());
+    }
+}
@@ -0,0 +1,147 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Indentation
+//!
+//! Rows are indented either with tabs, or with a number of spaces per level.
+//! The indentation of a file is guessed from its rows when it is loaded, and
+//! the rows can be converted from one indentation to another.
+
+use std::num::NonZeroUsize;
+
+/// The maximum number of spaces per level guessed from the rows of a file.
+const MAX_DETECTED_WIDTH: usize = 8;
+
+/// The indentation of a buffer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Indent {
+    /// One tab per level
+    Tabs,
+    /// A number of spaces per level
+    Spaces(NonZeroUsize),
+}
+
+impl Indent {
+    /// Return the name of the indentation, as shown in the status bar.
+    pub fn name(self) -> String {
+        match self {
+            Self::Tabs => String::from("Tabs"),
+            Self::Spaces(n) => format!("Spaces: {n}"),
+        }
+    }
+
+    /// Parse an indentation written as `tabs` or `spaces [<width>]`. If the
+    /// width is omitted, `default_width` is used.
+    pub fn parse(s: &str, default_width: NonZeroUsize) -> Option<Self> {
+        match s.split_whitespace().collect::<Vec<_>>()[..] {
+            ["tabs"] => Some(Self::Tabs),
+            ["spaces"] => Some(Self::Spaces(default_width)),
+            ["spaces", width] => width.parse().ok().map(Self::Spaces),
+            _ => None,
+        }
+    }
+
+    /// Return the text of one indentation level.
+    pub fn unit(self) -> Vec<u8> {
+        match self {
+            Self::Tabs => vec![b'\t'],
+            Self::Spaces(n) => vec![b' '; n.get()],
+        }
+    }
+
+    /// Return the width of one indentation level on the screen.
+    pub const fn width(self, tab_stop: NonZeroUsize) -> NonZeroUsize {
+        match self {
+            Self::Tabs => tab_stop,
+            Self::Spaces(n) => n,
+        }
+    }
+
+    /// Guess the indentation of `text` from the leading whitespace of its rows:
+    /// tabs if more rows start with a tab than with spaces, and otherwise the
+    /// most frequent difference between the numbers of leading spaces of
+    /// consecutive indented rows. Return `None` if no row is indented.
+    pub fn detect(text: &[u8]) -> Option<Self> {
+        let (mut n_tabs, mut n_spaces, mut widths) = (0, 0, [0; MAX_DETECTED_WIDTH + 1]);
+        let mut previous = 0;
+        for line in text.split(|b| *b == b'\n').filter(|line| !line.trim_ascii().is_empty()) {
+            let spaces = line.iter().take_while(|b| **b == b' ').count();
+            match line.first() {
+                Some(b'\t') => n_tabs += 1,
+                Some(b' ') if line.get(spaces) != Some(&b'\t') => n_spaces += 1,
+                _ => (),
+            }
+            // Differences of one space are usually alignment, e.g. in comments
+            if let diff @ 2..=MAX_DETECTED_WIDTH = spaces.abs_diff(previous) {
+                widths[diff] += 1;
+            }
+            previous = spaces;
+        }
+        let width =
+            (1..=MAX_DETECTED_WIDTH).rev().max_by_key(|n| widths[*n]).filter(|n| widths[*n] > 0);
+        match (n_tabs, n_spaces, width.and_then(NonZeroUsize::new)) {
+            (0, 0, _) => None,
+            (n_tabs, n_spaces, _) if n_tabs > n_spaces => Some(Self::Tabs),
+            (_, _, width) => width.map(Self::Spaces),
+        }
+    }
+
+    /// Convert the leading whitespace of a row, `indent`, to this indentation.
+    /// `from` is the indentation the row uses: the number of levels is kept,
+    /// and the remaining columns are written as spaces.
+    pub fn convert(self, indent: &[u8], from: Self, tab_stop: NonZeroUsize) -> Vec<u8> {
+        let tab = tab_stop.get();
+        let width =
+            indent.iter().fold(0, |w, b| if *b == b'\t' { w - w % tab + tab } else { w + 1 });
+        let from_width = from.width(tab_stop).get();
+        let (levels, rest) = (width / from_width, width % from_width);
+        [self.unit().repeat(levels), vec![b' '; rest]].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn spaces(n: usize) -> Indent { Indent::Spaces(NonZeroUsize::new(n).expect("Invalid width")) }
+
+    #[rstest]
+    #[case("", None)]
+    #[case("a\nb\n", None)]
+    #[case("a {\n\tb\n\t\tc\n}\n", Some(Indent::Tabs))]
+    #[case("a {\n  b {\n    c\n  }\n}\n", Some(spaces(2)))]
+    #[case("a:\n    b\n    c:\n        d\n\n    e\n", Some(spaces(4)))]
+    #[case("/**\n * a\n */\nb {\n    c\n}\n", Some(spaces(4)))]
+    #[case("a\n\tb\n\tc\n  d\n", Some(Indent::Tabs))]
+    #[case("a\n b\n", None)]
+    fn detect(#[case] text: &str, #[case] expected: Option<Indent>) {
+        assert_eq!(Indent::detect(text.as_bytes()), expected);
+    }
+
+    #[rstest]
+    #[case(b"\t\t", Indent::Tabs, spaces(2), b"    ")]
+    #[case(b"\t  ", Indent::Tabs, spaces(4), b"      ")]
+    #[case(b"          ", spaces(4), Indent::Tabs, b"\t\t  ")]
+    #[case(b"  \t", spaces(2), Indent::Tabs, b"\t\t")]
+    #[case(b"    ", spaces(2), spaces(4), b"        ")]
+    fn convert(
+        #[case] indent: &[u8], #[case] from: Indent, #[case] to: Indent, #[case] expected: &[u8],
+    ) {
+        let tab_stop = NonZeroUsize::new(4).expect("4 is not 0");
+        assert_eq!(to.convert(indent, from, tab_stop), expected);
+    }
+
+    #[test]
+    fn parse() {
+        let width = NonZeroUsize::new(4).expect("4 is not 0");
+        assert_eq!(Indent::parse(" tabs ", width), Some(Indent::Tabs));
+        assert_eq!(Indent::parse("spaces", width), Some(spaces(4)));
+        assert_eq!(Indent::parse("spaces 2", width), Some(spaces(2)));
+        assert_eq!(Indent::parse("spaces 0", width), None);
+        assert_eq!(Indent::parse("tabs 2", width), None);
+    }
+}
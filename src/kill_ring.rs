@@ -0,0 +1,95 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Kill ring
+//!
+//! The kill ring keeps the last pieces of text that were cut or copied, so that
+//! older ones can still be pasted after copying new text.
+
+use std::collections::VecDeque;
+
+/// A piece of text that was cut or copied.
+#[derive(Clone, Default, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Clip {
+    /// The text, without the final new line if it is made of whole rows.
+    pub text: Vec<u8>,
+    /// Whether the text is made of whole rows, which are pasted below the
+    /// current row.
+    pub rows: bool,
+    /// Whether the text is a block of columns, whose lines are pasted at the
+    /// same column of successive rows.
+    pub block: bool,
+}
+
+impl Clip {
+    /// Return the text as written to the system clipboard. Whole rows end with
+    /// a new line.
+    pub fn clipboard_text(&self) -> Vec<u8> {
+        [&self.text[..], if self.rows { b"\n" } else { b"" }].concat()
+    }
+}
+
+/// The pieces of text that were cut or copied, most recent first.
+#[derive(Default)]
+pub struct KillRing {
+    clips: VecDeque<Clip>,
+}
+
+impl KillRing {
+    /// Return the number of clips in the ring.
+    pub fn len(&self) -> usize { self.clips.len() }
+
+    /// Return the `i`-th most recent clip, starting from 0.
+    pub fn get(&self, i: usize) -> Option<&Clip> { self.clips.get(i) }
+
+    /// Return the clips, most recent first.
+    pub fn iter(&self) -> impl Iterator<Item=&Clip> { self.clips.iter() }
+
+    /// Add a clip to the ring, unless it is the same as the most recent one.
+    /// The oldest clips are dropped so that at most `capacity` clips are kept.
+    pub fn push(&mut self, clip: Clip, capacity: usize) {
+        if self.clips.front() != Some(&clip) {
+            self.clips.push_front(clip);
+        }
+        self.clips.truncate(capacity.max(1));
+    }
+
+    /// Append a clip to the most recent one, as if both had been cut at once.
+    /// The result is made of whole rows if the appended clip is.
+    pub fn append(&mut self, clip: Clip, capacity: usize) {
+        match self.clips.front_mut() {
+            Some(last) => {
+                last.text = [last.clipboard_text(), clip.text].concat();
+                (last.rows, last.block) = (clip.rows, clip.block);
+            }
+            None => self.push(clip, capacity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(text: &str, rows: bool) -> Clip { Clip { text: text.into(), rows, block: false } }
+
+    #[test]
+    fn push_and_append() {
+        let mut ring = KillRing::default();
+        ring.append(clip("a", true), 3);
+        ring.push(clip("a", true), 3);
+        assert_eq!(ring.len(), 1);
+        ring.append(clip("b", true), 3);
+        ring.append(clip("c", false), 3);
+        assert_eq!(ring.get(0), Some(&clip("a\nb\nc", false)));
+        assert_eq!(ring.get(0).map(Clip::clipboard_text), Some(b"a\nb\nc".to_vec()));
+
+        for text in ["d", "e", "f"] {
+            ring.push(clip(text, true), 3);
+        }
+        assert!(ring.iter().eq(&[clip("f", true), clip("e", true), clip("d", true)]));
+        assert_eq!(ring.get(0).map(Clip::clipboard_text), Some(b"f\n".to_vec()));
+    }
+}
@@ -6,15 +6,32 @@
 //!
 //! Kibi is a text editor in ≤1024 lines of code.
 
-pub use crate::{config::Config, editor::run, error::Error, sys::stdin};
+pub use crate::config::{Config, Keymap};
+pub use crate::{editor::run, error::Error, sys::stdin};
 
 pub mod ansi_escape;
+mod block;
+mod buffer;
+mod clipboard;
 mod config;
 mod editor;
+mod editorconfig;
+mod encoding;
 mod error;
+mod history;
+mod indent;
+mod kill_ring;
+mod line_ending;
+mod macros;
+mod modeline;
+mod pane;
+mod reflow;
+mod rope;
 mod row;
 mod syntax;
 mod terminal;
+mod transform;
+mod vi;
 
 #[cfg_attr(windows, path = "windows.rs")]
 #[cfg_attr(unix, path = "unix.rs")]
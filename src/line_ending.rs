@@ -0,0 +1,136 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Line endings
+//!
+//! The text of a buffer always uses `\n` to separate rows. The line endings of
+//! the file (`\n`, `\r\n` or `\r`) are detected when it is loaded, and written
+//! back when it is saved.
+
+use std::io::{self, Write};
+
+/// The characters that end a line in a file.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum LineEnding {
+    /// `\n`, as on Unix
+    #[default]
+    Lf,
+    /// `\r\n`, as on Windows
+    CrLf,
+    /// `\r`, as on classic Mac OS
+    Cr,
+}
+
+impl LineEnding {
+    /// All the line endings, in the order used to break ties when detecting
+    /// them.
+    pub const ALL: [Self; 3] = [Self::Lf, Self::CrLf, Self::Cr];
+
+    /// Return the name of the line ending, as shown in the status bar.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+            Self::Cr => "CR",
+        }
+    }
+
+    /// Return the characters of the line ending.
+    pub const fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::CrLf => b"\r\n",
+            Self::Cr => b"\r",
+        }
+    }
+
+    /// Return the line ending with the given name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|le| le.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Split `bytes` into lines ending with any of the line endings, and join
+    /// them with `\n`. Return the text, the most frequent line ending, and
+    /// whether several line endings are used.
+    pub fn normalize(bytes: &[u8]) -> (Vec<u8>, Self, bool) {
+        let (mut text, mut counts) = (Vec::with_capacity(bytes.len()), [0usize; 3]);
+        let mut iter = bytes.iter().peekable();
+        while let Some(&b) = iter.next() {
+            let line_ending = match b {
+                b'\n' => Self::Lf,
+                b'\r' if iter.next_if_eq(&&b'\n').is_some() => Self::CrLf,
+                b'\r' => Self::Cr,
+                _ => {
+                    text.push(b);
+                    continue;
+                }
+            };
+            counts[line_ending as usize] += 1;
+            text.push(b'\n');
+        }
+        let most_frequent = Self::ALL.into_iter().rev().max_by_key(|le| counts[*le as usize]);
+        let n_used = counts.iter().filter(|n| **n > 0).count();
+        (text, most_frequent.unwrap_or_default(), n_used > 1)
+    }
+
+    /// Write `chunks` of text, replacing each `\n` with the line ending.
+    /// Return the number of bytes written.
+    pub fn write<W: Write, C: AsRef<[u8]>, I: Iterator<Item=C>>(
+        self, writer: &mut W, chunks: I,
+    ) -> Result<usize, io::Error> {
+        let mut n_bytes = 0;
+        for chunk in chunks {
+            let chunk = chunk.as_ref();
+            if self == Self::Lf {
+                writer.write_all(chunk)?;
+                n_bytes += chunk.len();
+                continue;
+            }
+            for (i, part) in chunk.split(|b| *b == b'\n').enumerate() {
+                if i > 0 {
+                    writer.write_all(self.as_bytes())?;
+                    n_bytes += self.as_bytes().len();
+                }
+                writer.write_all(part)?;
+                n_bytes += part.len();
+            }
+        }
+        Ok(n_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(b"", b"", LineEnding::Lf, false)]
+    #[case(b"a\nb\n", b"a\nb\n", LineEnding::Lf, false)]
+    #[case(b"a\r\nb\r\n", b"a\nb\n", LineEnding::CrLf, false)]
+    #[case(b"a\rb", b"a\nb", LineEnding::Cr, false)]
+    #[case(b"a\r\nb\nc\r\n\r", b"a\nb\nc\n\n", LineEnding::CrLf, true)]
+    #[case(b"a\r\nb\n", b"a\nb\n", LineEnding::Lf, true)]
+    fn normalize(
+        #[case] bytes: &[u8], #[case] text: &[u8], #[case] line_ending: LineEnding,
+        #[case] mixed: bool,
+    ) {
+        assert_eq!(LineEnding::normalize(bytes), (text.to_vec(), line_ending, mixed));
+        let mut written = Vec::new();
+        let n_bytes = line_ending.write(&mut written, text.chunks(3)).expect("Could not write");
+        assert_eq!(n_bytes, written.len());
+        if !mixed {
+            assert_eq!(written, bytes);
+        }
+    }
+
+    #[test]
+    fn from_name() {
+        assert_eq!(LineEnding::from_name(" crlf "), Some(LineEnding::CrLf));
+        assert_eq!(LineEnding::from_name("LF"), Some(LineEnding::Lf));
+        assert_eq!(LineEnding::from_name("lfcr"), None);
+    }
+}
@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Macros
+//!
+//! Named keyboard macros are saved in `macros.ini` files in the data
+//! directories, with one `name=keys` line per macro. The keys are written in a
+//! notation similar to Vim's, for instance `<C-s>` or `<Up>`.
+
+use std::io::{self, ErrorKind};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::config::process_ini_file;
+
+/// The name of the files where macros are saved.
+const FILE_NAME: &str = "macros.ini";
+
+/// Load the macros saved in the data directories, by name. Macros saved in the
+/// first directories take precedence over those saved in the next ones.
+pub fn load(data_dirs: &[String]) -> BTreeMap<String, String> {
+    let mut macros = BTreeMap::new();
+    let paths = data_dirs.iter().map(|dir| Path::new(dir).join(FILE_NAME));
+    for path in paths.filter(|path| path.is_file()).rev() {
+        process_ini_file(&path, &mut |name, keys| {
+            macros.insert(String::from(name), String::from(keys));
+            Ok(())
+        });
+    }
+    macros
+}
+
+/// Save a macro in the first data directory, replacing the macro with the same
+/// name that may have been saved there.
+pub fn save(data_dirs: &[String], name: &str, keys: &str) -> Result<(), io::Error> {
+    let dir = data_dirs.first().ok_or_else(|| io::Error::other("No data directory"))?;
+    let path = Path::new(dir).join(FILE_NAME);
+    let content = match fs::read_to_string(&path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        r => r?,
+    };
+    let mut lines: Vec<_> = (content.lines())
+        .filter(|line| line.split_once('=').is_none_or(|(key, _)| key.trim() != name))
+        .collect();
+    let line = format!("{name}={keys}");
+    lines.push(&line);
+    fs::create_dir_all(dir)?;
+    fs::write(path, lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+#[cfg(not(target_family = "wasm"))] // No filesystem on wasm
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let tmp_dir = tempfile::TempDir::new().expect("Could not create temporary directory");
+        let dirs: Vec<_> = ["user", "system"]
+            .map(|dir| tmp_dir.path().join(dir).to_string_lossy().into_owned())
+            .into();
+        assert!(load(&dirs).is_empty());
+
+        save(&dirs[1..], "a", "<Home>#<Down>").expect("Could not save macro");
+        save(&dirs[1..], "b", "x").expect("Could not save macro");
+        save(&dirs, "b", "y").expect("Could not save macro");
+        save(&dirs, "c", "<C-s>").expect("Could not save macro");
+        save(&dirs, "c", "<C-s><C-q>").expect("Could not save macro");
+
+        let content = fs::read_to_string(Path::new(&dirs[0]).join(FILE_NAME));
+        assert_eq!(content.ok().as_deref(), Some("b=y\nc=<C-s><C-q>\n"));
+        let macros = load(&dirs);
+        let expected = [("a", "<Home>#<Down>"), ("b", "y"), ("c", "<C-s><C-q>")];
+        assert_eq!(macros, expected.map(|(k, v)| (String::from(k), String::from(v))).into());
+        assert!(save(&[], "a", "x").is_err());
+    }
+}
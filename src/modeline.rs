@@ -0,0 +1,145 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Modelines
+//!
+//! A file can set some of its own settings in a modeline, a comment in its
+//! first or last rows written for Vim (e.g. `vim: set ts=2 sw=2 et ft=yaml:`)
+//! or for Emacs (e.g. `-*- mode: python; tab-width: 4 -*-`). Only the tab
+//! size, the indentation and the language are read from modelines.
+
+use std::num::NonZeroUsize;
+
+/// The number of rows at the start and at the end of a file in which modelines
+/// are looked for.
+pub const N_ROWS: usize = 5;
+
+/// The markers that start a Vim modeline.
+const VIM_MARKERS: [&str; 4] = ["vim:", "Vim:", "vi:", "ex:"];
+
+/// The settings found in the modelines of a file. A setting is `None` if no
+/// modeline sets it.
+#[derive(Default)]
+#[cfg_attr(test, derive(Debug, PartialEq, Eq))]
+pub struct Modeline {
+    /// The size of a tab (`ts`, `tabstop`, `tab-width`)
+    pub tab_stop: Option<NonZeroUsize>,
+    /// Whether to indent with spaces (`et`, `expandtab`, `indent-tabs-mode`)
+    pub expand_tab: Option<bool>,
+    /// The number of columns per indentation level (`sw`, `shiftwidth`,
+    /// `*-offset`)
+    pub indent_width: Option<NonZeroUsize>,
+    /// The name of the language (`ft`, `filetype`, `syntax`, `mode`)
+    pub syntax: Option<String>,
+}
+
+impl Modeline {
+    /// Read the modelines in the given rows, the settings of the last rows
+    /// taking precedence.
+    pub fn find<T: AsRef<[u8]>, I: IntoIterator<Item=T>>(rows: I) -> Self {
+        let mut modeline = Self::default();
+        for row in rows {
+            let row = String::from_utf8_lossy(row.as_ref());
+            modeline.parse_vim(&row);
+            modeline.parse_emacs(&row);
+        }
+        modeline
+    }
+
+    /// Read a Vim modeline, either `vim: <options>` where the options are
+    /// separated by spaces or colons, or `vim: set <options>:` where they are
+    /// separated by spaces. The marker must be at the start of the row or
+    /// after a space.
+    fn parse_vim(&mut self, row: &str) {
+        let start = VIM_MARKERS.iter().find_map(|marker| {
+            let i = row.match_indices(marker).map(|(i, _)| i).find(|i| {
+                let before = row[..*i].chars().next_back();
+                before.is_some_and(char::is_whitespace) || (before.is_none() && *marker != "ex:")
+            })?;
+            Some(i + marker.len())
+        });
+        let Some(rest) = start.map(|start| row[start..].trim_start()) else { return };
+        // In `vim: set <options>:`, the options end at the first colon
+        let set = rest.strip_prefix("set ").or_else(|| rest.strip_prefix("se "));
+        let options: Vec<_> = set.map_or_else(
+            || rest.split(|c: char| c == ':' || c.is_whitespace()).collect(),
+            |set| set.split(':').next().unwrap_or_default().split_whitespace().collect(),
+        );
+        for option in options {
+            match option.split_once('=') {
+                Some(("ts" | "tabstop", n)) => self.tab_stop = n.parse().ok().or(self.tab_stop),
+                Some(("sw" | "shiftwidth", n)) =>
+                    self.indent_width = n.parse().ok().or(self.indent_width),
+                Some(("ft" | "filetype" | "syn" | "syntax", name)) =>
+                    self.syntax = Some(String::from(name)),
+                None if option == "et" || option == "expandtab" => self.expand_tab = Some(true),
+                None if option == "noet" || option == "noexpandtab" =>
+                    self.expand_tab = Some(false),
+                _ => (),
+            }
+        }
+    }
+
+    /// Read an Emacs modeline, `-*- <mode> -*-` or `-*- <variable>: <value>;
+    /// ... -*-`.
+    fn parse_emacs(&mut self, row: &str) {
+        let Some((_, rest)) = row.split_once("-*-") else { return };
+        let Some((vars, _)) = rest.split_once("-*-") else { return };
+        if !vars.contains(':') {
+            self.syntax = Some(String::from(vars.trim())).filter(|mode| !mode.is_empty());
+            return;
+        }
+        for (var, value) in vars.split(';').filter_map(|var| var.split_once(':')) {
+            let (var, value) = (var.trim().to_ascii_lowercase(), value.trim());
+            match var.as_str() {
+                "mode" => self.syntax = Some(String::from(value)),
+                "tab-width" => self.tab_stop = value.parse().ok().or(self.tab_stop),
+                "indent-tabs-mode" => self.expand_tab = Some(value == "nil"),
+                var if var.ends_with("-offset") || var.ends_with("-indent-level") =>
+                    self.indent_width = value.parse().ok().or(self.indent_width),
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn modeline(tab: usize, et: Option<bool>, width: usize, syntax: Option<&str>) -> Modeline {
+        Modeline {
+            tab_stop: NonZeroUsize::new(tab),
+            expand_tab: et,
+            indent_width: NonZeroUsize::new(width),
+            syntax: syntax.map(String::from),
+        }
+    }
+
+    #[rstest]
+    #[case("# vim: set ts=2 sw=2 et ft=yaml:", modeline(2, Some(true), 2, Some("yaml")))]
+    #[case("// vim:ts=8:noet", modeline(8, Some(false), 0, None))]
+    #[case("vi: set tabstop=4 shiftwidth=4 expandtab: ts=1", modeline(4, Some(true), 4, None))]
+    #[case("/* ex: syntax=c */", modeline(0, None, 0, Some("c")))]
+    #[case("ex: ts=2", Modeline::default())]
+    #[case("x = nvim: ts=4", Modeline::default())]
+    #[case("#!/usr/bin/env python3 -*- python -*-", modeline(0, None, 0, Some("python")))]
+    #[case(
+        ";; -*- mode: Lisp; tab-width: 8; indent-tabs-mode: nil; lisp-indent-offset: 2 -*-",
+        modeline(8, Some(true), 2, Some("Lisp"))
+    )]
+    #[case("# -*- coding: utf-8 -*-", Modeline::default())]
+    #[case("a -*- b", Modeline::default())]
+    fn find(#[case] row: &str, #[case] expected: Modeline) {
+        assert_eq!(Modeline::find([row]), expected);
+    }
+
+    #[test]
+    fn last_rows_take_precedence() {
+        let rows = ["# vim: ts=2 et", "", "# vim: ts=4"];
+        assert_eq!(Modeline::find(rows), modeline(4, Some(true), 0, None));
+    }
+}
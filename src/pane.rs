@@ -0,0 +1,139 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Panes
+//!
+//! The screen can be split horizontally and vertically into panes, each of
+//! them showing a buffer. The layout is a binary tree whose leaves are the
+//! panes.
+
+use std::mem;
+
+use crate::editor::CursorState;
+
+/// A rectangular part of the screen: `(top, left, height, width)`, where `top`
+/// and `left` are 0-indexed.
+pub type Rect = (usize, usize, usize, usize);
+
+/// A pane, showing a buffer with its own cursor and scroll offsets.
+#[derive(Default, Clone)]
+pub struct Pane {
+    /// The index of the buffer shown in the pane, among all open buffers.
+    pub buf_idx: usize,
+    /// The cursor state of the pane.
+    pub cursor: CursorState,
+    /// The part of the screen used by the pane, including its status bar.
+    pub rect: Rect,
+}
+
+/// The layout of the panes on the screen.
+pub enum Layout {
+    /// A single pane.
+    Pane(Box<Pane>),
+    /// Two layouts, side by side if `vertical` is true, one above the other
+    /// otherwise.
+    Split(bool, Box<[Self; 2]>),
+}
+
+impl Default for Layout {
+    fn default() -> Self { Self::Pane(Box::default()) }
+}
+
+impl Layout {
+    /// Return the panes, from left to right and from top to bottom.
+    pub fn panes(&self) -> Vec<&Pane> {
+        match self {
+            Self::Pane(pane) => vec![pane],
+            Self::Split(_, layouts) => layouts.iter().flat_map(Self::panes).collect(),
+        }
+    }
+
+    /// Return mutable references to the panes, in the same order as `panes`.
+    pub fn panes_mut(&mut self) -> Vec<&mut Pane> {
+        match self {
+            Self::Pane(pane) => vec![pane],
+            Self::Split(_, layouts) => layouts.iter_mut().flat_map(Self::panes_mut).collect(),
+        }
+    }
+
+    /// Return a mutable reference to the pane at index `i`.
+    pub fn pane_mut(&mut self, i: usize) -> &mut Pane { self.panes_mut().swap_remove(i) }
+
+    /// Split the pane at index `i` in two. `pane` is placed at index `i + 1`,
+    /// on the right of the pane if `vertical` is true, below it otherwise.
+    pub fn split(&mut self, i: usize, vertical: bool, pane: Pane) {
+        match self {
+            Self::Pane(_) => {
+                let layout = mem::take(self);
+                *self = Self::Split(vertical, Box::new([layout, Self::Pane(Box::new(pane))]));
+            }
+            Self::Split(_, layouts) => match layouts[0].panes().len() {
+                n if i < n => layouts[0].split(i, vertical, pane),
+                n => layouts[1].split(i - n, vertical, pane),
+            },
+        }
+    }
+
+    /// Remove the pane at index `i`, giving its space to the neighboring
+    /// layout. Return `false` if it is the only pane.
+    pub fn close(&mut self, i: usize) -> bool {
+        let Self::Split(_, layouts) = self else { return false };
+        let n = layouts[0].panes().len();
+        let (k, j) = if i < n { (0, i) } else { (1, i - n) };
+        if matches!(layouts[k], Self::Split(..)) {
+            return layouts[k].close(j);
+        }
+        *self = mem::take(&mut layouts[1 - k]);
+        true
+    }
+
+    /// Set the part of the screen used by the layout, and update the part used
+    /// by each pane. Panes that are side by side are separated by a column.
+    pub fn resize(&mut self, rect @ (top, left, height, width): Rect) {
+        match self {
+            Self::Pane(pane) => pane.rect = rect,
+            Self::Split(true, layouts) => {
+                let w = width.saturating_sub(1) / 2;
+                layouts[0].resize((top, left, height, w));
+                layouts[1].resize((top, left + w + 1, height, width.saturating_sub(w + 1)));
+            }
+            Self::Split(false, layouts) => {
+                let h = height / 2;
+                layouts[0].resize((top, left, h, width));
+                layouts[1].resize((top + h, left, height - h, width));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rects(layout: &Layout) -> Vec<Rect> { layout.panes().iter().map(|p| p.rect).collect() }
+
+    fn buf_indices(layout: &Layout) -> Vec<usize> {
+        layout.panes().iter().map(|p| p.buf_idx).collect()
+    }
+
+    #[test]
+    fn split_resize_and_close() {
+        let mut layout = Layout::default();
+        layout.split(0, true, Pane { buf_idx: 1, ..Default::default() });
+        layout.split(1, false, Pane { buf_idx: 2, ..Default::default() });
+        layout.resize((0, 0, 21, 81));
+        assert_eq!(rects(&layout), [(0, 0, 21, 40), (0, 41, 10, 40), (10, 41, 11, 40)]);
+        assert_eq!(buf_indices(&layout), [0, 1, 2]);
+
+        assert!(layout.close(1));
+        layout.resize((0, 0, 21, 81));
+        assert_eq!(rects(&layout), [(0, 0, 21, 40), (0, 41, 21, 40)]);
+        assert_eq!(buf_indices(&layout), [0, 2]);
+        assert!(layout.close(0));
+        assert!(!layout.close(0));
+        layout.resize((0, 0, 21, 81));
+        assert_eq!(rects(&layout), [(0, 0, 21, 81)]);
+        assert_eq!(layout.pane_mut(0).buf_idx, 2);
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Reflow
+//!
+//! Paragraphs are reflowed by joining their words and breaking them again into
+//! rows that fit in a given number of columns. The indentation and the comment
+//! start of the rows (e.g. `    // `) are kept as a prefix of the new rows.
+
+use std::num::NonZeroUsize;
+
+use unicode_width::UnicodeWidthChar;
+
+/// Split a row into its prefix and its content. The prefix is made of the
+/// indentation, followed by a comment start if the row starts with one, and
+/// the whitespace after it. Repeating the last character of the comment start
+/// or adding `!` after it is allowed, e.g. `///` or `//!` for `//`.
+fn split_prefix<'a>(row: &'a [u8], comment_starts: &[String]) -> (&'a [u8], &'a [u8]) {
+    let blank = |b: &u8| *b == b' ' || *b == b'\t';
+    let mut len = row.iter().take_while(|b| blank(b)).count();
+    let mut starts: Vec<_> = comment_starts.iter().map(String::as_bytes).collect();
+    starts.sort_by_key(|start| std::cmp::Reverse(start.len()));
+    if let Some(start) = starts.into_iter().find(|start| row[len..].starts_with(start)) {
+        len += start.len();
+        let last = start.last().copied();
+        len += row[len..].iter().take_while(|b| Some(**b) == last || **b == b'!').count();
+        len += row[len..].iter().take_while(|b| blank(b)).count();
+    }
+    row.split_at(len)
+}
+
+/// Return the comment start of a row (with its repeated characters, e.g.
+/// `///`), or an empty vector if it doesn't start with a comment. Consecutive
+/// rows are in the same paragraph if they have the same comment start. Return
+/// `None` if the row is blank, i.e. if it separates paragraphs.
+pub fn paragraph_kind(row: &[u8], comment_starts: &[String]) -> Option<Vec<u8>> {
+    let (prefix, content) = split_prefix(row, comment_starts);
+    (!content.trim_ascii().is_empty()).then(|| prefix.trim_ascii().to_vec())
+}
+
+/// Return the number of columns taken by `text` on the screen, starting at
+/// column `col`.
+fn width(text: &[u8], col: usize, tab: NonZeroUsize) -> usize {
+    String::from_utf8_lossy(text).chars().fold(col, |rx, c| match c {
+        '\t' => rx + tab.get() - rx % tab,
+        c => rx + c.width().unwrap_or(1),
+    }) - col
+}
+
+/// Reflow each paragraph of `rows` so that the new rows fit in `text_width`
+/// columns when possible; a word longer than that is put on a row of its own.
+/// The first row of a paragraph keeps its prefix, and the following ones use
+/// the prefix of its second row (if any), so that hanging indents are kept.
+/// Blank rows are kept. Return the new text, with rows separated by `\n`.
+pub fn reflow(
+    rows: &[Vec<u8>], comment_starts: &[String], text_width: usize, tab: NonZeroUsize,
+) -> Vec<u8> {
+    let mut new_rows: Vec<Vec<u8>> = Vec::new();
+    let mut i = 0;
+    while i < rows.len() {
+        let Some(kind) = paragraph_kind(&rows[i], comment_starts) else {
+            new_rows.push(rows[i].clone());
+            i += 1;
+            continue;
+        };
+        let n = rows[i..]
+            .iter()
+            .take_while(|row| paragraph_kind(row, comment_starts).as_ref() == Some(&kind))
+            .count();
+        let paragraph = &rows[i..i + n];
+        let first_prefix = split_prefix(&paragraph[0], comment_starts).0;
+        let prefix = split_prefix(paragraph.get(1).unwrap_or(&paragraph[0]), comment_starts).0;
+        let words = paragraph.iter().flat_map(|row| {
+            let content = split_prefix(row, comment_starts).1;
+            content.split(u8::is_ascii_whitespace).filter(|word| !word.is_empty())
+        });
+        let (mut row, mut col, mut empty) =
+            (first_prefix.to_vec(), width(first_prefix, 0, tab), true);
+        for word in words {
+            // Words contain no tabs, so their width doesn't depend on their column
+            let word_width = width(word, 0, tab);
+            if !empty && col + 1 + word_width > text_width {
+                new_rows.push(row);
+                (row, col, empty) = (prefix.to_vec(), width(prefix, 0, tab), true);
+            }
+            if !empty {
+                row.push(b' ');
+                col += 1;
+            }
+            row.extend_from_slice(word);
+            (col, empty) = (col + word_width, false);
+        }
+        new_rows.push(row);
+        i += n;
+    }
+    new_rows.join(&b'\n')
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("a b c d e f g h", "a b c d\ne f g h")]
+    #[case("abc\ndef\n\nghi jkl", "abc def\n\nghi jkl")]
+    #[case("  a b\n  c d e f", "  a b c\n  d e f")]
+    #[case("// a b c d e f", "// a b\n// c d\n// e f")]
+    #[case("\t/// a b c\n\t/// d", "\t/// a\n\t/// b\n\t/// c\n\t/// d")]
+    #[case("# a b c d\nx y", "# a b c\n# d\nx y")]
+    #[case("- a b c d\n  e f", "- a b c\n  d e f")]
+    #[case("abcdefghij k", "abcdefghij\nk")]
+    #[case("日本 語の 文", "日本\n語の 文")]
+    #[case("//\n// a b c", "//\n// a b\n// c")]
+    fn reflow_rows(#[case] text: &str, #[case] expected: &str) {
+        let rows: Vec<_> = text.split('\n').map(|row| row.as_bytes().to_vec()).collect();
+        let comment_starts = [String::from("//"), String::from("#")];
+        let tab = NonZeroUsize::new(4).expect("4 is not 0");
+        let reflowed = reflow(&rows, &comment_starts, 7, tab);
+        assert_eq!(String::from_utf8(reflowed).ok().as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn paragraphs() {
+        let comment_starts = [String::from("//")];
+        assert_eq!(paragraph_kind(b"  // a", &comment_starts), Some(b"//".to_vec()));
+        assert_eq!(paragraph_kind(b"//! a", &comment_starts), Some(b"//!".to_vec()));
+        assert_eq!(paragraph_kind(b"a // b", &comment_starts), Some(Vec::new()));
+        assert_eq!(paragraph_kind(b"  //  ", &comment_starts), None);
+        assert_eq!(paragraph_kind(b"", &comment_starts), None);
+    }
+}
@@ -0,0 +1,291 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Rope
+//!
+//! The text of a buffer is stored in chunks of limited size, so that inserting
+//! or deleting text only moves the bytes of the chunks that are modified. The
+//! chunks are indexed by their numbers of bytes and of new lines in a Fenwick
+//! tree, which allows finding the start of a line or the chunk containing a
+//! byte without going through all the chunks.
+
+use std::{borrow::Cow, iter};
+
+/// The number of bytes in a chunk of a newly created rope. When an insertion
+/// makes a chunk larger than twice this size, it is split again.
+const CHUNK_LEN: usize = 4096;
+
+/// A chunk of text.
+struct Chunk {
+    /// The bytes of the chunk.
+    text: Vec<u8>,
+    /// The number of new lines contained in `text`.
+    n_newlines: usize,
+}
+
+impl Chunk {
+    fn new(text: &[u8]) -> Self { Self { text: text.to_vec(), n_newlines: count_newlines(text) } }
+
+    /// Return the number of bytes and the number of new lines of the chunk.
+    const fn counts(&self) -> (usize, usize) { (self.text.len(), self.n_newlines) }
+}
+
+/// Text stored as a sequence of chunks, indexed by lines. Positions are given
+/// as `(x, y)`, where `y` is the index of the line and `x` the index of the
+/// byte in the line.
+#[derive(Default)]
+pub struct Rope {
+    /// The chunks of text. They are never empty.
+    chunks: Vec<Chunk>,
+    /// A Fenwick tree of the numbers of bytes and of new lines of the chunks:
+    /// element `i` (starting at 1) holds the counts of the chunks from
+    /// `i - (i & i.wrapping_neg())` (included) to `i` (excluded).
+    index: Vec<(usize, usize)>,
+    /// The number of lines. A rope with no line (the default) is different from
+    /// a rope with a single empty line; the first insertion creates the first
+    /// line.
+    n_lines: usize,
+    /// The total number of bytes, including new lines.
+    len: usize,
+}
+
+impl Rope {
+    /// Create a rope with the given text. The number of lines is the number of
+    /// new lines plus one.
+    pub fn new(text: &[u8]) -> Self {
+        let chunks = text.chunks(CHUNK_LEN).map(Chunk::new).collect();
+        let (n_lines, len) = (count_newlines(text) + 1, text.len());
+        let mut rope = Self { chunks, index: Vec::new(), n_lines, len };
+        rope.build_index();
+        rope
+    }
+
+    /// Return the number of lines.
+    pub const fn n_lines(&self) -> usize { self.n_lines }
+
+    /// Return the number of bytes, including new lines.
+    pub const fn len(&self) -> usize { self.len }
+
+    /// Return whether the rope contains no byte.
+    pub const fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Return the chunks of text, in order. Their concatenation is the whole
+    /// text.
+    #[cfg(test)]
+    pub fn chunks(&self) -> impl Iterator<Item=&[u8]> { self.chunks.iter().map(|c| &c.text[..]) }
+
+    /// Build the index of the chunks, after chunks have been added or removed.
+    fn build_index(&mut self) {
+        self.index = iter::once((0, 0)).chain(self.chunks.iter().map(Chunk::counts)).collect();
+        for i in 1..self.index.len() {
+            let j = i + (i & i.wrapping_neg());
+            if j < self.index.len() {
+                let (bytes, newlines) = self.index[i];
+                self.index[j] = (self.index[j].0 + bytes, self.index[j].1 + newlines);
+            }
+        }
+    }
+
+    /// Add the counts of chunk `i` to the index if `add` is true, or subtract
+    /// them otherwise, before and after the chunk is modified.
+    fn update_index(&mut self, i: usize, add: bool) {
+        let (bytes, newlines) = self.chunks[i].counts();
+        let mut j = i + 1;
+        while let Some(counts) = self.index.get_mut(j) {
+            *counts = if add {
+                (counts.0 + bytes, counts.1 + newlines)
+            } else {
+                (counts.0 - bytes, counts.1 - newlines)
+            };
+            j += j & j.wrapping_neg();
+        }
+    }
+
+    /// Return the index of the first chunk such that `key` applied to the total
+    /// counts of the chunks up to this one (included) is at least `target`,
+    /// and the total counts of the chunks before it. Return the number of
+    /// chunks if there is no such chunk.
+    fn find(
+        &self, key: impl Fn((usize, usize)) -> usize, target: usize,
+    ) -> (usize, (usize, usize)) {
+        let (mut i, mut before) = (0, (0, 0));
+        let mut step = self.index.len().next_power_of_two();
+        while step > 0 {
+            if let Some(&(bytes, newlines)) = self.index.get(i + step) {
+                let counts = (before.0 + bytes, before.1 + newlines);
+                if key(counts) < target {
+                    (i, before) = (i + step, counts);
+                }
+            }
+            step /= 2;
+        }
+        (i, before)
+    }
+
+    /// Return the offset of the first byte of line `y` in the text.
+    fn line_start(&self, y: usize) -> usize {
+        if y == 0 {
+            return 0;
+        }
+        let (i, (offset, n_newlines)) = self.find(|(_, newlines)| newlines, y);
+        let Some(chunk) = self.chunks.get(i) else { return offset };
+        let mut newlines = chunk.text.iter().enumerate().filter(|(_, b)| **b == b'\n');
+        offset + newlines.nth(y - n_newlines - 1).map_or(0, |(i, _)| i) + 1
+    }
+
+    /// Return the index of the chunk containing the byte at `offset`, and the
+    /// offset of the byte in this chunk. At the boundary between two chunks,
+    /// the end of the first chunk is returned.
+    fn locate(&self, offset: usize) -> (usize, usize) {
+        let (i, (before, _)) = self.find(|(bytes, _)| bytes, offset);
+        if i == self.chunks.len() { (i, 0) } else { (i, offset - before) }
+    }
+
+    /// Return the lines starting at line `y`, without their new line
+    /// characters.
+    pub fn lines(&self, y: usize) -> impl Iterator<Item=Vec<u8>> {
+        let (i, j) = self.locate(self.line_start(y));
+        let chunks = self.chunks.iter().skip(i).enumerate();
+        let mut bytes =
+            chunks.flat_map(move |(k, c)| &c.text[if k == 0 { j } else { 0 }..]).copied();
+        let mut remaining = self.n_lines.saturating_sub(y);
+        iter::from_fn(move || {
+            (remaining > 0).then(|| {
+                remaining -= 1;
+                bytes.by_ref().take_while(|b| *b != b'\n').collect()
+            })
+        })
+    }
+
+    /// Return line `y`, without its new line character. The line is only
+    /// copied if it spans several chunks.
+    pub fn line(&self, y: usize) -> Cow<'_, [u8]> {
+        let (i, j) = self.locate(self.line_start(y));
+        let Some(chunk) = self.chunks.get(i) else { return Cow::Borrowed(&[]) };
+        let rest = &chunk.text[j..];
+        match rest.iter().position(|b| *b == b'\n') {
+            Some(end) => Cow::Borrowed(&rest[..end]),
+            None if i + 1 == self.chunks.len() => Cow::Borrowed(rest),
+            None => Cow::Owned(self.lines(y).next().unwrap_or_default()),
+        }
+    }
+
+    /// Insert `text` at position `(x, y)`.
+    pub fn insert(&mut self, (x, y): (usize, usize), text: &[u8]) {
+        self.n_lines = self.n_lines.max(1);
+        let (i, j) = self.locate(self.line_start(y) + x);
+        if i == self.chunks.len() {
+            self.chunks.push(Chunk::new(&[]));
+            self.build_index();
+        }
+        self.update_index(i, false);
+        let chunk = &mut self.chunks[i];
+        chunk.text.splice(j..j, text.iter().copied());
+        chunk.n_newlines += count_newlines(text);
+        if chunk.text.len() > 2 * CHUNK_LEN {
+            let new_chunks: Vec<_> = chunk.text.chunks(CHUNK_LEN).map(Chunk::new).collect();
+            self.chunks.splice(i..=i, new_chunks);
+            self.build_index();
+        } else {
+            self.update_index(i, true);
+        }
+        self.n_lines += count_newlines(text);
+        self.len += text.len();
+    }
+
+    /// Delete the text between positions `(x0, y0)` (included) and `(x1, y1)`
+    /// (excluded), and return it.
+    pub fn delete(&mut self, (x0, y0): (usize, usize), (x1, y1): (usize, usize)) -> Vec<u8> {
+        let (start, end) = (self.line_start(y0) + x0, self.line_start(y1) + x1);
+        let mut deleted = Vec::with_capacity(end.saturating_sub(start));
+        let (first, offset) = self.locate(start);
+        let (mut chunk_start, mut emptied) = (start - offset, false);
+        for i in first..self.chunks.len() {
+            if chunk_start >= end {
+                break;
+            }
+            let chunk_end = chunk_start + self.chunks[i].text.len();
+            let range = start.max(chunk_start) - chunk_start..end.min(chunk_end) - chunk_start;
+            if !range.is_empty() {
+                self.update_index(i, false);
+                let text: Vec<u8> = self.chunks[i].text.drain(range).collect();
+                self.chunks[i].n_newlines -= count_newlines(&text);
+                self.update_index(i, true);
+                emptied |= self.chunks[i].text.is_empty();
+                deleted.extend(text);
+            }
+            chunk_start = chunk_end;
+        }
+        if emptied {
+            self.chunks.retain(|chunk| !chunk.text.is_empty());
+            self.build_index();
+        }
+        self.n_lines -= count_newlines(&deleted);
+        self.len -= deleted.len();
+        deleted
+    }
+}
+
+/// Return the number of new line characters in `text`.
+#[expect(clippy::naive_bytecount)]
+fn count_newlines(text: &[u8]) -> usize { text.iter().filter(|b| **b == b'\n').count() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(rope: &Rope) -> Vec<u8> { rope.chunks().flatten().copied().collect() }
+
+    #[test]
+    fn insert_and_delete() {
+        let mut rope = Rope::default();
+        assert_eq!(rope.n_lines(), 0);
+        rope.insert((0, 0), b"");
+        assert_eq!(rope.n_lines(), 1);
+        rope.insert((0, 0), b"Hello\nWorld");
+        rope.insert((5, 0), b",\nnew");
+        assert_eq!(text(&rope), b"Hello,\nnew\nWorld");
+        assert_eq!((rope.n_lines(), rope.len()), (3, 16));
+        assert_eq!(rope.line(1).to_vec(), b"new");
+        assert_eq!(rope.lines(1).collect::<Vec<_>>(), [&b"new"[..], b"World"]);
+
+        assert_eq!(rope.delete((2, 1), (1, 2)), b"w\nW");
+        assert_eq!(text(&rope), b"Hello,\nneorld");
+        assert_eq!((rope.n_lines(), rope.len()), (2, 13));
+        assert_eq!(rope.line(2).to_vec(), b"");
+    }
+
+    #[test]
+    fn large_text() {
+        let line = [b'a'; 100];
+        let mut rope = Rope::new(&[&line[..], b"\n"].concat().repeat(1000));
+        assert_eq!(rope.n_lines(), 1001);
+        assert!(rope.chunks().count() > 1);
+        assert_eq!(rope.line(999).to_vec(), line);
+        assert_eq!(rope.line(1000).to_vec(), b"");
+
+        rope.insert((50, 500), &b"b".repeat(3 * CHUNK_LEN));
+        assert!(rope.chunks().all(|chunk| chunk.len() <= 2 * CHUNK_LEN));
+        assert_eq!(rope.line(500).len(), 100 + 3 * CHUNK_LEN);
+        assert_eq!(rope.delete((50, 500), (50 + 3 * CHUNK_LEN, 500)), b"b".repeat(3 * CHUNK_LEN));
+        assert_eq!(rope.delete((0, 1), (0, 999)).len(), 998 * 101);
+        assert_eq!(rope.lines(0).collect::<Vec<_>>(), [&line[..], &line, &[]]);
+        assert_eq!(text(&rope), [&line[..], b"\n"].concat().repeat(2));
+    }
+
+    #[test]
+    fn lines_across_chunks() {
+        let lines: Vec<Vec<u8>> = (0..2000).map(|y| format!("line {y}").into_bytes()).collect();
+        let mut rope = Rope::new(&lines.join(&b'\n'));
+        for y in (0..2000).step_by(7) {
+            rope.insert((0, y), b"x");
+            rope.delete((0, y), (1, y));
+        }
+        assert!(rope.chunks().count() > 3);
+        assert!((0..2000).all(|y| rope.line(y) == lines[y]));
+        // Lines contained in a chunk are not copied
+        assert!(matches!(rope.line(1), Cow::Borrowed(b"line 1")));
+        assert!(rope.lines(1990).eq(lines[1990..].iter().cloned()));
+    }
+}
@@ -7,7 +7,7 @@
 //! Utilities for rows. A `Row` owns the underlying characters, the rendered
 //! string and the syntax highlighting information.
 
-use std::{iter::repeat_n, num::NonZeroUsize};
+use std::{iter::repeat_n, num::NonZeroUsize, ops::Range};
 
 use unicode_width::UnicodeWidthChar;
 
@@ -30,7 +30,7 @@ pub enum HlState {
 }
 
 /// Represents a row of characters and how it is rendered.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Row {
     /// The characters of the row.
     pub chars: Vec<u8>,
@@ -48,9 +48,6 @@ pub struct Row {
     pub hl: Vec<HlType>,
     /// The final state of the row.
     pub hl_state: HlState,
-    /// If not `None`, the range that is currently matched during a FIND
-    /// operation.
-    pub match_segment: Option<std::ops::Range<usize>>,
 }
 
 impl Row {
@@ -61,6 +58,13 @@ impl Row {
     /// Update the row: convert tabs into spaces and compute highlight symbols
     /// The `hl_state` argument is the `HLState` for the previous row.
     pub fn update(&mut self, syntax: &SyntaxConf, hl_state: HlState, tab: NonZeroUsize) -> HlState {
+        self.update_render(tab);
+        self.update_syntax(syntax, hl_state)
+    }
+
+    /// Update the rendered string of the row and the mappings between indices
+    /// in `self.chars` and `self.render`, without highlighting the row.
+    pub fn update_render(&mut self, tab: NonZeroUsize) {
         let (..) = (self.render.clear(), self.cx2rx.clear(), self.rx2cx.clear());
         let (mut cx, mut rx) = (0, 0);
         for c in String::from_utf8_lossy(&self.chars).chars() {
@@ -73,7 +77,28 @@ impl Row {
             (rx, cx) = (rx + n_rend_chars, cx + c.len_utf8());
         }
         let (..) = (self.cx2rx.push(rx), self.rx2cx.push(cx));
-        self.update_syntax(syntax, hl_state)
+    }
+
+    /// Return the start of each line on the screen when the row is wrapped at
+    /// `width` columns, as the index of the first rendered character of the
+    /// line and its column. Wide characters are never split across lines. If
+    /// the last line is full, an empty line is added for the cursor.
+    pub fn wrap(&self, width: usize) -> Vec<(usize, usize)> {
+        let (mut starts, mut rx) = (vec![(0, 0)], 0);
+        if width == 0 {
+            return starts;
+        }
+        for (i, c) in self.render.chars().enumerate() {
+            let (w, line_start) = (c.width().unwrap_or(1), starts.last().map_or(0, |s| s.1));
+            if rx + w > line_start + width && rx > line_start {
+                starts.push((i, rx));
+            }
+            rx += w;
+        }
+        if rx > 0 && rx == starts.last().map_or(0, |s| s.1) + width {
+            starts.push((self.render.chars().count(), rx));
+        }
+        starts
     }
 
     /// Obtain the character size, in bytes, given its position in
@@ -176,14 +201,23 @@ impl Row {
     }
 
     /// Draw the row and write the result to a buffer. An `offset` can be given,
-    /// as well as a limit on the length of the row (`max_len`). After
-    /// writing the characters, clear the rest of the line and move the
-    /// cursor to the start of the next line.
-    pub fn draw(&self, offset: usize, max_len: usize, buffer: &mut String, use_color: bool) {
+    /// as well as a limit on the length of the row (`max_len`) and overlays:
+    /// ranges of rendered characters that are highlighted with a given
+    /// `HlType` instead of their syntax highlighting, such as the text matched
+    /// during a FIND operation or the selected text. The first overlays take
+    /// precedence over the next ones. A selection overlay starting at the end
+    /// of the row is drawn as a selected space. After writing the characters,
+    /// clear the rest of the line and move the cursor to the start of the next
+    /// line.
+    pub fn draw(
+        &self, offset: usize, max_len: usize, overlays: &[(Range<usize>, HlType)],
+        buffer: &mut String, use_color: bool,
+    ) {
         let mut current_hl_type = HlType::Normal;
         let chars = self.render.chars().skip(offset).take(max_len);
         let mut rx = self.render.chars().take(offset).map(|c| c.width().unwrap_or(1)).sum();
-        for (c, mut hl_type) in chars.zip(self.hl.iter().skip(offset)) {
+        let (mut current_overlay, mut n_drawn) = (None, 0);
+        for (c, hl_type) in chars.zip(self.hl.iter().skip(offset)) {
             if c.is_ascii_control() {
                 let rendered_char = if (c as u8) <= 26 { (b'@' + c as u8) as char } else { '?' };
                 push_colored(buffer, WBG, &rendered_char.to_string(), use_color);
@@ -192,24 +226,27 @@ impl Row {
                     buffer.push_str(&current_hl_type.to_string());
                 }
             } else {
-                if let Some(match_segment) = &self.match_segment {
-                    if match_segment.contains(&rx) {
-                        // Set the highlight type to Match, i.e. set the background to cyan
-                        hl_type = &HlType::Match;
-                    } else if use_color && rx == match_segment.end {
-                        // Reset the formatting, in particular the background
-                        buffer.push_str(RESET);
-                    }
+                let overlay = overlays.iter().find(|(r, _)| r.contains(&rx)).map(|(_, t)| *t);
+                if use_color && current_overlay.is_some() && overlay != current_overlay {
+                    // Reset the formatting, in particular the background
+                    buffer.push_str(RESET);
                 }
-                if use_color && current_hl_type != *hl_type {
+                let hl_type = overlay.unwrap_or(*hl_type);
+                current_overlay = overlay;
+                if use_color && current_hl_type != hl_type {
                     buffer.push_str(&hl_type.to_string());
                 }
-                current_hl_type = *hl_type;
+                current_hl_type = hl_type;
                 buffer.push(c);
             }
             rx += c.width().unwrap_or(1);
+            n_drawn += 1;
         }
         buffer.push_str(if use_color { RESET } else { "" });
+        let end_selected = overlays.iter().any(|(r, t)| *t == HlType::Selection && r.start == rx);
+        if use_color && n_drawn < max_len && end_selected {
+            push_colored(buffer, &HlType::Selection.to_string(), " ", use_color);
+        }
     }
 }
 
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use std::fmt::{self, Display, Formatter};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 
 use crate::config::{self, parse_value as pv, parse_values as pvs};
@@ -23,6 +24,8 @@ pub enum HlType {
     MlComment = 134, // Blue
     Keyword1 = 33,   // Yellow
     Keyword2 = 35,   // Magenta
+    Bracket = 4,     // Underlined
+    Selection = 7,   // Inverted foreground and background colors
 }
 
 impl Display for HlType {
@@ -50,20 +53,56 @@ pub struct Conf {
     /// Keywords to highlight and there corresponding `HLType` (typically
     /// `HLType::Keyword1` or `HLType::Keyword2`)
     pub keywords: Vec<(HlType, Vec<String>)>,
+    /// Characters that open an indented block when they end a line, e.g. `{`.
+    pub indent_after: Vec<char>,
+    /// Characters that close an indented block, e.g. `}`. Typing one of them
+    /// after the indentation of a row removes one indentation level.
+    pub dedent_on: Vec<char>,
+    /// The pairs of opening and closing brackets, e.g. ("begin", "end"). If
+    /// empty, the default pairs `()`, `[]` and `{}` are used.
+    pub bracket_pairs: Vec<(String, String)>,
+    /// Whether to indent with spaces instead of tabs, overriding the global
+    /// configuration.
+    pub expand_tab: Option<bool>,
+    /// The number of spaces per indentation level, overriding the global
+    /// configuration.
+    pub indent_width: Option<NonZeroUsize>,
 }
 
+/// The bracket pairs used when none are configured.
+const DEFAULT_BRACKET_PAIRS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+
 impl Conf {
     /// Return the syntax configuration corresponding to the given file
     /// name, if a matching INI file is found in a config directory.
     /// If no matching configuration is found, return the default.
     pub fn find(name: &str, data_dirs: &[String]) -> Self {
+        Self::find_where(data_dirs, |_, sfix| sfix.iter().any(|s| name.ends_with(s)))
+            .unwrap_or_default()
+    }
+
+    /// Return the syntax configuration of the language with the given name
+    /// or extension, ignoring case (e.g. "python" or "py"), if a matching INI
+    /// file is found in a config directory.
+    pub fn find_by_name(name: &str, data_dirs: &[String]) -> Option<Self> {
+        let is_name = |s: &str| s.eq_ignore_ascii_case(name);
+        Self::find_where(data_dirs, |sc, sfix| {
+            is_name(&sc.name) || sfix.iter().any(|s| is_name(s.trim_start_matches('.')))
+        })
+    }
+
+    /// Return the first syntax configuration found in a config directory for
+    /// which `matches` returns true, given the configuration and the suffixes
+    /// of the file names it applies to.
+    fn find_where<F>(data_dirs: &[String], matches: F) -> Option<Self>
+    where F: Fn(&Self, &[String]) -> bool {
         for data_dir in data_dirs {
             match PathBuf::from(data_dir).join("syntax.d").read_dir() {
                 Ok(dir_entries) =>
                     for dir_entry in dir_entries {
                         match dir_entry.map(|dir_entry| Self::parse(&dir_entry.path())) {
                             // sfix = suffixes
-                            Ok((sc, sfix)) if sfix.iter().any(|s| name.ends_with(s)) => return sc,
+                            Ok((sc, sfix)) if matches(&sc, &sfix) => return Some(sc),
                             Ok((..)) => (),
                             Err(e) => eprintln!("Error iterating through {data_dir}/syntax.d: {e}"),
                         }
@@ -72,7 +111,7 @@ impl Conf {
                 Err(e) => eprintln!("Error iterating through {data_dir}/syntax.d: {e}"),
             }
         }
-        Self::default()
+        None
     }
 
     /// Load and parse a `SyntaxConf` from file.
@@ -93,12 +132,35 @@ impl Conf {
                 "multiline_string_delim" => sc.ml_string_delim = Some(pv(val)?),
                 "keywords_1" => sc.keywords.push((HlType::Keyword1, pvs(val)?)),
                 "keywords_2" => sc.keywords.push((HlType::Keyword2, pvs(val)?)),
+                "indent_after" => sc.indent_after = pvs(val)?,
+                "dedent_on" => sc.dedent_on = pvs(val)?,
+                "expand_tab" => sc.expand_tab = Some(pv(val)?),
+                "indent_width" => sc.indent_width = Some(pv(val)?),
+                "bracket_pairs" =>
+                    for pair in val.split(',') {
+                        match pair.split_whitespace().collect::<Vec<_>>()[..] {
+                            [open, close] => sc.bracket_pairs.push((open.into(), close.into())),
+                            _ => return Err(format!("Expected 2 brackets, got {pair}")),
+                        }
+                    },
                 _ => return Err(String::from("Invalid key")),
             }
             Ok(())
         });
         (sc, suffixes)
     }
+
+    /// Return the pairs of opening and closing brackets.
+    pub fn bracket_pairs(&self) -> Vec<(String, String)> {
+        if self.bracket_pairs.is_empty() {
+            DEFAULT_BRACKET_PAIRS
+                .iter()
+                .map(|(open, close)| ((*open).into(), (*close).into()))
+                .collect()
+        } else {
+            self.bracket_pairs.clone()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -125,6 +187,24 @@ mod tests {
         assert_eq!(file_count, syntax_names.len());
     }
 
+    #[test]
+    fn bracket_pairs() {
+        assert_eq!(Conf::default().bracket_pairs()[2], ("{".into(), "}".into()));
+        let (conf, _) = Conf::parse(Path::new("./syntax.d/ocaml.ini"));
+        assert_eq!(conf.bracket_pairs().last(), Some(&("begin".into(), "end".into())));
+    }
+
+    #[test]
+    fn find_by_name() {
+        let data_dirs = [String::from(".")];
+        let python = Conf::find_by_name("python", &data_dirs).map(|conf| conf.name);
+        assert_eq!(python.as_deref(), Some("Python"));
+        let rust = Conf::find_by_name("rs", &data_dirs).map(|conf| conf.name);
+        assert_eq!(rust.as_deref(), Some("Rust"));
+        assert_eq!(Conf::find_by_name("kibi", &data_dirs), None);
+        assert_eq!(Conf::find("main.rs", &data_dirs).name, "Rust");
+    }
+
     #[test]
     fn conf_from_invalid_path() {
         let tmp_dir = TempDir::new().expect("Could not create temporary directory");
@@ -0,0 +1,213 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Transformations
+//!
+//! Commands that transform text, such as changing its case, sorting lines or
+//! encoding it in base 64. The text is handled as bytes: the case of valid
+//! UTF-8 characters is changed, and invalid UTF-8 sequences are kept as is.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// A transformation of text. It fails if the text cannot be decoded.
+type Transform = fn(&[u8]) -> Result<Vec<u8>, String>;
+
+/// The transformations by name, with whether they act on whole lines.
+pub const TRANSFORMS: &[(&str, bool, Transform)] = &[
+    ("upper", false, |text| Ok(map_str(text, str::to_uppercase))),
+    ("lower", false, |text| Ok(map_str(text, str::to_lowercase))),
+    ("title", false, |text| Ok(map_str(text, title_case))),
+    ("sort", true, |text| Ok(map_lines(text, |lines| lines.sort()))),
+    ("sort-numeric", true, |text| Ok(map_lines(text, |lines| lines.sort_by(cmp_numbers)))),
+    ("sort-reverse", true, |text| Ok(map_lines(text, |lines| lines.sort_by(|a, b| b.cmp(a))))),
+    ("unique", true, |text| Ok(map_lines(text, unique))),
+    ("reverse", true, |text| Ok(map_lines(text, |lines| lines.reverse()))),
+    ("base64-encode", false, |text| Ok(base64_encode(text).into_bytes())),
+    ("base64-decode", false, base64_decode),
+    ("url-encode", false, |text| Ok(url_encode(text).into_bytes())),
+    ("url-decode", false, url_decode),
+    ("hex-encode", false, |text| Ok(hex_encode(text))),
+    ("hex-decode", false, hex_decode),
+];
+
+/// The characters used to encode data in base 64.
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Apply `f` to the valid UTF-8 parts of `text`, keeping invalid sequences.
+fn map_str(text: &[u8], f: fn(&str) -> String) -> Vec<u8> {
+    let mut result = Vec::with_capacity(text.len());
+    for chunk in text.utf8_chunks() {
+        result.extend(f(chunk.valid()).into_bytes());
+        result.extend(chunk.invalid());
+    }
+    result
+}
+
+/// Capitalize the first letter of each word, and write the others in lower
+/// case.
+fn title_case(s: &str) -> String {
+    let mut in_word = false;
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        if in_word {
+            result.extend(c.to_lowercase());
+        } else {
+            result.extend(c.to_uppercase());
+        }
+        in_word = c.is_alphanumeric() || c == '\'';
+    }
+    result
+}
+
+/// Apply `f` to the lines of `text`.
+fn map_lines(text: &[u8], f: impl FnOnce(&mut Vec<&[u8]>)) -> Vec<u8> {
+    let mut lines: Vec<_> = text.split(|b| *b == b'\n').collect();
+    f(&mut lines);
+    lines.join(&b'\n')
+}
+
+/// Compare lines by the number they start with, after leading whitespace.
+/// Lines that do not start with a number are compared as if it were 0.
+fn cmp_numbers(a: &&[u8], b: &&[u8]) -> Ordering {
+    let number = |line: &[u8]| {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_start();
+        let len = (line.char_indices())
+            .take_while(|(i, c)| c.is_ascii_digit() || *c == '.' || (*i == 0 && "+-".contains(*c)))
+            .count();
+        (1..=len).rev().find_map(|len| line[..len].parse::<f64>().ok()).unwrap_or(0.0)
+    };
+    number(a).total_cmp(&number(b))
+}
+
+/// Remove the lines that are the same as a previous line.
+fn unique(lines: &mut Vec<&[u8]>) {
+    let mut seen = HashSet::new();
+    lines.retain(|line| seen.insert(*line));
+}
+
+/// Encode `data` in base 64, with padding.
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk.iter().enumerate()).fold(0, |n, (i, b)| n | (u32::from(*b) << (16 - 8 * i)));
+        for i in 0..4 {
+            let c = BASE64_CHARS[((n >> (18 - 6 * i)) & 63) as usize];
+            encoded.push(if i <= chunk.len() { c as char } else { '=' });
+        }
+    }
+    encoded
+}
+
+/// Decode base 64 data. Whitespace is ignored, and padding is optional.
+fn base64_decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    let digits = text.iter().filter(|b| !b.is_ascii_whitespace());
+    let digits: Vec<_> = digits.take_while(|b| **b != b'=').collect();
+    let mut decoded = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        let mut n = 0;
+        for (i, c) in chunk.iter().enumerate() {
+            let v = (BASE64_CHARS.iter().zip(0u32..)).find(|(b, _)| b == c);
+            n |= v.ok_or("Invalid base 64")?.1 << (18 - 6 * i);
+        }
+        if chunk.len() == 1 {
+            return Err(String::from("Invalid base 64 length"));
+        }
+        decoded.extend(n.to_be_bytes()[1..chunk.len()].iter());
+    }
+    Ok(decoded)
+}
+
+/// Percent-encode all the bytes except unreserved characters, as in URLs.
+fn url_encode(text: &[u8]) -> String {
+    let unreserved = |b: u8| b.is_ascii_alphanumeric() || b"-_.~".contains(&b);
+    let encode =
+        |b: &u8| if unreserved(*b) { (*b as char).to_string() } else { format!("%{b:02X}") };
+    text.iter().map(encode).collect()
+}
+
+/// Decode percent-encoded bytes.
+fn url_decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.iter();
+    while let Some(b) = bytes.next() {
+        if *b == b'%' {
+            let hex = [bytes.next(), bytes.next()].map(|b| b.copied().unwrap_or_default());
+            decoded.push(parse_hex(&hex).ok_or("Invalid percent-encoding")?);
+        } else {
+            decoded.push(*b);
+        }
+    }
+    Ok(decoded)
+}
+
+/// Write each byte as a pair of hexadecimal digits.
+fn hex_encode(text: &[u8]) -> Vec<u8> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    text.iter().flat_map(|b| [DIGITS[usize::from(b >> 4)], DIGITS[usize::from(b & 15)]]).collect()
+}
+
+/// Decode bytes written as pairs of hexadecimal digits. Whitespace is ignored.
+fn hex_decode(text: &[u8]) -> Result<Vec<u8>, String> {
+    let digits: Vec<_> = text.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+    let pairs = digits.chunks(2).map(|pair| parse_hex(pair).ok_or("Invalid hexadecimal"));
+    Ok(pairs.collect::<Result<_, _>>()?)
+}
+
+/// Parse a byte written as two hexadecimal digits.
+fn parse_hex(digits: &[u8]) -> Option<u8> {
+    match digits {
+        [_, _] => u8::from_str_radix(str::from_utf8(digits).ok()?, 16).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(b"", "")]
+    #[case(b"f", "Zg==")]
+    #[case(b"fo", "Zm8=")]
+    #[case(b"foo", "Zm9v")]
+    #[case(b"foobar", "Zm9vYmFy")]
+    #[case("Kibi ✓\n".as_bytes(), "S2liaSDinJMK")]
+    fn base64_encoding(#[case] data: &[u8], #[case] expected: &str) {
+        assert_eq!(base64_encode(data), expected);
+        assert_eq!(base64_decode(expected.as_bytes()).as_deref(), Ok(data));
+    }
+
+    #[rstest]
+    #[case("upper", b"Kibi \xff\xc3\xa9t\xc3", b"KIBI \xff\xc3\x89T\xc3")]
+    #[case("lower", "ÉTÉ".as_bytes(), "été".as_bytes())]
+    #[case("title", b"hello wORLD, it's kibi_2", b"Hello World, It's Kibi_2")]
+    #[case("sort", b"b\na\n\nc", b"\na\nb\nc")]
+    #[case("sort-numeric", b"10 b\n 9 a\nx\n-1.5", b"-1.5\nx\n 9 a\n10 b")]
+    #[case("sort-reverse", b"b\na\nc", b"c\nb\na")]
+    #[case("unique", b"a\nb\na\nc\nb", b"a\nb\nc")]
+    #[case("reverse", b"a\nb\nc", b"c\nb\na")]
+    #[case("base64-decode", b"Zm9v\nYmE", b"fooba")]
+    #[case("url-encode", "a b/é~".as_bytes(), b"a%20b%2F%C3%A9~")]
+    #[case("url-decode", b"a%20b%2f%C3%A9+", "a b/é+".as_bytes())]
+    #[case("hex-encode", b"\x00Az", b"00417a")]
+    #[case("hex-decode", b"00 41 7A", b"\x00Az")]
+    fn transform(#[case] name: &str, #[case] text: &[u8], #[case] expected: &[u8]) {
+        let (_, _, f) = TRANSFORMS.iter().find(|(n, ..)| *n == name).expect("Unknown transform");
+        assert_eq!(f(text).as_deref(), Ok(expected));
+    }
+
+    #[rstest]
+    #[case(base64_decode, b"Zm9v!", "Invalid base 64")]
+    #[case(base64_decode, b"Zm9vY", "Invalid base 64 length")]
+    #[case(url_decode, b"100%", "Invalid percent-encoding")]
+    #[case(hex_decode, b"abc", "Invalid hexadecimal")]
+    #[case(hex_decode, b"zz", "Invalid hexadecimal")]
+    fn invalid_encoding(#[case] decode: Transform, #[case] text: &[u8], #[case] expected: &str) {
+        assert_eq!(decode(text), Err(String::from(expected)));
+    }
+}
@@ -0,0 +1,358 @@
+// SPDX-FileCopyrightText: 2020 Ilaï Deutel & Kibi Contributors
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! # Vi keymap
+//!
+//! With the `vi` keymap, the editor has a normal, an insert and a visual mode,
+//! as in Vi. In the normal and visual modes, the typed characters are parsed
+//! into commands such as `3dw` (delete 3 words), which the editor runs.
+
+/// The character typed with `Ctrl+R`.
+const CTRL_R: u8 = b'R' & 0x1f;
+
+/// The mode of the editor with the `vi` keymap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Characters are commands
+    #[default]
+    Normal,
+    /// Characters are inserted in the text
+    Insert,
+    /// Characters are commands, and motions extend the selection
+    Visual,
+}
+
+impl Mode {
+    /// Return the name of the mode, as shown in the status bar.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "NORMAL",
+            Self::Insert => "INSERT",
+            Self::Visual => "VISUAL",
+        }
+    }
+}
+
+/// A motion of the cursor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motion {
+    /// `h`: the previous character of the row
+    Left,
+    /// `l`: the next character of the row
+    Right,
+    /// `k`: the previous row
+    Up,
+    /// `j`: the next row
+    Down,
+    /// `w`: the start of the next word
+    NextWord,
+    /// `b`: the start of the previous word
+    PreviousWord,
+    /// `e`: the end of the next word
+    EndOfWord,
+    /// `0`: the start of the row
+    StartOfRow,
+    /// `$`: the end of the row
+    EndOfRow,
+    /// `gg` or `G`: the given row, counted from 1, or the last row if `None`
+    Row(Option<usize>),
+}
+
+impl Motion {
+    /// Return whether an operator applies to whole rows with this motion.
+    pub const fn is_linewise(self) -> bool { matches!(self, Self::Up | Self::Down | Self::Row(_)) }
+}
+
+/// An operator, applied to the text covered by a motion or selected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    /// `d`: cut the text
+    Delete,
+    /// `c`: cut the text and switch to the insert mode
+    Change,
+    /// `y`: copy the text
+    Yank,
+}
+
+/// Where text is inserted when switching to the insert mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Insertion {
+    /// `i`: before the cursor
+    Before,
+    /// `a`: after the cursor
+    After,
+    /// `I`: before the first non-blank character of the row
+    RowStart,
+    /// `A`: at the end of the row
+    RowEnd,
+    /// `o`: in a new row below the current row
+    RowBelow,
+    /// `O`: in a new row above the current row
+    RowAbove,
+}
+
+/// A command of the normal or the visual mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Move the cursor a number of times
+    Move(Motion, usize),
+    /// Apply the operator to the text between the cursor and the position
+    /// reached by moving the cursor a number of times
+    Operate(Operator, Motion, usize),
+    /// Apply the operator to a number of rows, from the current row (`dd`,
+    /// `cc`, `yy`)
+    OperateRows(Operator, usize),
+    /// Apply the operator to the selection of the visual mode
+    OperateSelection(Operator),
+    /// `p`: paste a number of times after the cursor
+    Paste(usize),
+    /// Switch to the insert mode
+    Insert(Insertion),
+    /// `v`: switch to the visual mode, or back to the normal mode
+    Visual,
+    /// `u`: undo a number of changes
+    Undo(usize),
+    /// `Ctrl+R`: redo a number of changes
+    Redo(usize),
+    /// `.`: repeat the last change a number of times
+    Repeat(usize),
+    /// `:`: enter a command in the prompt
+    Prompt,
+    /// `/`: search in the prompt
+    Find,
+}
+
+impl Command {
+    /// Return whether the command changes the text, and can be repeated.
+    pub const fn is_change(self) -> bool {
+        match self {
+            Self::Operate(op, ..) | Self::OperateRows(op, _) | Self::OperateSelection(op) =>
+                !matches!(op, Operator::Yank),
+            Self::Paste(_) | Self::Insert(_) => true,
+            Self::Move(..)
+            | Self::Visual
+            | Self::Undo(_)
+            | Self::Redo(_)
+            | Self::Repeat(_)
+            | Self::Prompt
+            | Self::Find => false,
+        }
+    }
+}
+
+/// The state of the `vi` keymap: the mode, and the part of the command typed
+/// so far.
+#[derive(Default)]
+pub struct Vi {
+    /// The current mode
+    pub mode: Mode,
+    /// The count typed before the command or the motion, if any
+    count: Option<usize>,
+    /// The operator waiting for a motion, with the count typed before it
+    operator: Option<(Operator, usize)>,
+    /// Whether `g` was typed, waiting for a second `g`
+    g: bool,
+}
+
+impl Vi {
+    /// Return whether a command has been partly typed.
+    pub const fn is_pending(&self) -> bool {
+        self.count.is_some() || self.operator.is_some() || self.g
+    }
+
+    /// Forget the command typed so far.
+    pub const fn cancel(&mut self) { (self.count, self.operator, self.g) = (None, None, false); }
+
+    /// Read a character typed in the normal or the visual mode. Return the
+    /// command if it is complete, or `None` if more characters are needed or
+    /// if the command is not valid.
+    pub fn parse(&mut self, c: u8) -> Option<Command> {
+        if c.is_ascii_digit() && (c != b'0' || self.count.is_some()) {
+            let digit = usize::from(c - b'0');
+            self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+            return None;
+        }
+        let (count, g, visual) = (self.count.take(), self.g, self.mode == Mode::Visual);
+        self.g = false;
+        let motion = match c {
+            b'g' if g => Some(Motion::Row(Some(count.unwrap_or(1)))),
+            b'G' => Some(Motion::Row(count)),
+            _ if g => {
+                self.operator = None;
+                return None;
+            }
+            b'h' => Some(Motion::Left),
+            b'l' => Some(Motion::Right),
+            b'k' => Some(Motion::Up),
+            b'j' => Some(Motion::Down),
+            b'w' => Some(Motion::NextWord),
+            b'b' => Some(Motion::PreviousWord),
+            b'e' => Some(Motion::EndOfWord),
+            b'0' => Some(Motion::StartOfRow),
+            b'$' => Some(Motion::EndOfRow),
+            b'g' => {
+                (self.count, self.g) = (count, true);
+                return None;
+            }
+            _ => None,
+        };
+        // The count of `gg` and `G` is a row number, not a number of moves
+        let n = if matches!(motion, Some(Motion::Row(_))) { 1 } else { count.unwrap_or(1) };
+        let operator = self.operator.take();
+        let command = match (operator, motion) {
+            (Some((op, op_count)), Some(motion)) =>
+                Command::Operate(op, motion, op_count.saturating_mul(n)),
+            (None, Some(motion)) => Command::Move(motion, n),
+            (Some((op, op_count)), None) if Some(op) == operator_for(c) =>
+                Command::OperateRows(op, op_count.saturating_mul(n)),
+            (Some(_), None) => return None,
+            (None, None) => self.parse_command(c, count.unwrap_or(1), visual)?,
+        };
+        Some(command)
+    }
+
+    /// Read a character that is not a motion, typed without a pending
+    /// operator.
+    const fn parse_command(&mut self, c: u8, count: usize, visual: bool) -> Option<Command> {
+        let command = match c {
+            c if let Some(op) = operator_for(c) => {
+                if visual {
+                    return Some(Command::OperateSelection(op));
+                }
+                self.operator = Some((op, count));
+                return None;
+            }
+            b'x' | b's' | b'X' | b'D' | b'C' | b'Y' if visual => {
+                let op = match c {
+                    b'x' | b'X' | b'D' => Operator::Delete,
+                    b'Y' => Operator::Yank,
+                    _ => Operator::Change,
+                };
+                Command::OperateSelection(op)
+            }
+            b'x' => Command::Operate(Operator::Delete, Motion::Right, count),
+            b'X' => Command::Operate(Operator::Delete, Motion::Left, count),
+            b's' => Command::Operate(Operator::Change, Motion::Right, count),
+            b'D' => Command::Operate(Operator::Delete, Motion::EndOfRow, 1),
+            b'C' => Command::Operate(Operator::Change, Motion::EndOfRow, 1),
+            b'Y' => Command::OperateRows(Operator::Yank, count),
+            b'p' => Command::Paste(count),
+            b'i' | b'a' | b'I' | b'A' | b'o' | b'O' if visual => return None,
+            b'i' => Command::Insert(Insertion::Before),
+            b'a' => Command::Insert(Insertion::After),
+            b'I' => Command::Insert(Insertion::RowStart),
+            b'A' => Command::Insert(Insertion::RowEnd),
+            b'o' => Command::Insert(Insertion::RowBelow),
+            b'O' => Command::Insert(Insertion::RowAbove),
+            b'v' => Command::Visual,
+            b'u' => Command::Undo(count),
+            CTRL_R => Command::Redo(count),
+            b'.' => Command::Repeat(count),
+            b':' => Command::Prompt,
+            b'/' => Command::Find,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+/// Return the operator typed with `c`, if any.
+const fn operator_for(c: u8) -> Option<Operator> {
+    match c {
+        b'd' => Some(Operator::Delete),
+        b'c' => Some(Operator::Change),
+        b'y' => Some(Operator::Yank),
+        _ => None,
+    }
+}
+
+/// Return the class of a character for word motions: blanks, word characters
+/// (letters, digits, `_` and non-ASCII characters) or punctuation. A word is a
+/// sequence of word characters or a sequence of punctuation characters.
+const fn class(b: u8) -> u8 {
+    match b {
+        b if b.is_ascii_whitespace() => 0,
+        b if b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii() => 1,
+        _ => 2,
+    }
+}
+
+/// Return the index in `row` of the start of the first word after the one at
+/// index `x`, if there is one in the row.
+pub fn next_word_start(row: &[u8], x: usize) -> Option<usize> {
+    let c = class(*row.get(x)?);
+    let mut i = x + row[x..].iter().take_while(|b| c != 0 && class(**b) == c).count();
+    i += row[i..].iter().take_while(|b| class(**b) == 0).count();
+    (i < row.len()).then_some(i)
+}
+
+/// Return the index in `row` of the start of the last word that starts before
+/// index `x`, if there is one in the row.
+pub fn previous_word_start(row: &[u8], x: usize) -> Option<usize> {
+    let before = &row[..x.min(row.len())];
+    let i = before.len() - before.iter().rev().take_while(|b| class(**b) == 0).count();
+    let c = class(*before[..i].last()?);
+    Some(i - before[..i].iter().rev().take_while(|b| class(**b) == c).count())
+}
+
+/// Return the index in `row` of the last character of the first word that
+/// ends at or after index `x`, if there is one in the row.
+pub fn word_end(row: &[u8], x: usize) -> Option<usize> {
+    let i = x + row.get(x..)?.iter().take_while(|b| class(**b) == 0).count();
+    let c = class(*row.get(i)?);
+    Some(i + row[i + 1..].iter().take_while(|b| class(**b) == c).count())
+}
+
+#[cfg(test)]
+mod tests {
+    use Command::{Insert, Move, Operate, OperateRows, OperateSelection};
+    use Motion::{Down, EndOfWord, NextWord, Right, Row};
+    use Operator::{Change, Delete, Yank};
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(Mode::Normal, "w", Some(Move(NextWord, 1)))]
+    #[case(Mode::Normal, "12j", Some(Move(Down, 12)))]
+    #[case(Mode::Normal, "dd", Some(OperateRows(Delete, 1)))]
+    #[case(Mode::Normal, "3yy", Some(OperateRows(Yank, 3)))]
+    #[case(Mode::Normal, "2d3e", Some(Operate(Delete, EndOfWord, 6)))]
+    #[case(Mode::Normal, "cw", Some(Operate(Change, NextWord, 1)))]
+    #[case(Mode::Normal, "x", Some(Operate(Delete, Right, 1)))]
+    #[case(Mode::Normal, "gg", Some(Move(Row(Some(1)), 1)))]
+    #[case(Mode::Normal, "5gg", Some(Move(Row(Some(5)), 1)))]
+    #[case(Mode::Normal, "dG", Some(Operate(Delete, Row(None), 1)))]
+    #[case(Mode::Normal, "0", Some(Move(Motion::StartOfRow, 1)))]
+    #[case(Mode::Normal, "10", None)]
+    #[case(Mode::Normal, "dy", None)]
+    #[case(Mode::Normal, "gx", None)]
+    #[case(Mode::Normal, "o", Some(Insert(Insertion::RowBelow)))]
+    #[case(Mode::Visual, "d", Some(OperateSelection(Delete)))]
+    #[case(Mode::Visual, "i", None)]
+    fn parse(#[case] mode: Mode, #[case] keys: &str, #[case] expected: Option<Command>) {
+        let mut vi = Vi { mode, ..Vi::default() };
+        let (last, first) = keys.as_bytes().split_last().expect("keys are not empty");
+        for c in first {
+            assert_eq!(vi.parse(*c), None);
+        }
+        assert_eq!(vi.parse(*last), expected);
+        assert_eq!(vi.is_pending(), expected.is_none() && keys == "10");
+    }
+
+    #[rstest]
+    #[case(b"foo bar", 0, Some(4), None, Some(2))]
+    #[case(b"foo.bar", 1, Some(3), Some(0), Some(2))]
+    #[case(b"foo  ", 3, None, Some(0), None)]
+    #[case(b"  foo", 1, Some(2), None, Some(4))]
+    #[case(b"a, b", 3, None, Some(1), None)]
+    fn word_motions(
+        #[case] row: &[u8], #[case] x: usize, #[case] next: Option<usize>,
+        #[case] previous: Option<usize>, #[case] end: Option<usize>,
+    ) {
+        assert_eq!(next_word_start(row, x), next);
+        assert_eq!(previous_word_start(row, x), previous);
+        assert_eq!(word_end(row, x + 1), end);
+    }
+}